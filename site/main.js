@@ -30,7 +30,7 @@ function paintmap() {
 
 function get_info() {
     const cid = get_complex_id();
-    const url = `${API_URL}/complex/${cid}`;
+    const url = `${API_URL}/complex/${cid}?text=true`;
     console.log(`querying ${url}`);
     fetch(url).then(rsp => rsp.json().then(on_complex_response)).catch(e => console.error(e));
 }
@@ -69,10 +69,32 @@ function on_complex_response(json) { // api::ComplexInfo
             .bindPopup('"' + entr.entrance_type + '" to ' + entr.daytime_routes.join(', '));
     }
 
+    paint_service(json.service || []);
     paint_upcoming(json.upcoming);
     paint_elevators2(json.elevators);
 
     setInterval(refetch_upcoming, 30*1000);
+    setInterval(refetch_service, 60*1000);
+}
+
+function paint_service(alerts) { // state::ServiceAlert
+    const parent = document.getElementById('service-alerts');
+    parent.innerHTML = '';
+    for (const alert of alerts) {
+        const elem = document.createElement('div');
+        elem.classList.add('notification', 'is-warning');
+        elem.appendChild(make_bullet(alert.route));
+        elem.appendChild(document.createTextNode(' ' + alert.message));
+        parent.appendChild(elem);
+    }
+}
+
+function refetch_service() {
+    const url = `${API_URL}/service?text=true`;
+    const routes = RESP.meta.routes;
+    fetch(url)
+        .then(rsp => rsp.json().then(alerts => paint_service(alerts.filter(a => routes.includes(a.route)))))
+        .catch(e => console.error(e));
 }
 
 function refetch_upcoming() {
@@ -190,7 +212,7 @@ function paint_elevators2(elevators) {
             bodyElem.innerHTML = `
                 <p> ${elev.serving} </p>
                 <p> <i>Alternative</i>: ${elev.alt_desc} </p>
-                <p> <i>Buses</i>: ${elev.buses} </p>
+                <p> <i>Buses</i>: ${elev.buses.join(', ')} </p>
                 <p> Starting ${fmt(out.start)} until ${fmt(out.est_return)} </p>
                 <p> ${ada}. <i>Reason</i>: ${out.reason} </p>
             `;
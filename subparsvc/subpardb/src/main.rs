@@ -1,5 +1,5 @@
 
-use subpar::{ Client, Feed, Listener, msg::Update, };
+use subpar::{ Client, Listener, routes, msg::Update, };
 use subpardb::Db;
 use tokio_stream::StreamExt as _;
 use std::time::Duration;
@@ -16,16 +16,7 @@ async fn main() -> anyhow::Result<()> {
         "localhost".to_string())?;
     db.reset_all().await?;
     
-    let feeds = vec![
-        Feed::from_static("l"),
-        Feed::from_static("1234567"),
-        Feed::from_static("ace"),
-        Feed::from_static("bdfm"),
-        Feed::from_static("g"),
-        Feed::from_static("jz"),
-        Feed::from_static("nqrw"),
-        Feed::from_static("si"),
-    ];
+    let feeds = routes::catalog().feeds();
 
     let interval = Duration::new(3, 0);
     let mut stream = Listener::new(Client::default(), feeds, interval).spawn();
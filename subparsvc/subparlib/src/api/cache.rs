@@ -0,0 +1,213 @@
+//! On-disk copies of API responses, so we can start up and keep serving when upstream is down.
+//! Each dataset is `<dir>/<name>.json` plus `<name>.meta.json`, both replaced atomically.
+
+use crate::Timestamp;
+use anyhow::{anyhow, Context as _};
+use metrohash::MetroHash128;
+use serde::{Serialize, Deserialize};
+use std::{future::Future, path::{Path, PathBuf}, time::Duration};
+use tokio::fs;
+use tracing::{debug, info, warn};
+
+/// How long a cached copy of a dataset is good for
+#[derive(Debug, Clone, Copy)]
+pub struct Dataset {
+    pub name: &'static str,
+    pub ttl: Duration,
+}
+
+const HOUR: u64 = 60 * 60;
+
+impl Dataset {
+    pub const EQUIPMENT: Dataset = Dataset { name: "equipment", ttl: Duration::from_secs(24 * HOUR) };
+    // polled hourly, so never reuse a copy from the last poll
+    pub const OUTAGES: Dataset = Dataset { name: "outages", ttl: Duration::from_secs(5 * 60) };
+    pub const COMPLEXES: Dataset = Dataset { name: "complexes", ttl: Duration::from_secs(7 * 24 * HOUR) };
+    pub const ENTRANCES: Dataset = Dataset { name: "entrances", ttl: Duration::from_secs(7 * 24 * HOUR) };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CacheMode {
+    /// Fetch when the cached copy is missing or expired
+    #[default]
+    Online,
+    /// Never fetch; serve whatever is on disk regardless of age
+    Offline,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheMeta {
+    pub url: String,
+    pub fetched_at: Timestamp,
+    /// MetroHash128 of the body, in hex
+    pub hash: String,
+    pub bytes: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Cache {
+    dir: PathBuf,
+    mode: CacheMode,
+}
+
+impl Default for Cache {
+    fn default() -> Self {
+        Cache::new("cache", CacheMode::Online)
+    }
+}
+
+impl Cache {
+    pub fn new(dir: impl Into<PathBuf>, mode: CacheMode) -> Self {
+        Cache { dir: dir.into(), mode }
+    }
+    pub fn mode(&self) -> CacheMode {
+        self.mode
+    }
+    /// The same directory, read regardless of age and never fetched
+    pub fn offline(&self) -> Cache {
+        Cache::new(&self.dir, CacheMode::Offline)
+    }
+    /// The body for `key`: cached if fresh, else fetched, else cached but stale
+    pub async fn get(
+        &self,
+        req: reqwest::RequestBuilder,
+        key: &str,
+        ttl: Duration,
+    ) -> anyhow::Result<String> {
+        self.get_with(key, ttl, fetch(req)).await
+    }
+    /// Like `get`, for bodies that take more than one request; `fetch` yields the url and body
+    pub async fn get_with(
+        &self,
+        key: &str,
+        ttl: Duration,
+        fetch: impl Future<Output = anyhow::Result<(String, String)>>,
+    ) -> anyhow::Result<String> {
+        let cached = self.read(key).await;
+        if let Some((meta, body)) = &cached {
+            let age = Timestamp::now().seconds_since(&meta.fetched_at);
+            if self.mode == CacheMode::Offline || (0..ttl.as_secs() as i64).contains(&age) {
+                debug!("Using cached {key} from {} ({age}s old)", meta.fetched_at);
+                return Ok(body.clone());
+            }
+        }
+        if self.mode == CacheMode::Offline {
+            return Err(anyhow!("{key} isn't cached in {} and we're offline", self.dir.display()));
+        }
+        match fetch.await {
+            Ok((url, body)) => {
+                if let Err(e) = self.write(key, &url, &body).await {
+                    warn!("Failed to cache {key}: {e:#}");
+                }
+                Ok(body)
+            }
+            Err(e) => match cached {
+                Some((meta, body)) => {
+                    warn!("Serving stale {key} from {}: {e:#}", meta.fetched_at);
+                    Ok(body)
+                }
+                None => Err(e),
+            },
+        }
+    }
+    pub async fn meta(&self, key: &str) -> Option<CacheMeta> {
+        let text = fs::read_to_string(self.meta_path(key)).await.ok()?;
+        serde_json::from_str(&text).ok()
+    }
+    async fn read(&self, key: &str) -> Option<(CacheMeta, String)> {
+        let meta = self.meta(key).await?;
+        let body = fs::read_to_string(self.body_path(key)).await.ok()?;
+        if hash(body.as_bytes()) != meta.hash {
+            warn!("Cached {key} doesn't match its hash; ignoring it");
+            return None;
+        }
+        Some((meta, body))
+    }
+    async fn write(&self, key: &str, url: &str, body: &str) -> anyhow::Result<()> {
+        fs::create_dir_all(&self.dir).await?;
+        let meta = CacheMeta {
+            url: url.to_owned(),
+            fetched_at: Timestamp::now(),
+            hash: hash(body.as_bytes()),
+            bytes: body.len(),
+        };
+        // body first: a new meta next to an old body fails the hash check instead of lying
+        write_atomic(&self.body_path(key), body.as_bytes()).await?;
+        write_atomic(&self.meta_path(key), &serde_json::to_vec(&meta)?).await?;
+        info!("Cached {key} ({} bytes)", meta.bytes);
+        Ok(())
+    }
+    fn body_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{key}.json"))
+    }
+    fn meta_path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{key}.meta.json"))
+    }
+}
+
+/// The url (for the meta) and body
+pub(super) async fn fetch(req: reqwest::RequestBuilder) -> anyhow::Result<(String, String)> {
+    let (http, req) = req.build_split();
+    let req = req?;
+    let url = req.url().to_string();
+    debug!("Fetching {url}");
+    let rsp = http.execute(req).await
+        .with_context(|| format!("Failed to request {url}"))?
+        .error_for_status()?;
+    Ok((url, rsp.text().await.context("Failed to accumulate")?))
+}
+
+/// Write to a temp file, then rename over `path`
+pub(crate) async fn write_atomic(path: &Path, data: &[u8]) -> anyhow::Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, data).await.with_context(|| format!("write {}", tmp.display()))?;
+    fs::rename(&tmp, path).await.with_context(|| format!("rename to {}", path.display()))?;
+    Ok(())
+}
+
+/// `write_atomic` outside the runtime
+pub(crate) fn write_atomic_blocking(path: &Path, data: &[u8]) -> anyhow::Result<()> {
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, data).with_context(|| format!("write {}", tmp.display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("rename to {}", path.display()))?;
+    Ok(())
+}
+
+fn hash(bytes: &[u8]) -> String {
+    use std::hash::Hasher as _;
+    let mut hasher = MetroHash128::new();
+    hasher.write(bytes);
+    let (hi, lo) = hasher.finish128();
+    format!("{hi:016x}{lo:016x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cache, CacheMode};
+    use crate::api::fixtures::Stub;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn offline_and_stale() {
+        let dir = std::env::temp_dir().join(format!("subpar-cache-{}", std::process::id()));
+        let stub = Stub::serve(vec![(200, "[4]".into()), (500, "down".into())]).await;
+        let http = reqwest::Client::default();
+        let req = || http.get(&stub.url);
+        let online = Cache::new(&dir, CacheMode::Online);
+        assert_eq!(online.get(req(), "x", Duration::from_secs(60)).await.unwrap(), "[4]");
+        assert_eq!(online.get(req(), "x", Duration::from_secs(60)).await.unwrap(), "[4]", "fresh, so not fetched");
+        assert_eq!(stub.requests().len(), 1);
+        assert_eq!(online.meta("x").await.unwrap().url, format!("{}/", stub.url));
+        // expired, and the fetch fails, so serve stale
+        assert_eq!(online.get(req(), "x", Duration::ZERO).await.unwrap(), "[4]");
+        assert_eq!(stub.requests().len(), 2);
+        assert!(online.get(req(), "y", Duration::ZERO).await.is_err(), "nothing stale to fall back on");
+        let offline = Cache::new(&dir, CacheMode::Offline);
+        assert_eq!(offline.get(req(), "x", Duration::ZERO).await.unwrap(), "[4]");
+        assert!(offline.get(req(), "y", Duration::ZERO).await.is_err());
+        assert_eq!(stub.requests().len(), 3, "offline never fetches");
+        tokio::fs::write(dir.join("x.json"), "[3]").await.unwrap();
+        assert!(offline.get(req(), "x", Duration::ZERO).await.is_err());
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
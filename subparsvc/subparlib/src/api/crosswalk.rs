@@ -0,0 +1,313 @@
+//! Linking records across MTA datasets that don't share ids.
+//! Outages and equipment name their station in free text, so we match on normalized names and routes.
+
+use super::{AccessEquipment, AccessOutage, ComplexId, ComplexInfo, EquipmentId};
+use crate::{routes, msg::Route};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+/// Station names in the outage/equipment feeds => the name data.ny.gov uses
+const ALIASES: &[(&str, &str)] = &[
+    ("42St/Port Authority-Bus Terminal", "42 St-Port Authority Bus Terminal"),
+    ("Times Sq-42 St/42 St-Port Authority", "Times Sq-42 St"),
+    ("World Trade Center/Chambers St", "Chambers St"),
+    ("Jamaica Center", "Jamaica Center-Parsons/Archer"),
+    ("South Ferry/Whitehall St", "South Ferry"),
+];
+
+/// Commuter rail stations => the subway complex they share a building with, when the names differ
+const HUBS: &[(&str, &str)] = &[
+    ("Jamaica", "Sutphin Blvd-Archer Av-JFK Airport"),
+    ("Atlantic Terminal", "Atlantic Av-Barclays Ctr"),
+    ("Woodside", "61 St-Woodside"),
+    ("Hunterspoint Av", "Hunters Point Av"),
+];
+
+/// Good enough to attach an outage to a complex
+const THRESHOLD: f64 = 0.6;
+/// The runner-up has to be this far behind, or it's ambiguous
+const MARGIN: f64 = 0.05;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchedBy {
+    Equipment,
+    Alias,
+    Name,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Match {
+    pub complex_id: ComplexId,
+    /// 0-1
+    pub confidence: f64,
+    pub by: MatchedBy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    Outage,
+    Equipment,
+}
+
+/// Why a name didn't match
+#[derive(Debug, Clone, Serialize)]
+pub struct Miss {
+    pub reason: String,
+    pub best: Option<Match>,
+}
+
+/// A record we couldn't place, with our best guess if any
+#[derive(Debug, Clone, Serialize)]
+pub struct Unmatched {
+    pub source: Source,
+    pub equipment: EquipmentId,
+    pub station: String,
+    pub routes: Vec<Route>,
+    pub reason: String,
+    pub best: Option<Match>,
+}
+
+impl Unmatched {
+    pub fn outage(o: &AccessOutage, reason: String, best: Option<Match>) -> Self {
+        Unmatched {
+            source: Source::Outage,
+            equipment: o.equipment,
+            station: o.station.clone(),
+            routes: o.routes.clone(),
+            reason,
+            best,
+        }
+    }
+}
+
+struct Candidate {
+    id: ComplexId,
+    names: Vec<HashSet<String>>,
+    routes: HashSet<Route>,
+}
+
+pub struct Crosswalk {
+    candidates: Vec<Candidate>,
+    equipment: HashMap<EquipmentId, ComplexId>,
+    aliases: HashMap<String, String>,
+    unmatched: Vec<Unmatched>,
+}
+
+impl Crosswalk {
+    /// Equipment whose complex isn't in `cplxs` is reported in `unmatched`
+    pub fn new(cplxs: &[ComplexInfo], equipment: &[AccessEquipment]) -> Self {
+        let candidates = cplxs.iter()
+            .map(|c| Candidate {
+                id: c.complex_id,
+                names: std::iter::once(c.stop_name.as_str())
+                    .chain(c.constituent_station_names.split("; "))
+                    .map(tokens)
+                    .collect(),
+                routes: canonical(&c.routes),
+            })
+            .collect();
+        let aliases = ALIASES.iter().map(|(from, to)| (normalize(from), normalize(to))).collect();
+        let mut walk = Crosswalk { candidates, equipment: HashMap::new(), aliases, unmatched: vec![] };
+        let known: HashSet<ComplexId> = cplxs.iter().map(|c| c.complex_id).collect();
+        for eq in equipment {
+            if known.contains(&eq.complex_id) {
+                walk.equipment.insert(eq.equipmentno, eq.complex_id);
+                continue;
+            }
+            let best = walk.station(&eq.station, &eq.trains).or_else(|miss| miss.best.ok_or(()));
+            walk.unmatched.push(Unmatched {
+                source: Source::Equipment,
+                equipment: eq.equipmentno,
+                station: eq.station.clone(),
+                routes: eq.trains.clone(),
+                reason: format!("complex {} isn't in the complex list", eq.complex_id),
+                best: best.ok(),
+            });
+        }
+        walk
+    }
+    /// Equipment we couldn't place when building the crosswalk
+    pub fn unmatched(&self) -> &[Unmatched] {
+        &self.unmatched
+    }
+    /// By equipment id if we know it, else by station name and routes
+    pub fn outage(&self, o: &AccessOutage) -> Result<Match, Unmatched> {
+        if let Some(&complex_id) = self.equipment.get(&o.equipment) {
+            return Ok(Match { complex_id, confidence: 1.0, by: MatchedBy::Equipment });
+        }
+        self.station(&o.station, &o.routes).map_err(|miss| Unmatched::outage(o, miss.reason, miss.best))
+    }
+    /// The one complex that clearly best fits a station name and its routes
+    pub fn station(&self, name: &str, routes: &[Route]) -> Result<Match, Miss> {
+        let norm = normalize(name);
+        let (target, by) = match self.aliases.get(&norm) {
+            Some(alias) => (alias.as_str(), MatchedBy::Alias),
+            None => (norm.as_str(), MatchedBy::Name),
+        };
+        let words: HashSet<String> = target.split(' ').map(str::to_owned).collect();
+        let routes = canonical(routes);
+        let mut scored: Vec<Match> = self.candidates.iter()
+            .map(|c| {
+                let name = c.names.iter().map(|n| jaccard(&words, n)).fold(0.0, f64::max);
+                let confidence = match routes.is_empty() {
+                    true => name,
+                    false => 0.7 * name + 0.3 * jaccard(&routes, &c.routes),
+                };
+                Match { complex_id: c.id, confidence, by }
+            })
+            .collect();
+        scored.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+        let miss = |reason: String, best: Option<&Match>| Miss { reason, best: best.cloned() };
+        match scored.as_slice() {
+            [] => Err(miss("no complexes".into(), None)),
+            [best, ..] if best.confidence < THRESHOLD => {
+                Err(miss(format!("best match only {:.2}", best.confidence), Some(best)))
+            },
+            [best, next, ..] if best.confidence - next.confidence < MARGIN => Err(miss(
+                format!("ambiguous between {} and {}", best.complex_id, next.complex_id),
+                Some(best),
+            )),
+            [best, ..] => Ok(best.clone()),
+        }
+    }
+}
+
+/// Whether a commuter rail station and a nearby subway complex are the same place by name
+pub fn same_station(commuter: &str, subway: &str) -> bool {
+    let (a, b) = (normalize(commuter), normalize(subway));
+    let hub = HUBS.iter().any(|(from, to)| normalize(from) == a && normalize(to) == b);
+    hub || jaccard(&tokens(&a), &tokens(&b)) >= 0.5
+}
+
+/// Lowercase words, with street-name abbreviations and ordinals made uniform
+pub fn normalize(name: &str) -> String {
+    let mut spaced = String::with_capacity(name.len());
+    let mut prev: Option<char> = None;
+    for c in name.chars() {
+        // 42St => 42 St
+        if prev.is_some_and(|p| p.is_ascii_digit()) && c.is_alphabetic() {
+            spaced.push(' ');
+        }
+        spaced.push(if c.is_alphanumeric() { c.to_ascii_lowercase() } else { ' ' });
+        prev = Some(c);
+    }
+    let words: Vec<&str> = spaced.split_whitespace()
+        .map(|w| match w {
+            "street" => "st",
+            "avenue" | "ave" => "av",
+            "square" => "sq",
+            "road" => "rd",
+            "boulevard" => "blvd",
+            "parkway" => "pkwy",
+            "center" => "ctr",
+            w => w,
+        })
+        .collect();
+    let mut out: Vec<&str> = vec![];
+    for (i, w) in words.iter().enumerate() {
+        // 42nd => 42, but only right after a number, so Rd stays a road
+        let after_number = i > 0 && words[i - 1].chars().all(|c| c.is_ascii_digit());
+        if after_number && matches!(*w, "st" | "nd" | "rd" | "th") && words.get(i + 1).is_some_and(|n| is_street(n)) {
+            continue;
+        }
+        out.push(w);
+    }
+    out.join(" ")
+}
+
+fn is_street(w: &str) -> bool {
+    matches!(w, "st" | "av" | "rd" | "blvd" | "pl" | "dr")
+}
+
+fn tokens(name: &str) -> HashSet<String> {
+    normalize(name).split(' ').map(str::to_owned).collect()
+}
+
+fn canonical(routes: &[Route]) -> HashSet<Route> {
+    routes.iter().map(|r| routes::catalog().canonical(r).unwrap_or(*r)).collect()
+}
+
+fn jaccard<T: Eq + std::hash::Hash>(a: &HashSet<T>, b: &HashSet<T>) -> f64 {
+    let union = a.union(b).count();
+    match union {
+        0 => 0.0,
+        n => a.intersection(b).count() as f64 / n as f64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize, same_station, Crosswalk, MatchedBy, Source};
+    use crate::{api::fixtures::{complex, equipment, outage}, msg::Route};
+    use serde_json::json;
+
+    fn walk() -> Crosswalk {
+        let cplx = |id: &str, name: &str, names: &str, routes: &str| complex(json!({
+            "complex_id": id, "stop_name": name, "constituent_station_names": names, "daytime_routes": routes,
+        }));
+        let cplxs = [
+            cplx("611", "Times Sq-42 St", "Times Sq-42 St; 42 St-Port Authority Bus Terminal", "1 2 3 7 A C E N Q R W S"),
+            cplx("164", "34 St-Penn Station", "34 St-Penn Station", "A C E"),
+            cplx("318", "34 St-Penn Station", "34 St-Penn Station", "1 2 3"),
+            cplx("167", "23 St", "23 St", "C E"),
+        ];
+        let equip = [
+            equipment(json!({"equipmentno": "EL100", "stationcomplexid": "611", "station": "Times Sq-42 St"})),
+            // its complex isn't in the list, but its station name is
+            equipment(json!({"equipmentno": "EL200", "stationcomplexid": "999", "station": "23 Street", "trainno": "C/E"})),
+        ];
+        Crosswalk::new(&cplxs, &equip)
+    }
+
+    #[test]
+    fn stations() {
+        let cw = walk();
+        let routes = |rs: &[&str]| rs.iter().map(|r| Route::make(r)).collect::<Vec<_>>();
+        let m = cw.station("42St/Port Authority-Bus Terminal", &routes(&["A", "C", "E"])).unwrap();
+        assert_eq!((m.complex_id.to_string(), m.by), ("611".into(), MatchedBy::Alias));
+        let m = cw.station("34 Street-Penn Station", &routes(&["2", "3"])).unwrap();
+        assert_eq!(m.complex_id.to_string(), "318");
+        assert!(cw.station("34 St-Penn Station", &[]).unwrap_err().reason.starts_with("ambiguous"));
+        let miss = cw.station("Jamaica-179 St", &routes(&["F"])).unwrap_err();
+        assert!(miss.best.is_none_or(|b| b.confidence < 0.6));
+        assert!(same_station("Grand Central", "Grand Central-42 St"));
+        assert!(same_station("Jamaica", "Sutphin Blvd-Archer Av-JFK Airport"));
+        assert!(!same_station("Nostrand Av", "Kingston-Throop Avs"));
+    }
+
+    #[test]
+    fn outages() {
+        let cw = walk();
+        let unmatched = cw.unmatched();
+        assert_eq!(unmatched.len(), 1);
+        let u = &unmatched[0];
+        assert_eq!((u.source, u.equipment.as_ref()), (Source::Equipment, "EL200"));
+        assert!(u.reason.contains("999"), "{}", u.reason);
+        assert_eq!(u.best.as_ref().map(|b| b.complex_id.to_string()), Some("167".into()));
+        let at = |equip: &str, station: &str, trains: &str| cw.outage(&outage(json!({
+            "equipment": equip, "station": station, "trainno": trains,
+        })));
+        let m = at("EL100", "anywhere", "L").unwrap();
+        assert_eq!((m.complex_id.to_string(), m.by, m.confidence), ("611".into(), MatchedBy::Equipment, 1.0));
+        let m = at("EL300", "34 St-Penn Station", "1/2/3").unwrap();
+        assert_eq!((m.complex_id.to_string(), m.by), ("318".into(), MatchedBy::Name));
+        let u = at("EL301", "34 St-Penn Station", "").unwrap_err();
+        assert_eq!((u.source, u.equipment.as_ref()), (Source::Outage, "EL301"));
+        assert!(u.reason.starts_with("ambiguous"), "{}", u.reason);
+        assert!(u.best.is_some());
+        let u = at("EL302", "Jamaica-179 St", "F").unwrap_err();
+        assert!(u.reason.starts_with("best match only"), "{}", u.reason);
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(normalize("42St/Port Authority-Bus Terminal"), "42 st port authority bus terminal");
+        assert_eq!(normalize("W 4 St-Wash Sq"), "w 4 st wash sq");
+        assert_eq!(normalize("Jamaica Center-Parsons/Archer"), "jamaica ctr parsons archer");
+        assert_eq!(normalize("161 Street-Yankee Stadium"), "161 st yankee stadium");
+        assert_eq!(normalize("23rd St"), "23 st");
+        assert_eq!(normalize("Beach 36 Street"), "beach 36 st");
+    }
+}
@@ -0,0 +1,93 @@
+//! Dataset rows for tests, parsed from the APIs' own formats. Each starts from a real
+//! row at 1 Av (L) and takes overrides, e.g. `equipment(json!({"equipmentno": "EL1"}))`.
+//! Also a local HTTP server to fetch them from.
+
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use tokio::{io::{AsyncReadExt as _, AsyncWriteExt as _}, net::TcpListener};
+use super::{AccessEquipment, AccessOutage, ComplexInfo, SubwayEntrance};
+
+pub fn complex(over: Value) -> ComplexInfo {
+    parse(json!({
+        "complex_id": "119", "is_complex": "FALSE", "number_of_stations_in_complex": "1",
+        "stop_name": "1 Av", "display_name": "1 Av (L)", "constituent_station_names": "1 Av",
+        "gtfs_stop_ids": "L06", "borough": "M", "cbd": "TRUE", "daytime_routes": "L",
+        "structure_type": "Subway", "latitude": "40.730953", "longitude": "-73.981628", "ada": "1",
+        "ada_notes": null,
+    }), over)
+}
+
+pub fn equipment(over: Value) -> AccessEquipment {
+    parse(json!({
+        "station": "1 Av", "trainno": "L", "equipmentno": "EL293", "equipmenttype": "EL",
+        "serving": "E 14 St and Avenue A (SW corner) to Canarsie-bound platform", "ADA": "Y",
+        "isactive": "Y", "nonNYCT": "N", "shortdescription": "Street to Brooklyn-bound platform",
+        "linesservedbyelevator": "L", "elevatorsgtfsstopid": "L06", "elevatormrn": "119",
+        "stationcomplexid": "119", "nextadanorth": "117, L", "nextadasouth": "120, L", "redundant": 0,
+        "busconnections": "M15, M15 SBS, M14A SBS", "alternativeroute": "Take a westbound M14A SBS to 4 Av",
+    }), over)
+}
+
+pub fn outage(over: Value) -> AccessOutage {
+    parse(json!({
+        "station": "1 Av", "trainno": "L", "equipment": "EL293", "equipmenttype": "EL",
+        "serving": "E 14 St and Avenue A (SW corner) to Canarsie-bound platform", "ADA": "Y",
+        "outagedate": "06/26/2024 09:35:00 AM", "estimatedreturntoservice": "06/28/2024 11:45:00 PM",
+        "reason": "Repair", "isupcomingoutage": "N", "ismaintenanceoutage": "N",
+    }), over)
+}
+
+pub fn entrance(over: Value) -> SubwayEntrance {
+    parse(json!({
+        "division": "BMT", "line": "Canarsie", "borough": "M", "stop_name": "1 Av", "complex_id": "119",
+        "constituent_station_name": "1 Av", "station_id": "119", "gtfs_stop_id": "L06",
+        "daytime_routes": "L", "entrance_type": "Elevator", "entry_allowed": "YES", "exit_allowed": "YES",
+        "entrance_latitude": "40.730584", "entrance_longitude": "-73.981245",
+    }), over)
+}
+
+fn parse<T: DeserializeOwned>(mut base: Value, over: Value) -> T {
+    let Value::Object(over) = over else { panic!("overrides should be an object") };
+    base.as_object_mut().unwrap().extend(over);
+    serde_json::from_value(base).unwrap()
+}
+
+/// Answers each request with the next of its responses, then keeps repeating the last
+pub struct Stub {
+    pub url: String,
+    /// Each request's path and query
+    pub requests: Arc<Mutex< Vec<String> >>,
+}
+
+impl Stub {
+    pub async fn serve(responses: Vec<(u16, String)>) -> Stub {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+        let seen = requests.clone();
+        tokio::spawn(async move {
+            for i in 0.. {
+                let Ok((mut conn, _)) = listener.accept().await else { return };
+                let mut buf = vec![0; 8192];
+                let mut len = 0;
+                while !buf[..len].windows(4).any(|w| w == b"\r\n\r\n") {
+                    match conn.read(&mut buf[len..]).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => len += n,
+                    }
+                }
+                let head = String::from_utf8_lossy(&buf[..len]);
+                let path = head.split(' ').nth(1).unwrap_or_default().to_owned();
+                seen.lock().unwrap().push(path);
+                let (status, body) = &responses[i.min(responses.len() - 1)];
+                let rsp = format!("HTTP/1.1 {status} X\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len());
+                let _ = conn.write_all(rsp.as_bytes()).await;
+            }
+        });
+        Stub { url, requests }
+    }
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
@@ -1,77 +1,105 @@
 
 use reqwest;
-use std::{path::Path, str::FromStr, marker::PhantomData, fmt::Display, any::type_name};
+use std::{str::FromStr, marker::PhantomData, fmt::Display, any::type_name};
 use serde::{Serialize, Deserialize, de::DeserializeOwned};
-use crate::{Timestamp, msg::{Route, StopId}};
-use tokio::fs;
+use crate::{Timestamp, routes::{self, Agency}, msg::{Route, StationId}};
 use anyhow::Context as _;
 use tracing::{debug};
 use reqwest::Url;
 
-const PREFER_CACHE: bool = false;
-
-pub struct Client(reqwest::Client);
+mod cache;
+mod soda;
+pub mod crosswalk;
+#[cfg(test)]
+pub(crate) mod fixtures;
+pub use cache::{Cache, CacheMode, CacheMeta, Dataset};
+pub(crate) use cache::write_atomic_blocking;
+pub use soda::{Query, SodaResource, SODA_BASE};
+pub use crosswalk::{Crosswalk, Unmatched};
+
+pub struct Client {
+    http: reqwest::Client,
+    cache: Cache,
+    /// Sent as X-App-Token to data.ny.gov for higher rate limits
+    soda_token: Option<String>,
+}
 
 impl Default for Client {
     fn default() -> Self {
-        Client(reqwest::Client::default())
+        Client::with_cache(Cache::default())
     }
 }
 
 impl Client {
+    pub fn with_cache(cache: Cache) -> Self {
+        let soda_token = std::env::var("SODA_APP_TOKEN").ok().filter(|t| !t.is_empty());
+        Client { http: reqwest::Client::default(), cache, soda_token }
+    }
+    /// Only ever reads from `cache/`
+    pub fn offline() -> Self {
+        Client::with_cache(Cache::new("cache", CacheMode::Offline))
+    }
+    pub fn cache(&self) -> &Cache {
+        &self.cache
+    }
+    /// Only reads what this client has cached, however old, e.g. to start quickly
+    pub fn cached(&self) -> Self {
+        Client::with_cache(self.cache.offline())
+    }
     pub async fn get_equipment(&self) -> anyhow::Result<Vec<AccessEquipment>> {
         let url = "https://api-endpoint.mta.info/Dataservice/mtagtfsfeeds/nyct%2Fnyct_ene_equipments.json";
-        self.get_inner(url, Path::new("cache/equipment.json")).await
+        self.get_inner(url, Dataset::EQUIPMENT).await
     }
     pub async fn get_outage(&self) -> anyhow::Result<Vec<AccessOutage>> {
         let url = "https://api-endpoint.mta.info/Dataservice/mtagtfsfeeds/nyct%2Fnyct_ene.json";
-        self.get_inner(url, Path::new("cache/outages.json")).await
-    }
-    pub async fn get_outages_nocache(&self) -> anyhow::Result<Vec<AccessOutage>> {
-        let url = "https://api-endpoint.mta.info/Dataservice/mtagtfsfeeds/nyct%2Fnyct_ene.json";
-        self.get_inner(url, Path::new("/dev/null")).await
+        self.get_inner(url, Dataset::OUTAGES).await
     }
 
     pub async fn get_complexes(&self) -> anyhow::Result<Vec<ComplexInfo>> {
-        let url = "https://data.ny.gov/resource/5f5g-n3cz.json";
-        self.get_inner(url, Path::new("cache/complexes.json")).await
+        self.fetch_all().await
     }
     pub async fn get_entrances(&self) -> anyhow::Result<Vec<SubwayEntrance>> {
-        let base = "https://data.ny.gov/resource/i9wp-a4ja.json";
-        let mut ret = vec![];
-        for i in 0..10 {
-            let mut params = vec![("$limit", "1000".to_string())];
-            if i > 0 {
-                params.push(("$offset", (i*1000).to_string()));
+        self.fetch_all().await
+    }
+    /// Every row of a data.ny.gov dataset
+    pub async fn fetch_all<T: SodaResource>(&self) -> anyhow::Result<Vec<T>> {
+        self.fetch(&Query::default()).await
+    }
+    /// Every row `query` matches, cached as a whole
+    pub async fn fetch<T: SodaResource>(&self, query: &Query) -> anyhow::Result<Vec<T>> {
+        debug!("Loading {}", type_name::<T>());
+        let key = query.key(T::DATASET.name);
+        let body = self.cache.get_with(&key, T::DATASET.ttl, self.fetch_pages(T::url(), query)).await?;
+        serde_json::from_str(&body).with_context(|| format!("Failed to parse {key}"))
+    }
+    /// Pages through `query` until a short page or its limit, all at once,
+    /// so the cache never mixes pages from different fetches
+    async fn fetch_pages(&self, base: String, query: &Query) -> anyhow::Result<(String, String)> {
+        let mut rows: Vec<serde_json::Value> = vec![];
+        for page in 0.. {
+            let Some(want) = query.page_len(page) else { break };
+            let url = Url::parse_with_params(&base, query.params(page, want))?;
+            let mut req = self.http.get(url);
+            if let Some(token) = &self.soda_token {
+                req = req.header("X-App-Token", token);
             }
-            let url = Url::parse_with_params(base, &params)?;
-            let path = format!("cache/entrances.{i}.json");
-            let mut new: Vec<_> = self.get_inner(url.as_str(), Path::new(&path)).await?;
-            let len = new.len();
-            ret.append(&mut new);
-            if len < 1000 { break }
+            let (url, body) = cache::fetch(req).await?;
+            let mut page: Vec<serde_json::Value> = serde_json::from_str(&body)
+                .with_context(|| format!("Failed to parse {url}"))?;
+            let len = page.len();
+            rows.append(&mut page);
+            if len < want { break }
         }
-        Ok(ret)
-    }
-
-    async fn get_inner<T: DeserializeOwned>(&self, url: &str, path: &Path) -> anyhow::Result<T> {
-        let body = if PREFER_CACHE && path.exists() {
-            debug!("Loading {} from DISK", type_name::<T>());
-            fs::read_to_string(path)
-                .await
-                .with_context(|| format!("Failed to load file {}", path.display()))?
-        } else {
-            debug!("Fetching {} from mta.info", type_name::<T>());
-            let rsp = self.0.get(url)
-                .send().await
-                .with_context(|| format!( "Failed to request {url}"))?;
-            let body = rsp.text().await.context("Failed to accumulate")?;
-            if let Err(e) = fs::write(path, &body).await {
-                tracing::warn!("Failed to write response to disk {path:?}: {e}");
-            }
-            body
-        };
-        Ok(serde_json::from_str(&body)?)
+        Ok((base, serde_json::to_string(&rows)?))
+    }
+
+    async fn get_inner<T: DeserializeOwned>(&self, url: &str, data: Dataset) -> anyhow::Result<T> {
+        self.get_req(self.http.get(url), data.name, data).await
+    }
+    async fn get_req<T: DeserializeOwned>(&self, req: reqwest::RequestBuilder, key: &str, data: Dataset) -> anyhow::Result<T> {
+        debug!("Loading {}", type_name::<T>());
+        let body = self.cache.get(req, key, data.ttl).await?;
+        serde_json::from_str(&body).with_context(|| format!("Failed to parse {key}"))
     }
 }
 
@@ -94,15 +122,19 @@ pub struct AccessEquipment {
     #[serde(deserialize_with = "parse_route_list")]
     pub linesservedbyelevator: Vec<Route>,
     #[serde(rename = "elevatorsgtfsstopid", deserialize_with = "parse_stop_list")]
-    pub stop_ids: Vec<StopId>, // hyphen-concatenated StopIds,
+    pub stop_ids: Vec<StationId>, // hyphen-concatenated StopIds,
     // elevatorsgtfsstopid: Vec<String>, // hyphen-concatenated StopIds,
     pub elevatormrn: String, // slash-delimited numbers ?
     #[serde(rename = "stationcomplexid", deserialize_with = "parse_quoted_complex_id")]
     pub complex_id: ComplexId, // station id
-    pub nextadanorth: String,
-    pub nextadasouth: String,
+    /// Nearest accessible stations in each direction, e.g. "117, L"
+    #[serde(deserialize_with = "parse_next_ada")]
+    pub nextadanorth: Vec<(ComplexId, Route)>,
+    #[serde(deserialize_with = "parse_next_ada")]
+    pub nextadasouth: Vec<(ComplexId, Route)>,
     pub redundant: i32,
-    pub busconnections: String, // e.g. "Bx6, Bx6 SBS, Bx13"
+    #[serde(deserialize_with = "parse_bus_list")]
+    pub busconnections: Vec<String>, // e.g. "Bx6, Bx6 SBS, Bx13"
     pub alternativeroute: String,
 }
 
@@ -173,7 +205,7 @@ pub struct SubwayEntrance {
     pub constituent_station_name: String,
     pub station_id: String,
     #[serde(rename = "gtfs_stop_id", deserialize_with = "parse_stop_list2")]
-    pub stop_ids: Vec<StopId>,   // "; ".join(StopIds)
+    pub stop_ids: Vec<StationId>,   // "; ".join(StopIds)
     #[serde(rename = "daytime_routes", deserialize_with = "parse_route_list2")]
     pub routes: Vec<Route>,
     pub entrance_type: String,
@@ -227,7 +259,7 @@ pub struct ComplexInfo {
     pub display_name: String,   // stop_name w/ routes
     pub constituent_station_names: String,
     #[serde(rename = "gtfs_stop_ids", deserialize_with = "parse_stop_list3")]
-    pub stop_ids: Vec<StopId>,
+    pub stop_ids: Vec<StationId>,
     pub borough: String,
     #[serde(deserialize_with = "parse_bool")]
     pub cbd: bool,
@@ -243,25 +275,31 @@ pub struct ComplexInfo {
     pub ada_notes: Option<String>,
 }
 
-fn sort_routes(rs: &str) -> String {
-    let mut sorted: Vec<char> = rs.chars().collect();
-    sorted.sort();
-    sorted.dedup();
-    sorted.into_iter().filter(|c| c.is_alphanumeric()).collect()
+impl SodaResource for ComplexInfo {
+    const ID: &'static str = "5f5g-n3cz";
+    const DATASET: Dataset = Dataset::COMPLEXES;
 }
 
-impl<'a> PartialEq<AccessOutage> for &'a ComplexInfo {
-    fn eq(&self, out: &AccessOutage) -> bool {
-        let name = out.station.replace("42St/Port Authority-", "42 St-Port Authority ");
-        todo!() /*
-        self.constituent_station_names.split("; ").find(|s| s == &name).is_some()
-            && sort_routes(&self.daytime_routes) == sort_routes(&out.serving) */
-    }
+impl SodaResource for SubwayEntrance {
+    const ID: &'static str = "i9wp-a4ja";
+    const DATASET: Dataset = Dataset::ENTRANCES;
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
 pub struct ComplexId(u32);
 
+impl ComplexId {
+    /// For commuter rail stations that aren't part of a subway complex, e.g. LI:237 => 100237
+    pub fn commuter(agency: Agency, stop: u32) -> Option<Self> {
+        let base = match agency {
+            Agency::Nyct => return None,
+            Agency::Lirr => 100_000,
+            Agency::Mnr => 200_000,
+        };
+        (stop < 100_000).then_some(ComplexId(base + stop))
+    }
+}
+
 crate::newt! {
     #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash, Deserialize, Serialize)]
     pub struct EquipmentId[6];
@@ -300,27 +338,59 @@ fn parse_quoted_complex_id<'de, D: Deserializer<'de>>(deser: D) -> Result<Comple
     deser.deserialize_str(QuotedVisitor::default()).map(ComplexId)
 }
 
-fn parse_stop_list<'de, D: Deserializer<'de>>(deser: D) -> Result<Vec<StopId>, D::Error> {
+fn parse_stop_list<'de, D: Deserializer<'de>>(deser: D) -> Result<Vec<StationId>, D::Error> {
     deser.deserialize_str(List::from("/"))
 }
 
-fn parse_stop_list2<'de, D: Deserializer<'de>>(deser: D) -> Result<Vec<StopId>, D::Error> {
+fn parse_stop_list2<'de, D: Deserializer<'de>>(deser: D) -> Result<Vec<StationId>, D::Error> {
     deser.deserialize_str(List::from(" "))
 }
 
-fn parse_stop_list3<'de, D: Deserializer<'de>>(deser: D) -> Result<Vec<StopId>, D::Error> {
+fn parse_stop_list3<'de, D: Deserializer<'de>>(deser: D) -> Result<Vec<StationId>, D::Error> {
     deser.deserialize_str(List::from("; "))
 }
 
 fn parse_route_list<'de, D: Deserializer<'de>>(deser: D) -> Result<Vec<Route>, D::Error> {
-    // hack to remove LIRR (irrelevant and too long)
+    // skip anything the catalog doesn't know about (e.g. a bus)
     let strings = deser.deserialize_str(List::<String>::from("/"))?;
     Ok(strings.iter()
-        .filter(|s| "LIRR" != s.as_str() && "METRO-NORTH" != s.as_str())
-        .map(|s| s.parse::<Route>().unwrap())
+        .filter(|s| match routes::catalog().get(s) {
+            Some(_) => true,
+            None => { debug!("skipping unknown route '{s}'"); false },
+        })
+        .map(|s| Route::make(s))
         .collect())
 }
 
+fn parse_bus_list<'de, D: Deserializer<'de>>(deser: D) -> Result<Vec<String>, D::Error> {
+    let buses = deser.deserialize_str(List::<String>::from(","))?;
+    Ok(buses.into_iter().map(|b| b.trim().to_owned()).filter(|b| !b.is_empty()).collect())
+}
+
+/// "117, L" or "117, L/M; 120, L". Free text in places, so skip what doesn't parse
+fn parse_next_ada<'de, D: Deserializer<'de>>(deser: D) -> Result<Vec<(ComplexId, Route)>, D::Error> {
+    let text = String::deserialize(deser)?;
+    Ok(next_ada(&text))
+}
+
+fn next_ada(text: &str) -> Vec<(ComplexId, Route)> {
+    let mut ret = vec![];
+    for entry in text.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+        let parsed = entry.split_once(',').and_then(|(id, rts)| {
+            let id = ComplexId(id.trim().parse().ok()?);
+            let rts: Vec<Route> = rts.split(['/', ' ']).filter(|r| !r.is_empty())
+                .filter_map(|r| routes::catalog().get(r).map(|_| Route::make(r)))
+                .collect();
+            Some((id, rts))
+        });
+        match parsed {
+            Some((id, rts)) if !rts.is_empty() => ret.extend(rts.into_iter().map(|r| (id, r))),
+            _ => debug!("skipping next ADA station '{entry}'"),
+        }
+    }
+    ret
+}
+
 fn parse_route_list2<'de, D: Deserializer<'de>>(deser: D) -> Result<Vec<Route>, D::Error> {
     deser.deserialize_str(List::<Route>::from(" "))
 }
@@ -343,6 +413,7 @@ impl<'de, T: FromStr> de::Visitor<'de> for List<T>
     fn visit_str<E: de::Error>(self, s: &str) -> Result<Self::Value, E> {
         let mut elems: Vec<T> = vec![];
         for (i, chunk) in s.split(self.0).enumerate() {
+            if chunk.is_empty() { continue }
             match chunk.parse() {
                 Ok(x) => elems.push(x),
                 Err(e) => return Err(de::Error::custom(format!(
@@ -440,3 +511,68 @@ impl fmt::Display for ComplexId {
         write!(f, "{}", self.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{next_ada, Cache, CacheMode, Client, ComplexId, Dataset, Query, SodaResource};
+    use crate::routes::Agency;
+    use super::fixtures::Stub;
+    use std::{sync::OnceLock, time::Duration};
+
+    static STUB: OnceLock<String> = OnceLock::new();
+
+    #[derive(serde::Deserialize)]
+    struct Row {
+        n: u32,
+    }
+
+    impl SodaResource for Row {
+        const ID: &'static str = "test-rows";
+        // always refetched, unless offline
+        const DATASET: Dataset = Dataset { name: "rows", ttl: Duration::ZERO };
+        fn url() -> String {
+            format!("{}/{}.json", STUB.get().unwrap(), Self::ID)
+        }
+    }
+
+    #[tokio::test]
+    async fn fetch_all_at_once() {
+        let page = |ns: &[u32]| (200, serde_json::to_string(&ns.iter().map(|n| serde_json::json!({"n": n})).collect::<Vec<_>>()).unwrap());
+        let stub = Stub::serve(vec![page(&[1, 2]), page(&[3, 4]), page(&[5]), page(&[1, 2]), (500, "down".into())]).await;
+        STUB.set(stub.url.clone()).unwrap();
+        let dir = std::env::temp_dir().join(format!("subpar-soda-{}", std::process::id()));
+        let client = Client::with_cache(Cache::new(&dir, CacheMode::Online));
+        let query = Query::new().page_size(2);
+        let ns = |rows: Vec<Row>| rows.into_iter().map(|r| r.n).collect::<Vec<_>>();
+        assert_eq!(ns(client.fetch(&query).await.unwrap()), [1, 2, 3, 4, 5]);
+        let requests = stub.requests();
+        assert_eq!(requests.len(), 3, "until a short page");
+        assert!(requests[2].contains("%24offset=4"), "{}", requests[2]);
+        assert_eq!(ns(client.cached().fetch(&query).await.unwrap()), [1, 2, 3, 4, 5]);
+        assert_eq!(stub.requests().len(), 3);
+        let cached: Vec<_> = std::fs::read_dir(&dir).unwrap().map(|e| e.unwrap().file_name()).collect();
+        assert_eq!(cached.len(), 2, "one body and its meta: {cached:?}");
+        // the second page fails, so none of the refetch is used
+        assert_eq!(ns(client.fetch(&query).await.unwrap()), [1, 2, 3, 4, 5]);
+        assert_eq!(stub.requests().len(), 5);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(client.fetch::<Row>(&query).await.is_err(), "nothing cached, and upstream is down");
+    }
+
+    #[test]
+    fn next_ada_stations() {
+        let show = |s| next_ada(s).iter().map(|(c, r)| format!("{c}:{r}")).collect::<Vec<_>>();
+        assert_eq!(show("117, L"), ["117:L"]);
+        assert_eq!(show("611, N/Q; 164, A"), ["611:N", "611:Q", "164:A"]);
+        assert_eq!(show("Take the M14 bus"), Vec::<String>::new());
+        assert_eq!(show(""), Vec::<String>::new());
+    }
+
+    #[test]
+    fn commuter_ids() {
+        assert_eq!(ComplexId::commuter(Agency::Lirr, 237), Some(ComplexId(100_237)));
+        assert_eq!(ComplexId::commuter(Agency::Mnr, 1), Some(ComplexId(200_001)));
+        assert_eq!(ComplexId::commuter(Agency::Lirr, 100_000), None, "would collide with Metro-North's");
+        assert_eq!(ComplexId::commuter(Agency::Nyct, 237), None);
+    }
+}
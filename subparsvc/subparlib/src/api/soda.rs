@@ -0,0 +1,120 @@
+//! Socrata Open Data API queries against data.ny.gov.
+//! A new dataset is a row struct plus a `SodaResource` impl.
+
+use super::Dataset;
+use serde::de::DeserializeOwned;
+
+pub const SODA_BASE: &str = "https://data.ny.gov/resource";
+
+/// A data.ny.gov dataset whose rows deserialize to `Self`
+pub trait SodaResource: DeserializeOwned {
+    /// The 4x4 id, e.g. 5f5g-n3cz
+    const ID: &'static str;
+    /// Cache name and TTL
+    const DATASET: Dataset;
+    fn url() -> String {
+        format!("{SODA_BASE}/{}.json", Self::ID)
+    }
+}
+
+/// SoQL parameters. Without a `limit`, pages until the dataset runs out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    select: Option<String>,
+    filter: Option<String>,
+    order: String,
+    limit: Option<usize>,
+    page_size: usize,
+}
+
+impl Default for Query {
+    fn default() -> Self {
+        // paging is only stable with a total order; :id is the row id
+        Query { select: None, filter: None, order: ":id".into(), limit: None, page_size: 1000 }
+    }
+}
+
+impl Query {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// `$select`, e.g. "complex_id, stop_name"
+    pub fn select(mut self, cols: &str) -> Self {
+        self.select = Some(cols.to_owned());
+        self
+    }
+    /// `$where`, e.g. "borough = 'M'"
+    pub fn filter(mut self, cond: &str) -> Self {
+        self.filter = Some(cond.to_owned());
+        self
+    }
+    /// `$order`; defaults to the row id
+    pub fn order(mut self, cols: &str) -> Self {
+        self.order = cols.to_owned();
+        self
+    }
+    /// At most this many rows in total
+    pub fn limit(mut self, n: usize) -> Self {
+        self.limit = Some(n);
+        self
+    }
+    pub fn page_size(mut self, n: usize) -> Self {
+        self.page_size = n.max(1);
+        self
+    }
+    /// Rows to ask for on page `page`, or None once `limit` is reached
+    pub(super) fn page_len(&self, page: usize) -> Option<usize> {
+        let offset = page * self.page_size;
+        match self.limit {
+            Some(limit) if offset >= limit => None,
+            Some(limit) => Some(self.page_size.min(limit - offset)),
+            None => Some(self.page_size),
+        }
+    }
+    pub(super) fn params(&self, page: usize, len: usize) -> Vec<(&'static str, String)> {
+        let mut params = vec![];
+        if let Some(s) = &self.select {
+            params.push(("$select", s.clone()));
+        }
+        if let Some(w) = &self.filter {
+            params.push(("$where", w.clone()));
+        }
+        params.push(("$order", self.order.clone()));
+        params.push(("$limit", len.to_string()));
+        if page > 0 {
+            params.push(("$offset", (page * self.page_size).to_string()));
+        }
+        params
+    }
+    /// Cache key for every row; custom queries don't share it with the full dataset
+    pub(super) fn key(&self, name: &str) -> String {
+        if *self == Query::default() {
+            return name.to_owned();
+        }
+        use std::hash::{Hash as _, Hasher as _};
+        let mut hasher = metrohash::MetroHash64::new();
+        (&self.select, &self.filter, &self.order, self.limit, self.page_size).hash(&mut hasher);
+        format!("{name}-{:08x}", hasher.finish() as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Query;
+
+    #[test]
+    fn paging() {
+        let q = Query::new().filter("borough = 'M'").limit(2500);
+        assert_eq!(q.page_len(2), Some(500));
+        assert_eq!(q.params(2, 500), [
+            ("$where", "borough = 'M'".to_string()),
+            ("$order", ":id".to_string()),
+            ("$limit", "500".to_string()),
+            ("$offset", "2000".to_string()),
+        ]);
+        assert_eq!(q.page_len(3), None);
+        assert_eq!(Query::new().page_len(7), Some(1000));
+        assert_eq!(Query::new().key("entrances"), "entrances");
+        assert_ne!(q.key("entrances"), "entrances");
+    }
+}
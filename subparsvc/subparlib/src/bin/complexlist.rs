@@ -1,4 +1,4 @@
-use subpar::{api};
+use subpar::{api, routes};
 
 fn score_borough(b: &str) -> u32 {
     match b {
@@ -33,7 +33,8 @@ async fn main() -> anyhow::Result<()> {
         let mut rs = cplx.routes.clone();
         rs.sort();
         let rs = rs.iter()
-            .map(|r| format!(r#"<img class="icon bullet" alt="{r}" src="/f/R{r}.svg" />"#))
+            .filter_map(|r| routes::catalog().route(r).map(|info| (r, info.bullet_img())))
+            .map(|(r, img)| format!(r#"<img class="icon bullet" alt="{r}" src="/f/{img}" />"#))
             .fold(String::new(), |a, b| a + " " + &b);
         let id = cplx.complex_id;
         let ada = {
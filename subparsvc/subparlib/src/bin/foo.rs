@@ -1,27 +1,9 @@
 #![allow(unused)]
 
-use subpar::{api, Client, Feed, ManifestStops, /* PollClient*/};
+use subpar::{api, Client, Feed, ManifestStops, manifest::GtfsSource, /* PollClient*/};
 use std::collections::HashMap;
 use tracing::info;
 
-fn is_match(e: &api::SubwayEntrance, o: &api::AccessOutage) -> bool {
-    #[derive(Eq, PartialEq, Debug)]
-    struct Key {
-        name: String,
-    }
-    impl From<&api::SubwayEntrance> for Key {
-        fn from(e: &api::SubwayEntrance) -> Self {
-            Key { name: e.stop_name.clone() }
-        }
-    }
-    impl From<&api::AccessOutage> for Key {
-        fn from(o: &api::AccessOutage) -> Self {
-            Key { name: o.station.clone() }
-        }
-    }
-    false
-}
-
 // AccessEquipment.equipmentno matches AccessOutage.equipment
 // AccessEquipment.elevatorsgtfsstopid '/'.join(parent_stop_ids)
 
@@ -43,15 +25,14 @@ async fn main() -> anyhow::Result<()> {
     // return Ok(());
     // info!("{:?}", &complexes[0]);
 
-    let stops = ManifestStops::from_file("archive/stops.txt");
-    let ids: HashMap<_, _> = stops.iter().map(|r| (r.stop, r)).collect();
+    let stops = ManifestStops::from_source(&GtfsSource::detect("archive")?);
 
     let complexes = client.get_complexes().await?;
     for cplx in complexes {
         if cplx.stop_ids.len() == 1 { continue }
         println!("Complex '{}' ", cplx.display_name);
         for stop in cplx.stop_ids {
-            println!("\t{stop}: {}", ids[&stop].name);
+            println!("\t{stop}: {}", stops.station(&stop).unwrap().name);
         }
 
     }
@@ -1,18 +1,30 @@
-#![allow(unused)]
 use anyhow::anyhow;
-use tracing::info;
-use subpar::{msg::StopId, manifest::ManifestStops};
-use std::{env};
+use subpar::manifest::{GtfsSource, ManifestStops, Policy, StaticSchedule};
+use std::{env, path::Path, process::ExitCode};
 
-
-fn main() -> anyhow::Result<()> {
-    env::set_var("RUST_LOG", "debug");
+/// Validate a stops.txt, or a whole static GTFS zip or directory, and print every bad row
+fn main() -> anyhow::Result<ExitCode> {
     tracing_subscriber::fmt::init();
-    let mut args = env::args();
-    let stops_file = args.nth(1).ok_or_else(|| anyhow!("usage: ./a stops.csv"))?;
-    tracing::info!("Reading {}", stops_file);
-    let stops = ManifestStops::from_file(&stops_file);
-    // for stop in stops.iter() { println!("{:?}", stop); }
-    Ok(())
+    let path = env::args().nth(1).ok_or_else(|| anyhow!("usage: ./a (stops.txt | gtfs.zip | gtfs_dir)"))?;
+    let is_zip = path.ends_with(".zip");
+    let reports = if is_zip || Path::new(&path).is_dir() {
+        let src = GtfsSource::detect(&path)?;
+        if let Some(info) = src.feed_info() {
+            println!("{src}: {info}");
+        }
+        match StaticSchedule::try_from_source(&src, Policy::SkipBad) {
+            Ok((_, reports)) => reports,
+            Err(report) => vec![report],
+        }
+    } else {
+        match ManifestStops::try_from_file(&path, Policy::SkipBad) {
+            Ok((_, report)) => vec![report],
+            Err(report) => vec![report],
+        }
+    };
+    for report in &reports {
+        println!("{report}");
+    }
+    let clean = reports.iter().all(|r| r.is_clean());
+    Ok(if clean { ExitCode::SUCCESS } else { ExitCode::FAILURE })
 }
-
@@ -1,3 +1,4 @@
+use crate::routes::{self, Agency};
 use std::fmt;
 
 #[derive(Clone)]
@@ -19,10 +20,14 @@ impl Feed {
     pub fn name(&self) -> &'static str {
         self.label
     }
+    pub fn agency(&self) -> Agency {
+        routes::catalog().feed_agency(self.label)
+    }
     pub fn from_static(f: &'static str) -> Self {
-        let base = "https://api-endpoint.mta.info/Dataservice/mtagtfsfeeds/nyct%2Fgtfs";
+        let root = "https://api-endpoint.mta.info/Dataservice/mtagtfsfeeds";
+        let base = format!("{root}/nyct%2Fgtfs");
         let url = match f {
-            "1234567" => base.to_string(),
+            "1234567" => base,
             | "ace"
             | "bdfm"
             | "g"
@@ -31,6 +36,8 @@ impl Feed {
             | "l"
             | "si"
             => format!("{}-{}", base, f),
+            "lirr" => format!("{root}/lirr%2Fgtfs-lirr"),
+            "mnr" => format!("{root}/mnr%2Fgtfs-mnr"),
             _ => panic!("unrecognized feed {f}"),
         };
         Feed::new(f, &url)
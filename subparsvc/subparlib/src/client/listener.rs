@@ -85,7 +85,7 @@ impl Listener {
             Ok(ms) => ms,
             Err(e) => { return tracing::error!("Parse failure for {name}: {e}") },
         };
-        let batch = match Batch::parse(&msgs) {
+        let batch = match Batch::parse_for(&msgs, feed.agency()) {
             Ok(br) => br,
             Err(e) => {
                 return tracing::warn!("Failed to parse results out of feed message: {e}");
@@ -1,6 +1,7 @@
 
 mod utils;
-pub use utils::timestamp::Timestamp;
+pub use utils::timestamp::{Timestamp, Humanize, Locale, Tz, NYC};
+pub use utils::sso::heap_fallbacks as sso_heap_fallbacks;
 
 mod proto;
 pub use proto::{FromGtfs, gtfs_realtime as gtfs};
@@ -19,3 +20,6 @@ pub mod state;
 pub mod api;
 pub use api::{Client as ApiClient};
 
+pub mod routes;
+pub use routes::{RouteCatalog, RouteInfo};
+
@@ -0,0 +1,72 @@
+use crate::msg::Date;
+use super::{csv::{FromCsv, CsvRow}, RowError};
+
+#[derive(Debug, Clone)]
+pub struct CalendarRow {
+    pub service_id: String,
+    /// Monday first
+    pub days: [bool; 7],
+    pub start: Date,
+    pub end: Date,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    Added,
+    Removed,
+}
+
+#[derive(Debug, Clone)]
+pub struct CalendarDateRow {
+    pub service_id: String,
+    pub date: Date,
+    pub exception: Exception,
+}
+
+const DAYS: [&str; 7] = ["monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday"];
+
+impl CalendarRow {
+    /// Ignores calendar_dates.txt, see `StaticSchedule::services_on`
+    pub fn runs_on(&self, date: Date) -> bool {
+        self.start <= date
+            && date <= self.end
+            && self.days[date.weekday().num_days_from_monday() as usize]
+    }
+}
+
+impl FromCsv for CalendarRow {
+    const FILENAME: &'static str = "calendar.txt";
+    const REQUIRED: &'static [&'static str] = &[
+        "service_id", "monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday",
+        "start_date", "end_date",
+    ];
+    fn parse(row: CsvRow) -> Result<Self, RowError> {
+        let service_id = row.get("service_id")?.to_owned();
+        let mut days = [false; 7];
+        for (day, col) in days.iter_mut().zip(DAYS) {
+            *day = match row.get(col)? {
+                "0" => false,
+                "1" => true,
+                x => return Err(row.error(col, format!("expected 0 or 1, not '{x}'"))),
+            };
+        }
+        let start = row.date("start_date")?;
+        let end = row.date("end_date")?;
+        Ok(CalendarRow { service_id, days, start, end })
+    }
+}
+
+impl FromCsv for CalendarDateRow {
+    const FILENAME: &'static str = "calendar_dates.txt";
+    const REQUIRED: &'static [&'static str] = &["service_id", "date", "exception_type"];
+    fn parse(row: CsvRow) -> Result<Self, RowError> {
+        let service_id = row.get("service_id")?.to_owned();
+        let date = row.date("date")?;
+        let exception = match row.get("exception_type")? {
+            "1" => Exception::Added,
+            "2" => Exception::Removed,
+            x => return Err(row.error("exception_type", format!("expected 1 or 2, not '{x}'"))),
+        };
+        Ok(CalendarDateRow { service_id, date, exception })
+    }
+}
@@ -1,20 +1,31 @@
-use std::str::{FromStr, Split};
 use std::{
+    collections::HashMap,
     fs::File,
     io::{BufRead, BufReader},
     marker::PhantomData,
+    mem,
+    str::FromStr,
 };
+use crate::msg::Date;
+use super::{RowError, GtfsSource};
 
-pub trait FromCsv {
-    const HEADER: &'static str;
+pub trait FromCsv: Sized {
     const FILENAME: &'static str;
-    fn parse(row: CsvIter) -> Self;
+    /// Columns that must be in the header.
+    /// Any other column is optional and extra columns are ignored.
+    const REQUIRED: &'static [&'static str];
+    fn parse(row: CsvRow) -> Result<Self, RowError>;
+    /// Rows to pass over without parsing, e.g. ones another type loads from the same file
+    fn skip(_row: &CsvRow) -> bool {
+        false
+    }
 }
 
 pub struct FileIter<T> {
-    reader: BufReader<File>,
-    line_buffer: String,
-    line_number: usize,
+    records: Records<Box<dyn BufRead>>,
+    header: Header,
+    line: usize,
+    broken: bool,
     row_type: PhantomData<T>,
 }
 
@@ -25,23 +36,58 @@ impl<T: FromCsv> FileIter<T> {
         Self::new(full.as_path().as_os_str().to_str().unwrap())
     }
     pub fn new(path: &str) -> Self {
-        let file = File::open(path).unwrap_or_else(|e| panic!("Failed to open file {path}: {e}"));
-        let mut reader = BufReader::new(file);
-        let mut line_buffer = String::new();
-        reader
-            .read_line(&mut line_buffer)
-            .unwrap_or_else(|e| panic!("Failed to read file header for {path}: {e}"));
-        assert_eq!(
-            line_buffer.trim(),
-            T::HEADER,
-            "Unexpected file header (left=actual)"
-        );
-        line_buffer.clear();
-        FileIter {
-            reader,
-            line_buffer,
-            line_number: 1,
-            row_type: PhantomData,
+        Self::try_new(path).unwrap_or_else(|e| panic!("Failed to open {path}: {e}"))
+    }
+    /// Fails on I/O errors or a header missing required columns
+    pub fn try_new(path: &str) -> Result<Self, RowError> {
+        let file = File::open(path).map_err(|e| RowError::new(0, None, e.to_string()))?;
+        Self::from_reader(Box::new(BufReader::new(file)))
+    }
+    /// `T::FILENAME` from a directory or zip
+    pub fn try_open(src: &GtfsSource) -> Result<Self, RowError> {
+        let reader = src.open(T::FILENAME).map_err(|e| RowError::new(0, None, e.to_string()))?;
+        Self::from_reader(reader)
+    }
+    pub fn from_reader(reader: Box<dyn BufRead>) -> Result<Self, RowError> {
+        let mut records = Records::new(reader);
+        let header = match records.next_record() {
+            Ok(Some(rec)) => Header::new(rec.fields),
+            Ok(None) => return Err(RowError::new(1, None, "empty file")),
+            Err(e) => return Err(e),
+        };
+        if let Some(col) = T::REQUIRED.iter().find(|c| !header.cols.contains_key(**c)) {
+            let reason = format!("missing required column; header was {:?}", header.names);
+            return Err(RowError::new(1, Some(col), reason));
+        }
+        Ok(FileIter { records, header, line: 1, broken: false, row_type: PhantomData })
+    }
+    /// Line the last row started on
+    pub fn line(&self) -> usize {
+        self.line
+    }
+    /// A bad row doesn't stop iteration, but a broken file (I/O, unterminated quote) does
+    pub fn try_next(&mut self) -> Option<Result<T, RowError>> {
+        if self.broken {
+            return None;
+        }
+        loop {
+            let rec = match self.records.next_record() {
+                Ok(rec) => rec?,
+                Err(e) => {
+                    self.broken = true;
+                    return Some(Err(e));
+                }
+            };
+            self.line = rec.line;
+            tracing::debug!("Parsing line {} fields {:?}", rec.line, rec.fields);
+            if rec.fields.len() > self.header.names.len() {
+                let extra = rec.fields.len() - self.header.names.len();
+                return Some(Err(RowError::new(rec.line, None, format!("{extra} unexpected fields"))));
+            }
+            let row = CsvRow { header: &self.header, fields: &rec.fields, line: rec.line };
+            if !T::skip(&row) {
+                return Some(T::parse(row));
+            }
         }
     }
 }
@@ -49,102 +95,175 @@ impl<T: FromCsv> FileIter<T> {
 impl<T: FromCsv> Iterator for FileIter<T> {
     type Item = T;
     fn next(&mut self) -> Option<T> {
-        let err = |e| panic!("Failed to read line {}: {e}", self.line_number);
-        if 0 == self
-            .reader
-            .read_line(&mut self.line_buffer)
-            .unwrap_or_else(err)
-        {
-            return None;
+        let res = self.try_next()?;
+        Some(res.unwrap_or_else(|e| panic!("Failed to parse {}: {e}", T::FILENAME)))
+    }
+}
+
+struct Header {
+    names: Vec<String>,
+    cols: HashMap<String, usize>,
+}
+
+impl Header {
+    fn new(names: Vec<String>) -> Self {
+        let names: Vec<String> = names.into_iter().map(|n| n.trim().to_owned()).collect();
+        let cols = names.iter().enumerate().map(|(i, n)| (n.clone(), i)).collect();
+        Header { names, cols }
+    }
+}
+
+struct Record {
+    /// Where the record starts; it may span several lines
+    line: usize,
+    fields: Vec<String>,
+}
+
+/// RFC 4180 records: quoted fields may contain commas, newlines, and "" escapes
+struct Records<R> {
+    reader: R,
+    buf: String,
+    line: usize,
+}
+
+impl<R: BufRead> Records<R> {
+    fn new(reader: R) -> Self {
+        Records { reader, buf: String::new(), line: 0 }
+    }
+    /// After an error, the rest of the file is unreadable
+    fn next_record(&mut self) -> Result<Option<Record>, RowError> {
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut quoted = false;
+        let mut start = self.line + 1;
+        loop {
+            self.buf.clear();
+            let n = self.reader.read_line(&mut self.buf)
+                .map_err(|e| RowError::new(self.line + 1, None, e.to_string()))?;
+            if n == 0 {
+                return match quoted {
+                    true => Err(RowError::new(start, None, "unterminated quote")),
+                    false => Ok(None),
+                };
+            }
+            self.line += 1;
+            let mut text = self.buf.strip_suffix('\n').unwrap_or(&self.buf);
+            text = text.strip_suffix('\r').unwrap_or(text);
+            if self.line == 1 {
+                text = text.strip_prefix('\u{feff}').unwrap_or(text);
+            }
+            if text.is_empty() && !quoted {
+                start += 1;
+                continue;
+            }
+            let mut chars = text.chars().peekable();
+            while let Some(c) = chars.next() {
+                match (quoted, c) {
+                    (true, '"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    },
+                    (true, '"') => quoted = false,
+                    (false, '"') if field.is_empty() => quoted = true,
+                    (false, ',') => fields.push(mem::take(&mut field)),
+                    (_, c) => field.push(c),
+                }
+            }
+            if quoted {
+                field.push('\n');
+                continue;
+            }
+            fields.push(field);
+            return Ok(Some(Record { line: start, fields }));
         }
-        let buf = &self.line_buffer.trim();
-        tracing::debug!("Parsing line {} buffer \"{buf}\"", self.line_number);
-        let elem = T::parse(CsvIter::new(self.line_number, buf));
-        self.line_buffer.clear();
-        self.line_number += 1;
-        Some(elem)
     }
 }
 
-pub struct CsvIter<'a> {
-    data: &'a str,
-    parts: Split<'a, char>,
+/// One record, with cells looked up by column name
+pub struct CsvRow<'a> {
+    header: &'a Header,
+    fields: &'a [String],
     line: usize,
 }
 
-impl<'a> CsvIter<'a> {
-    pub fn new(line: usize, data: &'a str) -> Self {
-        let parts = data.split(',');
-        CsvIter { data, line, parts }
-    }
-    pub fn try_next(&mut self) -> Result<&'a str, String> {
-        self.parts.next().ok_or_else(|| {
-            format!(
-                "Failed to parse line {} of CSV: Insufficient cells\n{}",
-                self.line, self.data
-            )
-        })
+impl<'a> CsvRow<'a> {
+    pub fn line(&self) -> usize {
+        self.line
     }
-    pub fn next(&mut self) -> &'a str {
-        self.try_next().unwrap_or_else(|s| panic!("{s}"))
+    fn cell(&self, col: &str) -> Option<&'a str> {
+        let fields = self.fields;
+        self.header.cols.get(col).and_then(|&i| fields.get(i)).map(String::as_str)
     }
-    pub fn next_n<const N: usize>(&mut self) -> [&'a str; N] {
-        [(); N].map(|()| self.next())
+    /// An error about this row's `col`
+    pub fn error(&self, col: &str, reason: impl Into<String>) -> RowError {
+        RowError::new(self.line, Some(col), reason)
     }
-    pub fn try_next_n<const N: usize>(&mut self) -> Result<[&'a str; N], String> {
-        let mut arr = [""; N];
-        for i in 0..N {
-            arr[i] = self.try_next()?;
-        }
-        Ok(arr)
+    pub fn get(&self, col: &str) -> Result<&'a str, RowError> {
+        self.cell(col).ok_or_else(|| self.error(col, "missing"))
     }
-    pub fn next_as<T: FromStr>(&mut self) -> T
+    /// Missing columns and empty cells become None
+    pub fn opt(&self, col: &str) -> Option<&'a str> {
+        self.cell(col).filter(|s| !s.is_empty())
+    }
+    pub fn get_as<T: FromStr>(&self, col: &str) -> Result<T, RowError>
+    where
+        T::Err: std::fmt::Debug,
+    {
+        self.parse(col, self.get(col)?)
+    }
+    pub fn opt_as<T: FromStr>(&self, col: &str) -> Result<Option<T>, RowError>
     where
         T::Err: std::fmt::Debug,
     {
-        self.try_next_as().unwrap()
+        self.opt(col).map(|s| self.parse(col, s)).transpose()
     }
-    pub fn try_next_as<T: FromStr>(&mut self) -> Result<T, String>
+    fn parse<T: FromStr>(&self, col: &str, s: &str) -> Result<T, RowError>
     where
         T::Err: std::fmt::Debug,
     {
-        self.next().parse().map_err(|e| {
-            format!(
-                "Failed to parse line {} of CSV: Wrong data type ({}): {:?}\n{}",
-                self.line,
-                std::any::type_name::<T>(),
-                e,
-                self.data
-            )
+        s.parse().map_err(|e| {
+            self.error(col, format!("wrong data type for '{s}' ({}): {e:?}", std::any::type_name::<T>()))
         })
     }
-    fn next_time(&mut self) -> (u8, u8, u8) {
-        todo!()
+    pub fn date(&self, col: &str) -> Result<Date, RowError> {
+        Date::from_yyyymmdd(self.get(col)?).map_err(|e| self.error(col, e.to_string()))
     }
-    pub fn try_next_time(&mut self) -> Result<(u8, u8, u8), String> {
-        let text = self.next();
+    pub fn time(&self, col: &str) -> Result<(u8, u8, u8), RowError> {
+        let text = self.get(col)?;
         let mut iter = text.split(':');
         let mut pop = || iter.next().map(str::parse::<u8>);
         match (pop(), pop(), pop(), pop()) {
             (Some(Ok(h)), Some(Ok(m)), Some(Ok(s)), None) => Ok((h, m, s)),
             (_, _, None, None) | (_, _, _, Some(_)) => {
-                Err(format!("Malformed time '{text}' not hh:mm:ss"))
+                Err(self.error(col, format!("malformed time '{text}' not hh:mm:ss")))
             }
-            bad => Err(format!("Unknown time: {bad:?}")),
+            bad => Err(self.error(col, format!("unknown time: {bad:?}"))),
         }
     }
-    pub fn finish(self) {
-        self.try_finish().unwrap();
-    }
-    pub fn try_finish(self) -> Result<(), String> {
-        let remain = self.parts.count();
-        if remain == 0 {
-            Ok(())
-        } else {
-            Err(format!(
-                "Found {} unexpected fields in CSV line\n{}",
-                remain, self.data
-            ))
-        }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Records;
+
+    fn parse(text: &str) -> Vec<(usize, Vec<String>)> {
+        let mut records = Records::new(text.as_bytes());
+        std::iter::from_fn(|| records.next_record().unwrap())
+            .map(|r| (r.line, r.fields))
+            .collect()
+    }
+
+    #[test]
+    fn rfc4180() {
+        let rows = parse("\u{feff}stop_id,stop_name\r\nA41,\"Jay St, MetroTech\"\r\n\n\"R29\",\"Say \"\"hi\"\"\nthere\"\n101,\n");
+        let expect = |line, fields: &[&str]| (line, fields.iter().map(|s| s.to_string()).collect());
+        assert_eq!(rows, [
+            expect(1, &["stop_id", "stop_name"]),
+            expect(2, &["A41", "Jay St, MetroTech"]),
+            expect(4, &["R29", "Say \"hi\"\nthere"]),
+            expect(6, &["101", ""]),
+        ]);
+        let mut bad = Records::new("a,\"b\nc".as_bytes());
+        assert!(bad.next_record().is_err());
     }
 }
@@ -0,0 +1,15 @@
+//! Static GTFS feeds for tests, zipped in memory
+
+use super::GtfsSource;
+use std::io::Write as _;
+
+/// Each file's lines are trimmed at the start, so they can be indented in the source
+pub fn zipped(files: &[(&str, &str)]) -> GtfsSource {
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+    for (name, text) in files {
+        zip.start_file(*name, zip::write::SimpleFileOptions::default()).unwrap();
+        let text: String = text.lines().map(|l| l.trim_start().to_owned() + "\n").collect();
+        zip.write_all(text.as_bytes()).unwrap();
+    }
+    GtfsSource::bytes(zip.finish().unwrap().into_inner()).unwrap()
+}
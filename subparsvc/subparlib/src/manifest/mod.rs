@@ -1,13 +1,40 @@
 //! Note about parsing CSV files:
 //! These files change very infrequently.
 //! Even if a file is malformed CSV, we need to adapt to the illegal format.
-//! Therefore parsing errors are panic!()s with as much debug info as possible.
-//! Unexpected parsing errors are irrecoverable.
-//! They should always be investigated and never be handled.
+//! Therefore parsing errors in dev tools are panic!()s with as much debug info as possible.
+//! Long-running services should use the try_ variants instead,
+//! which collect every bad row into a `LoadReport` and apply a `Policy`.
 
 mod csv;
-pub use csv::{CsvIter, FileIter, FromCsv};
+pub use csv::{CsvRow, FileIter, FromCsv};
+
+mod report;
+pub use report::{RowError, LoadReport, Loaded, Policy, try_load, try_load_with, try_load_file_with};
+
+mod source;
+pub use source::{GtfsSource, ZipSource, FeedInfo};
 
 mod stop;
 pub use stop::{StopRow, ManifestStops};
 
+mod route;
+pub use route::RouteRow;
+
+mod trip;
+pub use trip::{TripRow, StopTimeRow};
+
+mod calendar;
+pub use calendar::{CalendarRow, CalendarDateRow, Exception};
+
+mod transfer;
+pub use transfer::{TransferRow, ShapePoint};
+
+mod pathway;
+pub use pathway::{PathwayRow, PathwayMode, StopNode};
+
+mod schedule;
+pub use schedule::StaticSchedule;
+
+#[cfg(test)]
+pub(crate) mod fixtures;
+
@@ -0,0 +1,81 @@
+use super::{csv::{FromCsv, CsvRow}, RowError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathwayMode {
+    Walkway,
+    Stairs,
+    MovingSidewalk,
+    Escalator,
+    Elevator,
+    FareGate,
+    ExitGate,
+}
+
+impl PathwayMode {
+    /// Usable in a wheelchair
+    pub fn step_free(&self) -> bool {
+        !matches!(self, PathwayMode::Stairs | PathwayMode::Escalator)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PathwayRow {
+    /// Matched against equipment ids, so an elevator's outage closes its pathway
+    pub pathway_id: String,
+    pub from: String,
+    pub to: String,
+    pub mode: PathwayMode,
+    pub bidirectional: bool,
+}
+
+/// Any row of stops.txt, including the entrances and nodes that pathways connect.
+/// `StopRow` only accepts stations and platforms, and skips the rest.
+#[derive(Debug, Clone)]
+pub struct StopNode {
+    pub id: String,
+    /// 0 platform, 1 station, 2 entrance/exit, 3 generic node, 4 boarding area
+    pub location_type: u8,
+    pub parent: Option<String>,
+}
+
+impl FromCsv for PathwayRow {
+    const FILENAME: &'static str = "pathways.txt";
+    const REQUIRED: &'static [&'static str] =
+        &["pathway_id", "from_stop_id", "to_stop_id", "pathway_mode", "is_bidirectional"];
+    fn parse(row: CsvRow) -> Result<Self, RowError> {
+        let mode = match row.get_as::<u8>("pathway_mode")? {
+            1 => PathwayMode::Walkway,
+            2 => PathwayMode::Stairs,
+            3 => PathwayMode::MovingSidewalk,
+            4 => PathwayMode::Escalator,
+            5 => PathwayMode::Elevator,
+            6 => PathwayMode::FareGate,
+            7 => PathwayMode::ExitGate,
+            x => return Err(row.error("pathway_mode", format!("unknown mode {x}"))),
+        };
+        let bidirectional = match row.get("is_bidirectional")? {
+            "0" => false,
+            "1" => true,
+            x => return Err(row.error("is_bidirectional", format!("expected 0 or 1, not '{x}'"))),
+        };
+        Ok(PathwayRow {
+            pathway_id: row.get("pathway_id")?.to_owned(),
+            from: row.get("from_stop_id")?.to_owned(),
+            to: row.get("to_stop_id")?.to_owned(),
+            mode,
+            bidirectional,
+        })
+    }
+}
+
+impl FromCsv for StopNode {
+    const FILENAME: &'static str = "stops.txt";
+    const REQUIRED: &'static [&'static str] = &["stop_id"];
+    fn parse(row: CsvRow) -> Result<Self, RowError> {
+        Ok(StopNode {
+            id: row.get("stop_id")?.to_owned(),
+            location_type: row.opt_as("location_type")?.unwrap_or(0),
+            parent: row.opt("parent_station").map(str::to_owned),
+        })
+    }
+}
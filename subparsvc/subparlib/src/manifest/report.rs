@@ -0,0 +1,211 @@
+use std::{error::Error as StdError, fmt};
+use super::{csv::{FileIter, FromCsv}, GtfsSource};
+
+/// One bad row (or header) in a CSV file
+#[derive(Debug, Clone)]
+pub struct RowError {
+    pub line: usize,
+    pub column: Option<String>,
+    pub reason: String,
+}
+
+/// What to do about bad rows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// Stop at the first bad row
+    FailFast,
+    /// Drop bad rows and keep going
+    SkipBad,
+    /// Drop bad rows, but fail if there are more than this many
+    Threshold(usize),
+}
+
+/// Every problem found loading one file
+#[derive(Debug, Clone)]
+pub struct LoadReport {
+    pub file: String,
+    /// Rows successfully loaded
+    pub rows: usize,
+    pub errors: Vec<RowError>,
+    /// Whether the load as a whole failed under its `Policy`
+    pub failed: bool,
+}
+
+impl RowError {
+    pub fn new(line: usize, column: Option<&str>, reason: impl Into<String>) -> Self {
+        RowError { line, column: column.map(str::to_owned), reason: reason.into() }
+    }
+}
+
+impl LoadReport {
+    /// For an optional file that isn't there
+    pub fn empty(file: String) -> Self {
+        LoadReport { file, rows: 0, errors: vec![], failed: false }
+    }
+    pub fn is_clean(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+pub type Loaded<T> = Result<(Vec<T>, LoadReport), LoadReport>;
+
+/// Load every row of `T::FILENAME` from `src`, collecting errors according to `policy`.
+/// `check` can reject rows that parse fine but conflict with earlier ones.
+pub fn try_load_with<T: FromCsv>(
+    src: &GtfsSource,
+    policy: Policy,
+    check: impl FnMut(&T) -> Result<(), String>,
+) -> Loaded<T> {
+    load_rows(src.describe(T::FILENAME), FileIter::try_open(src), policy, check)
+}
+
+pub fn try_load<T: FromCsv>(src: &GtfsSource, policy: Policy) -> Loaded<T> {
+    try_load_with(src, policy, |_| Ok(()))
+}
+
+/// Like `try_load_with` for a lone CSV file, whatever it's called
+pub fn try_load_file_with<T: FromCsv>(
+    path: &str,
+    policy: Policy,
+    check: impl FnMut(&T) -> Result<(), String>,
+) -> Loaded<T> {
+    load_rows(path.to_owned(), FileIter::try_new(path), policy, check)
+}
+
+fn load_rows<T: FromCsv>(
+    file: String,
+    iter: Result<FileIter<T>, RowError>,
+    policy: Policy,
+    mut check: impl FnMut(&T) -> Result<(), String>,
+) -> Loaded<T> {
+    let mut report = LoadReport { file, rows: 0, errors: vec![], failed: false };
+    let mut iter = match iter {
+        Ok(iter) => iter,
+        Err(e) => {
+            report.errors.push(e);
+            report.failed = true;
+            return Err(report);
+        }
+    };
+    let mut rows = vec![];
+    while let Some(res) = iter.try_next() {
+        let res = res.and_then(|row| match check(&row) {
+            Ok(()) => Ok(row),
+            Err(reason) => Err(RowError::new(iter.line(), None, reason)),
+        });
+        match res {
+            Ok(row) => rows.push(row),
+            Err(e) => {
+                report.errors.push(e);
+                if policy == Policy::FailFast {
+                    report.failed = true;
+                    return Err(report);
+                }
+            }
+        }
+    }
+    report.rows = rows.len();
+    match policy {
+        Policy::Threshold(max) if report.errors.len() > max => {
+            report.failed = true;
+            Err(report)
+        }
+        _ => Ok((rows, report)),
+    }
+}
+
+
+impl StdError for RowError {}
+impl StdError for LoadReport {}
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}", self.line)?;
+        if let Some(col) = &self.column {
+            write!(f, ", column {col}")?;
+        }
+        write!(f, ": {}", self.reason)
+    }
+}
+
+impl fmt::Display for LoadReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = if self.failed { "FAILED" } else { "loaded" };
+        write!(f, "{}: {status}, {} rows, {} errors", self.file, self.rows, self.errors.len())?;
+        for e in &self.errors {
+            write!(f, "\n  {e}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{try_load, try_load_with, Policy, LoadReport};
+    use crate::manifest::{GtfsSource, CalendarDateRow};
+    use std::{fs, path::PathBuf};
+
+    // rows 3, 4, and 6 are bad
+    const DATES: &str = "service_id,date,exception_type\n\
+        A,20240704,1\n\
+        B,20240704,3\n\
+        C,July 4,1\n\
+        D,20240705,2\n\
+        E,20240705,1,extra\n";
+
+    fn fixture(name: &str, text: &str) -> (GtfsSource, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("subpar-report-{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("calendar_dates.txt"), text).unwrap();
+        (GtfsSource::Dir(dir.clone()), dir)
+    }
+
+    fn lines(report: &LoadReport) -> Vec<usize> {
+        report.errors.iter().map(|e| e.line).collect()
+    }
+
+    #[test]
+    fn policies() {
+        let (src, dir) = fixture("policies", DATES);
+        let load = |policy| try_load::<CalendarDateRow>(&src, policy);
+
+        let report = load(Policy::FailFast).unwrap_err();
+        assert!(report.failed);
+        assert_eq!(lines(&report), [3]);
+        assert_eq!(report.errors[0].column.as_deref(), Some("exception_type"));
+
+        let (rows, report) = load(Policy::SkipBad).unwrap();
+        let ids: Vec<_> = rows.iter().map(|r| r.service_id.as_str()).collect();
+        assert_eq!(ids, ["A", "D"]);
+        assert_eq!((report.rows, report.failed), (2, false));
+        assert_eq!(lines(&report), [3, 4, 6]);
+        assert_eq!(report.errors[1].column.as_deref(), Some("date"));
+        assert_eq!(report.errors[2].column, None, "extra fields aren't any one column");
+
+        let (rows, report) = load(Policy::Threshold(3)).unwrap();
+        assert_eq!((rows.len(), report.errors.len()), (2, 3));
+        let report = load(Policy::Threshold(2)).unwrap_err();
+        assert!(report.failed);
+        assert_eq!((report.rows, report.errors.len()), (2, 3), "the whole file is still checked");
+        assert!(report.to_string().starts_with(&format!("{}: FAILED, 2 rows, 3 errors", src.describe("calendar_dates.txt"))));
+
+        let (rows, report) = try_load_with(&src, Policy::SkipBad, |row: &CalendarDateRow| match row.service_id.as_str() {
+            "D" => Err("no D".into()),
+            _ => Ok(()),
+        }).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(lines(&report), [3, 4, 5, 6]);
+        assert_eq!(report.errors[2].reason, "no D");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn bad_header() {
+        let (src, dir) = fixture("header", "service_id,date\nA,20240704\n");
+        let report = try_load::<CalendarDateRow>(&src, Policy::SkipBad).unwrap_err();
+        assert!(report.failed);
+        assert_eq!(lines(&report), [1]);
+        assert_eq!(report.errors[0].column.as_deref(), Some("exception_type"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,28 @@
+use crate::msg::Route;
+use super::{csv::{FromCsv, CsvRow}, RowError};
+
+#[derive(Debug, Clone)]
+pub struct RouteRow {
+    pub route: Route,
+    pub short_name: String,
+    pub long_name: String,
+    pub desc: String,
+    pub color: Option<String>,
+    pub text_color: Option<String>,
+}
+
+impl FromCsv for RouteRow {
+    const FILENAME: &'static str = "routes.txt";
+    const REQUIRED: &'static [&'static str] = &["route_id"];
+    fn parse(row: CsvRow) -> Result<Self, RowError> {
+        let text = |col| row.opt(col).unwrap_or_default().to_owned();
+        Ok(RouteRow {
+            route: Route::make(row.get("route_id")?),
+            short_name: text("route_short_name"),
+            long_name: text("route_long_name"),
+            desc: text("route_desc"),
+            color: row.opt("route_color").map(|c| format!("#{c}")),
+            text_color: row.opt("route_text_color").map(|c| format!("#{c}")),
+        })
+    }
+}
@@ -0,0 +1,341 @@
+use std::collections::{HashMap, HashSet};
+use chrono::TimeZone as _;
+use crate::{Timestamp, NYC, msg::{Date, Time, Route, StopId, TripDir, TripId, TripParts}};
+use super::{
+    csv::FromCsv,
+    report::{try_load, try_load_with, Loaded, LoadReport, Policy},
+    source::{GtfsSource, FeedInfo},
+    ManifestStops, RouteRow, TripRow, StopTimeRow, CalendarRow, CalendarDateRow, Exception,
+    TransferRow, ShapePoint, PathwayRow, StopNode,
+};
+
+/// Everything in a static GTFS directory, indexed for lookups
+pub struct StaticSchedule {
+    pub feed_info: Option<FeedInfo>,
+    pub stops: ManifestStops,
+    routes: HashMap<Route, RouteRow>,
+    trips: HashMap<String, TripRow>,
+    // by trip_id, in stop_sequence order
+    stop_times: HashMap<String, Vec<StopTimeRow>>,
+    calendar: HashMap<String, CalendarRow>,
+    exceptions: HashMap<Date, Vec<CalendarDateRow>>,
+    transfers: HashMap<StopId, Vec<TransferRow>>,
+    shapes: HashMap<String, Vec<ShapePoint>>,
+    pathways: Vec<PathwayRow>,
+    // every stops.txt row, but only loaded when there are pathways
+    nodes: HashMap<String, StopNode>,
+    // realtime-compatible trip ids => trip_ids, see `TripParts::from_static_id`
+    by_parts: HashMap<TripParts, Vec<String>>,
+    // by route and direction, each distinct stop sequence in a stable order
+    patterns: HashMap<(Route, TripDir), Vec<Vec<StopId>>>,
+}
+
+impl StaticSchedule {
+    /// calendar_dates.txt, transfers.txt, shapes.txt, and pathways.txt are optional
+    pub fn from_source(src: &GtfsSource) -> Self {
+        match Self::try_from_source(src, Policy::FailFast) {
+            Ok((schedule, _)) => schedule,
+            Err(report) => panic!("{report}"),
+        }
+    }
+    /// One report per file loaded
+    pub fn try_from_source(src: &GtfsSource, policy: Policy) -> Result<(Self, Vec<LoadReport>), LoadReport> {
+        let mut reports = vec![];
+        let (stops, report) = ManifestStops::try_from_source(src, policy)?;
+        reports.push(report);
+        let routes: HashMap<_, _> = keep(&mut reports, try_load::<RouteRow>(src, policy))?
+            .into_iter()
+            .map(|r| (r.route, r))
+            .collect();
+        let mut seen = HashSet::new();
+        let res = try_load_with(src, policy, |row: &TripRow| {
+            match seen.insert(row.trip_id.clone()) {
+                true => Ok(()),
+                false => Err(format!("duplicate trip_id {}", row.trip_id)),
+            }
+        });
+        let trips: HashMap<_, _> = keep(&mut reports, res)?
+            .into_iter()
+            .map(|t| (t.trip_id.clone(), t))
+            .collect();
+        let res = try_load_with(src, policy, |row: &StopTimeRow| {
+            match trips.contains_key(&row.trip_id) {
+                true => Ok(()),
+                false => Err(format!("unknown trip_id {}", row.trip_id)),
+            }
+        });
+        let mut stop_times = HashMap::<String, Vec<StopTimeRow>>::new();
+        for row in keep(&mut reports, res)? {
+            stop_times.entry(row.trip_id.clone()).or_default().push(row);
+        }
+        for times in stop_times.values_mut() {
+            times.sort_by_key(|st| st.seq);
+        }
+        let res = try_load::<CalendarRow>(src, policy);
+        let calendar: HashMap<_, _> = keep(&mut reports, res)?
+            .into_iter()
+            .map(|c| (c.service_id.clone(), c))
+            .collect();
+        let mut exceptions = HashMap::<Date, Vec<CalendarDateRow>>::new();
+        for row in keep(&mut reports, optional::<CalendarDateRow>(src, policy))? {
+            exceptions.entry(row.date).or_default().push(row);
+        }
+        let mut transfers = HashMap::<StopId, Vec<TransferRow>>::new();
+        for row in keep(&mut reports, optional::<TransferRow>(src, policy))? {
+            transfers.entry(row.from).or_default().push(row);
+        }
+        let mut shapes = HashMap::<String, Vec<ShapePoint>>::new();
+        for row in keep(&mut reports, optional::<ShapePoint>(src, policy))? {
+            shapes.entry(row.shape_id.clone()).or_default().push(row);
+        }
+        for points in shapes.values_mut() {
+            points.sort_by_key(|p| p.seq);
+        }
+        let pathways = keep(&mut reports, optional::<PathwayRow>(src, policy))?;
+        let nodes = match pathways.is_empty() {
+            true => HashMap::new(),
+            false => keep(&mut reports, try_load::<StopNode>(src, policy))?
+                .into_iter()
+                .map(|n| (n.id.clone(), n))
+                .collect(),
+        };
+        let feed_info = src.feed_info();
+        tracing::info!(
+            "Loaded static schedule from {src} ({}): {} routes, {} trips, {} services",
+            feed_info.as_ref().map_or("no feed_info".into(), ToString::to_string),
+            routes.len(), trips.len(), calendar.len()
+        );
+        let mut by_parts = HashMap::<TripParts, Vec<String>>::new();
+        for trip in trips.values() {
+            match trip.parts() {
+                Some(parts) => by_parts.entry(parts).or_default().push(trip.trip_id.clone()),
+                None => tracing::debug!("static trip {} has no realtime equivalent", trip.trip_id),
+            }
+        }
+        let mut patterns = HashMap::<(Route, TripDir), HashSet<Vec<StopId>>>::new();
+        for trip in trips.values() {
+            let stops = stop_times.get(&trip.trip_id).into_iter().flatten().map(|st| st.stop).collect();
+            patterns.entry((trip.route, trip.dir)).or_default().insert(stops);
+        }
+        let patterns = patterns.into_iter()
+            .map(|(k, set)| {
+                let mut v: Vec<_> = set.into_iter().collect();
+                v.sort_by(|a, b| a.iter().map(AsRef::<str>::as_ref).cmp(b.iter().map(AsRef::as_ref)));
+                (k, v)
+            })
+            .collect();
+        let schedule = StaticSchedule {
+            feed_info, stops, routes, trips, stop_times, calendar, exceptions, transfers, shapes,
+            pathways, nodes, by_parts, patterns,
+        };
+        Ok((schedule, reports))
+    }
+    pub fn pathways(&self) -> &[PathwayRow] {
+        &self.pathways
+    }
+    /// Any stops.txt row, if the feed has pathways
+    pub fn node(&self, id: &str) -> Option<&StopNode> {
+        self.nodes.get(id)
+    }
+    pub fn route(&self, route: &Route) -> Option<&RouteRow> {
+        self.routes.get(route)
+    }
+    pub fn trip(&self, trip_id: &str) -> Option<&TripRow> {
+        self.trips.get(trip_id)
+    }
+    pub fn trips(&self) -> impl Iterator<Item = &TripRow> {
+        self.trips.values()
+    }
+    /// Each distinct stop sequence a route runs in direction `dir`, e.g. one per branch
+    pub fn stop_patterns(&self, route: &Route, dir: TripDir) -> &[Vec<StopId>] {
+        self.patterns.get(&(*route, dir)).map(Vec::as_slice).unwrap_or_default()
+    }
+    pub fn stop_times(&self, trip_id: &str) -> &[StopTimeRow] {
+        self.stop_times.get(trip_id).map(Vec::as_slice).unwrap_or_default()
+    }
+    /// service_ids running on `date`, after calendar_dates.txt exceptions
+    pub fn services_on(&self, date: Date) -> HashSet<&str> {
+        let mut services: HashSet<&str> = self.calendar.values()
+            .filter(|c| c.runs_on(date))
+            .map(|c| c.service_id.as_str())
+            .collect();
+        for ex in self.exceptions.get(&date).into_iter().flatten() {
+            match ex.exception {
+                Exception::Added => services.insert(&ex.service_id),
+                Exception::Removed => services.remove(ex.service_id.as_str()),
+            };
+        }
+        services
+    }
+    /// Whether `service_id` runs on `date`, after calendar_dates.txt exceptions
+    pub fn service_runs(&self, service_id: &str, date: Date) -> bool {
+        let exception = self.exceptions.get(&date)
+            .and_then(|ex| ex.iter().find(|e| e.service_id == service_id));
+        match exception {
+            Some(e) => e.exception == Exception::Added,
+            None => self.calendar.get(service_id).is_some_and(|c| c.runs_on(date)),
+        }
+    }
+    /// The static trip a realtime trip is running,
+    /// matched by route, direction, and origin time on its service day
+    pub fn match_trip(&self, trip: &TripId) -> Option<&TripRow> {
+        let day = Date::new(trip.date());
+        self.by_parts.get(&trip.data())?
+            .iter()
+            .filter_map(|id| self.trips.get(id))
+            .find(|t| self.service_runs(&t.service_id, day))
+    }
+    /// When `trip` is scheduled to arrive at `stop` on service day `date`
+    pub fn scheduled_arrival(&self, trip: &TripRow, stop: &StopId, date: Date) -> Option<Timestamp> {
+        let st = self.stop_times(&trip.trip_id).iter().find(|st| &st.stop == stop)?;
+        local_time(date, st.arrival)
+    }
+    /// Trips scheduled for the service day `date`.
+    /// Their times may run past midnight into the next calendar day.
+    pub fn trips_on(&self, date: Date) -> impl Iterator<Item = &TripRow> {
+        let services = self.services_on(date);
+        self.trips.values().filter(move |t| services.contains(t.service_id.as_str()))
+    }
+    /// How many trips of each route and direction are scheduled to be running at some point in `[from, to]`
+    pub fn running(&self, from: Timestamp, to: Timestamp) -> HashMap<(Route, TripDir), usize> {
+        let today = Date::new(to.in_tz(NYC).date_naive());
+        let yesterday = Date::new(today.to_naive() - chrono::TimeDelta::days(1));
+        let mut counts = HashMap::new();
+        for date in [yesterday, today] {
+            for trip in self.trips_on(date) {
+                let times = self.stop_times(&trip.trip_id);
+                let (Some(first), Some(last)) = (times.first(), times.last()) else { continue };
+                let start = local_time(date, first.departure);
+                let end = local_time(date, last.arrival);
+                if start.is_some_and(|t| t <= to) && end.is_some_and(|t| t >= from) {
+                    *counts.entry((trip.route, trip.dir)).or_default() += 1;
+                }
+            }
+        }
+        counts
+    }
+    pub fn transfers_from(&self, stop: &StopId) -> &[TransferRow] {
+        self.transfers.get(stop).map(Vec::as_slice).unwrap_or_default()
+    }
+    pub fn shape(&self, shape_id: &str) -> &[ShapePoint] {
+        self.shapes.get(shape_id).map(Vec::as_slice).unwrap_or_default()
+    }
+}
+
+fn keep<T>(reports: &mut Vec<LoadReport>, res: Loaded<T>) -> Result<Vec<T>, LoadReport> {
+    let (rows, report) = res?;
+    reports.push(report);
+    Ok(rows)
+}
+
+/// GTFS times count from noon minus 12h on the service day, which is an hour off midnight
+/// when the clocks change, and may exceed 24:00:00
+fn local_time(date: Date, t: Time) -> Option<Timestamp> {
+    let noon = NYC.from_local_datetime(&date.to_naive().and_hms_opt(12, 0, 0)?).single()?;
+    let secs = ((t.offset as i64 * 24 + t.h as i64) * 60 + t.m as i64) * 60 + t.s as i64;
+    let at = noon - chrono::TimeDelta::hours(12) + chrono::TimeDelta::seconds(secs);
+    Some(Timestamp::from_utc(at.with_timezone(&chrono::Utc)))
+}
+
+/// A missing file loads as zero rows
+fn optional<T: FromCsv>(src: &GtfsSource, policy: Policy) -> Loaded<T> {
+    if src.contains(T::FILENAME) {
+        try_load(src, policy)
+    } else {
+        tracing::info!("No {} in {src}", T::FILENAME);
+        Ok((vec![], LoadReport::empty(src.describe(T::FILENAME))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StaticSchedule, local_time};
+    use crate::{Timestamp, manifest::fixtures::zipped, msg::{Date, Time, Route, StopId, TripDir, TripId}};
+
+    const FILES: &[(&str, &str)] = &[
+        ("stops.txt", "stop_id,stop_name,stop_lat,stop_lon,parent_station\n\
+            101,Van Cortlandt Park-242 St,40.889248,-73.898583,\n\
+            101S,Van Cortlandt Park-242 St,40.889248,-73.898583,101\n"),
+        ("routes.txt", "route_id,route_short_name\n1,1\n"),
+        ("trips.txt", "route_id,trip_id,service_id,trip_headsign,direction_id\n\
+            1,AFA23GEN-1038-Sunday-00_144200_1..S03R,Sunday,South Ferry,1\n"),
+        ("stop_times.txt", "trip_id,stop_id,arrival_time,departure_time,stop_sequence\n\
+            AFA23GEN-1038-Sunday-00_144200_1..S03R,101S,24:02:00,24:02:00,1\n"),
+        ("calendar.txt", "service_id,monday,tuesday,wednesday,thursday,friday,saturday,sunday,start_date,end_date\n\
+            Sunday,0,0,0,0,0,0,1,20240101,20241231\n"),
+        ("calendar_dates.txt", "service_id,date,exception_type\nSunday,20240704,1\n"),
+    ];
+
+    #[test]
+    fn adherence() {
+        let sched = StaticSchedule::from_source(&zipped(FILES));
+        let sunday = Date::make(2024, 7, 7);
+        assert_eq!(sched.trips_on(sunday).count(), 1);
+        assert_eq!(sched.trips_on(Date::make(2024, 7, 8)).count(), 0);
+        assert_eq!(sched.trips_on(Date::make(2024, 7, 4)).count(), 1);
+        let trip = TripId::parse("144200_1..S03R", sunday).unwrap();
+        let row = sched.match_trip(&trip).unwrap();
+        assert_eq!(row.headsign, "South Ferry");
+        let at = sched.scheduled_arrival(row, &StopId::make("101S"), sunday).unwrap();
+        // 00:02 EDT the next morning
+        assert_eq!(at, Timestamp::from_unix(1720411320));
+        let monday = TripId::parse("144200_1..S03R", Date::make(2024, 7, 8)).unwrap();
+        assert!(sched.match_trip(&monday).is_none());
+        assert_eq!(sched.stop_patterns(&Route::make("1"), TripDir::South), [vec![StopId::make("101S")]]);
+        assert!(sched.stop_patterns(&Route::make("1"), TripDir::North).is_empty());
+    }
+
+    #[test]
+    fn spring_forward() {
+        let day = |d: u32| Date::new(chrono::NaiveDate::from_ymd_opt(2024, 3, d).unwrap());
+        let at = |date, h, m| local_time(date, Time::from_gtfs((h, m, 0))).map(|t| t.as_utc().to_rfc3339());
+        assert_eq!(at(day(8), 8, 0).as_deref(), Some("2024-03-08T13:00:00+00:00"));
+        // the night the clocks go forward at 02:00, counted from 11 PM EST
+        assert_eq!(at(day(9), 26, 30).as_deref(), Some("2024-03-10T07:30:00+00:00"), "03:30 EDT");
+        assert_eq!(at(day(10), 2, 30).as_deref(), Some("2024-03-10T06:30:00+00:00"));
+        assert_eq!(at(day(10), 25, 0).as_deref(), Some("2024-03-11T05:00:00+00:00"), "01:00 EDT");
+    }
+
+    #[test]
+    fn calendar() {
+        let mut files = FILES[..3].to_vec();
+        files[2] = ("trips.txt", "route_id,trip_id,service_id,trip_headsign,direction_id\n\
+            1,AFA23GEN-1038-Weekday-00_060000_1..S03R,Weekday,South Ferry,1\n\
+            1,AFA23GEN-1038-Sunday-00_060000_1..S03R,Sunday,South Ferry,1\n");
+        files.extend([
+            ("stop_times.txt", "trip_id,stop_id,arrival_time,departure_time,stop_sequence\n"),
+            ("calendar.txt", "service_id,monday,tuesday,wednesday,thursday,friday,saturday,sunday,start_date,end_date\n\
+                Weekday,1,1,1,1,1,0,0,20240701,20240731\n\
+                Sunday,0,0,0,0,0,0,1,20240101,20241231\n"),
+            ("calendar_dates.txt", "service_id,date,exception_type\n\
+                Weekday,20240704,2\n\
+                Sunday,20240704,1\n\
+                Special,20240705,1\n\
+                Weekday,20240731,2\n"),
+        ]);
+        let sched = StaticSchedule::from_source(&zipped(&files));
+        let on = |m, d| {
+            let mut services: Vec<_> = sched.services_on(Date::make(2024, m, d)).into_iter().collect();
+            services.sort();
+            services
+        };
+        assert_eq!(on(6, 28), [] as [&str; 0], "Friday before the range starts");
+        assert_eq!(on(7, 1), ["Weekday"], "the first day is included");
+        assert_eq!(on(7, 4), ["Sunday"], "removed and added on a holiday");
+        assert_eq!(on(7, 5), ["Special", "Weekday"], "added without a calendar.txt row");
+        assert_eq!(on(7, 30), ["Weekday"]);
+        assert_eq!(on(7, 31), [] as [&str; 0], "the last day, removed");
+        assert_eq!(on(8, 1), [] as [&str; 0], "Thursday after the range ends");
+        assert_eq!(on(12, 29), ["Sunday"]);
+        assert_eq!(on(12, 30), [] as [&str; 0]);
+
+        let july4 = Date::make(2024, 7, 4);
+        assert!(!sched.service_runs("Weekday", july4));
+        assert!(sched.service_runs("Sunday", july4));
+        assert!(sched.service_runs("Special", Date::make(2024, 7, 5)));
+        assert!(!sched.service_runs("Special", Date::make(2024, 7, 6)));
+        let trips: Vec<_> = sched.trips_on(july4).map(|t| t.service_id.as_str()).collect();
+        assert_eq!(trips, ["Sunday"]);
+        assert_eq!(sched.trips_on(Date::make(2024, 7, 2)).count(), 1);
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    fmt, fs,
+    io::{self, BufRead, BufReader, Cursor, Read as _},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use crate::msg::Date;
+use super::{csv::{FromCsv, CsvRow}, RowError};
+
+/// Where static GTFS files come from
+#[derive(Debug, Clone)]
+pub enum GtfsSource {
+    /// An unzipped archive
+    Dir(PathBuf),
+    /// e.g. google_transit.zip, or one already in memory
+    Zip(ZipSource),
+}
+
+/// A zip archive, opened once; its entries are read through the same handle
+#[derive(Clone)]
+pub struct ZipSource {
+    /// The path, or a description of the bytes
+    name: Arc<str>,
+    archive: Arc<Mutex< zip::ZipArchive<Box<dyn ReadSeek>> >>,
+}
+
+/// feed_info.txt, when the archive has one
+#[derive(Debug, Clone)]
+pub struct FeedInfo {
+    pub publisher: String,
+    pub version: Option<String>,
+    pub start: Option<Date>,
+    pub end: Option<Date>,
+}
+
+impl GtfsSource {
+    /// A zip file, a directory of txt files, or else the newest zip in a directory
+    pub fn detect(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        if path.is_file() {
+            GtfsSource::zip(path)
+        } else if path.join("stops.txt").exists() {
+            Ok(GtfsSource::Dir(path.to_owned()))
+        } else {
+            Self::latest(path)
+        }
+    }
+    /// The most recently modified zip in `dir`, so dated archives can sit side by side
+    pub fn latest(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        let mut newest = None;
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "zip") {
+                let key = (entry.metadata()?.modified()?, path);
+                newest = newest.max(Some(key));
+            }
+        }
+        let (_, path) = newest.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("no GTFS zip in {}", dir.display()))
+        })?;
+        tracing::info!("Using static GTFS archive {}", path.display());
+        GtfsSource::zip(path)
+    }
+    /// Fails if it isn't a readable zip
+    pub fn zip(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let file = fs::File::open(path)?;
+        ZipSource::new(path.display().to_string(), Box::new(file)).map(GtfsSource::Zip)
+    }
+    /// A zip archive already in memory, e.g. freshly downloaded
+    pub fn bytes(bytes: impl Into<Arc<[u8]>>) -> io::Result<Self> {
+        let bytes = bytes.into();
+        ZipSource::new(format!("<{} byte zip>", bytes.len()), Box::new(Cursor::new(bytes))).map(GtfsSource::Zip)
+    }
+    pub fn contains(&self, name: &str) -> bool {
+        match self {
+            GtfsSource::Dir(dir) => dir.join(name).exists(),
+            GtfsSource::Zip(zip) => zip.archive.lock().unwrap().index_for_name(name).is_some(),
+        }
+    }
+    /// Zip entries are decompressed into memory
+    pub fn open(&self, name: &str) -> io::Result<Box<dyn BufRead>> {
+        match self {
+            GtfsSource::Dir(dir) => Ok(Box::new(BufReader::new(fs::File::open(dir.join(name))?))),
+            GtfsSource::Zip(zip) => Ok(Box::new(Cursor::new(zip.read(name)?))),
+        }
+    }
+    /// e.g. "archive/google_transit.zip:stops.txt"
+    pub fn describe(&self, name: &str) -> String {
+        match self {
+            GtfsSource::Dir(dir) => dir.join(name).display().to_string(),
+            GtfsSource::Zip(_) => format!("{self}:{name}"),
+        }
+    }
+    pub fn feed_info(&self) -> Option<FeedInfo> {
+        if !self.contains(FeedInfo::FILENAME) {
+            return None;
+        }
+        super::FileIter::<FeedInfo>::try_open(self)
+            .ok()?
+            .try_next()?
+            .map_err(|e| tracing::warn!("Bad {}: {e}", self.describe(FeedInfo::FILENAME)))
+            .ok()
+    }
+}
+
+impl ZipSource {
+    /// Reads the archive's directory
+    fn new(name: String, reader: Box<dyn ReadSeek>) -> io::Result<Self> {
+        let archive = zip::ZipArchive::new(reader)?;
+        Ok(ZipSource { name: name.into(), archive: Arc::new(Mutex::new(archive)) })
+    }
+    fn read(&self, name: &str) -> io::Result<Vec<u8>> {
+        let mut archive = self.archive.lock().unwrap();
+        let mut entry = archive.by_name(name)?;
+        let mut buf = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+trait ReadSeek: io::Read + io::Seek + Send {}
+impl<T: io::Read + io::Seek + Send> ReadSeek for T {}
+
+impl fmt::Display for GtfsSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GtfsSource::Dir(path) => path.display().fmt(f),
+            GtfsSource::Zip(zip) => f.write_str(&zip.name),
+        }
+    }
+}
+
+impl fmt::Debug for ZipSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("ZipSource").field(&self.name).finish()
+    }
+}
+
+impl FromCsv for FeedInfo {
+    const FILENAME: &'static str = "feed_info.txt";
+    const REQUIRED: &'static [&'static str] = &["feed_publisher_name"];
+    fn parse(row: CsvRow) -> Result<Self, RowError> {
+        let date = |col| row.opt(col).map(|_| row.date(col)).transpose();
+        Ok(FeedInfo {
+            publisher: row.get("feed_publisher_name")?.to_owned(),
+            version: row.opt("feed_version").map(str::to_owned),
+            start: date("feed_start_date")?,
+            end: date("feed_end_date")?,
+        })
+    }
+}
+
+impl fmt::Display for FeedInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.publisher)?;
+        if let Some(v) = &self.version {
+            write!(f, " version {v}")?;
+        }
+        if let (Some(start), Some(end)) = (self.start, self.end) {
+            write!(f, " ({start} to {end})")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GtfsSource;
+    use crate::manifest::{ManifestStops, Policy};
+    use crate::msg::Date;
+    use std::{fs, io::Write as _, path::Path};
+
+    const STOPS: &str = "stop_id,stop_name,stop_lat,stop_lon,parent_station\n101,Van Cortlandt Park-242 St,40.889248,-73.898583,\n";
+    const FEED_INFO: &str = "feed_publisher_name,feed_version,feed_start_date,feed_end_date\nMTA New York City Transit,2024-07-01,20240701,20241231\n";
+
+    fn zip(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+        for (name, text) in files {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default()).unwrap();
+            zip.write_all(text.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    fn stops(src: &GtfsSource) -> usize {
+        ManifestStops::try_from_source(src, Policy::FailFast).unwrap().0.len()
+    }
+
+    #[test]
+    fn zip_files() {
+        let dir = std::env::temp_dir().join(format!("subpar-source-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, bytes: &[u8]| fs::write(dir.join(name), bytes).unwrap();
+        write("20240601.zip", &zip(&[("stops.txt", STOPS)]));
+        write("20240701.zip", &zip(&[("stops.txt", STOPS), ("feed_info.txt", FEED_INFO)]));
+        write("notes.txt", b"not a zip");
+
+        let src = GtfsSource::detect(&dir).unwrap();
+        assert!(src.to_string().ends_with("20240701.zip"), "the newest zip: {src}");
+        assert!(src.contains("stops.txt") && !src.contains("trips.txt"));
+        assert_eq!((stops(&src), stops(&src)), (1, 1), "entries can be read more than once");
+        let info = src.feed_info().unwrap();
+        assert_eq!(info.version.as_deref(), Some("2024-07-01"));
+        assert_eq!((info.start, info.end), (Some(Date::make(2024, 7, 1)), Some(Date::make(2024, 12, 31))));
+        assert!(src.describe("stops.txt").ends_with("20240701.zip:stops.txt"));
+
+        let older = GtfsSource::detect(dir.join("20240601.zip")).unwrap();
+        assert!(older.feed_info().is_none());
+        assert!(GtfsSource::zip(dir.join("notes.txt")).is_err());
+        assert!(GtfsSource::detect(Path::new("/nonexistent")).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn in_memory() {
+        let src = GtfsSource::bytes(zip(&[("stops.txt", STOPS), ("feed_info.txt", FEED_INFO)])).unwrap();
+        assert_eq!(stops(&src), 1);
+        assert_eq!(src.feed_info().unwrap().publisher, "MTA New York City Transit");
+        assert!(src.open("trips.txt").is_err());
+        assert!(GtfsSource::bytes(b"not a zip".to_vec()).is_err());
+    }
+}
@@ -1,30 +1,62 @@
-use std::{ops, fmt, collections::HashMap};
-use crate::msg::StopId;
-use super::csv::{FileIter, FromCsv, CsvIter};
+use std::{ops, fmt, collections::{HashMap, HashSet}};
+use crate::msg::{StopId, Stop, StationId, PlatformId, InvalidStop};
+use super::{csv::{FromCsv, CsvRow}, report::{try_load_with, try_load_file_with, Policy, LoadReport, RowError}, GtfsSource};
 
 pub struct ManifestStops {
     stops: HashMap<StopId, StopRow>,
+    platforms: HashMap<StationId, Vec<PlatformId>>,
 }
 
 #[derive(Debug)]
 pub struct StopRow {
     pub stop: StopId,
     pub name: String,
-    pub parent: Option<StopId>,
+    pub parent: Option<StationId>,
     pub location: (f64, f64),
+    /// 1 accessible, 2 not, else unknown
+    pub wheelchair_boarding: Option<u8>,
 }
 
 impl ManifestStops {
     pub fn from_file(path: &str) -> Self {
+        match Self::try_from_file(path, Policy::FailFast) {
+            Ok((stops, _)) => stops,
+            Err(report) => panic!("{report}"),
+        }
+    }
+    pub fn from_source(src: &GtfsSource) -> Self {
+        match Self::try_from_source(src, Policy::FailFast) {
+            Ok((stops, _)) => stops,
+            Err(report) => panic!("{report}"),
+        }
+    }
+    pub fn try_from_file(path: &str, policy: Policy) -> Result<(Self, LoadReport), LoadReport> {
+        let mut seen = HashSet::new();
+        let loaded = try_load_file_with(path, policy, |row| Self::check(&mut seen, row))?;
+        Ok(Self::build(loaded))
+    }
+    pub fn try_from_source(src: &GtfsSource, policy: Policy) -> Result<(Self, LoadReport), LoadReport> {
+        let mut seen = HashSet::new();
+        let loaded = try_load_with(src, policy, |row| Self::check(&mut seen, row))?;
+        Ok(Self::build(loaded))
+    }
+    fn check(seen: &mut HashSet<StopId>, row: &StopRow) -> Result<(), String> {
+        match seen.insert(row.stop) {
+            true => Ok(()),
+            false => Err(format!("duplicate stop_id {}", row.stop)),
+        }
+    }
+    fn build((rows, report): (Vec<StopRow>, LoadReport)) -> (Self, LoadReport) {
         let mut stops = HashMap::<StopId, StopRow>::new();
-        for row in FileIter::<StopRow>::new(path) {
+        let mut platforms = HashMap::<StationId, Vec<PlatformId>>::new();
+        for row in rows {
             tracing::debug!("parsed {row}");
-            if let Some(dupe) = stops.get(&row.stop) {
-                panic!("Duplicate Row: {dupe:?} vs {row:?}");
+            if let Ok(Stop::Platform(p)) = row.stop.kind() {
+                platforms.entry(p.station).or_default().push(p);
             }
             stops.insert(row.stop, row);
         }
-        ManifestStops { stops }
+        (ManifestStops { stops, platforms }, report)
     }
     pub fn len(&self) -> usize {
         self.stops.len()
@@ -32,6 +64,28 @@ impl ManifestStops {
     pub fn iter(&self) -> impl Iterator<Item = &StopRow> {
         self.stops.values()
     }
+    pub fn station(&self, id: &StationId) -> Option<&StopRow> {
+        self.stops.get(&id.stop_id())
+    }
+    /// Platforms whose parent_station is `id`
+    pub fn platforms(&self, id: &StationId) -> &[PlatformId] {
+        self.platforms.get(id).map(Vec::as_slice).unwrap_or_default()
+    }
+}
+
+impl TryFrom<&StopRow> for Stop {
+    type Error = InvalidStop;
+    /// Checks the row's id against its parent_station
+    fn try_from(row: &StopRow) -> Result<Stop, InvalidStop> {
+        let err = |reason| Err(InvalidStop::new(row.stop.as_ref(), reason));
+        match (row.stop.kind()?, &row.parent) {
+            (s @ Stop::Station(_), None) => Ok(s),
+            (Stop::Station(_), Some(_)) => err("station has a parent_station"),
+            (p @ Stop::Platform(_), Some(parent)) if &p.station() == parent => Ok(p),
+            (Stop::Platform(_), Some(_)) => err("platform doesn't match its parent_station"),
+            (Stop::Platform(_), None) => err("platform has no parent_station"),
+        }
+    }
 }
 
 impl ops::Index<StopId> for ManifestStops {
@@ -46,7 +100,7 @@ impl fmt::Display for StopRow {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let (lat, lon) = self.location;
         write!(f, "{} \"{}\" @ ({}, {})", self.stop, self.name, lat, lon)?;
-        if let Some(x) = self.parent {
+        if let Some(x) = &self.parent {
             write!(f, " ({x})")?;
         }
         Ok(())
@@ -55,32 +109,23 @@ impl fmt::Display for StopRow {
 
 
 impl FromCsv for StopRow {
-    const HEADER: &'static str = concat!(
-        "stop_id,stop_code,stop_name,stop_desc,",
-        "stop_lat,stop_lon,zone_id,",
-        "stop_url,location_type,parent_station"
-    );
     const FILENAME: &'static str = "stops.txt";
-    fn parse(mut row: CsvIter) -> Self {
-        let [stop_id, _, stop_name, _] = row.next_n();
-        let location: (f64, f64) = (row.next_as(), row.next_as());
-        let [_, _, _, parent] = row.next_n();
-        let stop: StopId = stop_id
-            .parse()
-            .unwrap_or_else(|e| panic!("Bad stop_id: {e:?}"));
-        let parent = if parent == "" {
-            None
-        } else {
-            let res: Result<StopId, _> = parent.parse();
-            Some(res.unwrap_or_else(|e| panic!("Bad parent stop: {e:?}")))
-        };
-        row.finish();
-        let name = stop_name.to_owned();
-        StopRow {
-            stop,
-            name,
-            location,
-            parent,
-        }
+    const REQUIRED: &'static [&'static str] = &["stop_id", "stop_name", "stop_lat", "stop_lon"];
+    fn parse(row: CsvRow) -> Result<Self, RowError> {
+        let location: (f64, f64) = (row.get_as("stop_lat")?, row.get_as("stop_lon")?);
+        let stop: StopId = row.get_as("stop_id")?;
+        let parent = row.opt("parent_station")
+            .map(|parent| parent.parse::<StationId>())
+            .transpose()
+            .map_err(|e| row.error("parent_station", e.to_string()))?;
+        let name = row.get("stop_name")?.to_owned();
+        let wheelchair_boarding = row.opt_as("wheelchair_boarding")?;
+        let stop_row = StopRow { stop, name, location, parent, wheelchair_boarding };
+        Stop::try_from(&stop_row).map_err(|e| row.error("stop_id", e.to_string()))?;
+        Ok(stop_row)
+    }
+    /// Entrances and nodes are `StopNode`s, for pathways
+    fn skip(row: &CsvRow) -> bool {
+        row.opt_as::<u8>("location_type").ok().flatten().is_some_and(|t| t >= 2)
     }
 }
@@ -0,0 +1,44 @@
+use crate::msg::StopId;
+use super::{csv::{FromCsv, CsvRow}, RowError};
+
+#[derive(Debug, Clone)]
+pub struct TransferRow {
+    pub from: StopId,
+    pub to: StopId,
+    pub kind: u8,
+    /// Seconds
+    pub min_time: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ShapePoint {
+    pub shape_id: String,
+    pub seq: u32,
+    pub location: (f64, f64),
+}
+
+impl FromCsv for TransferRow {
+    const FILENAME: &'static str = "transfers.txt";
+    const REQUIRED: &'static [&'static str] = &["from_stop_id", "to_stop_id", "transfer_type"];
+    fn parse(row: CsvRow) -> Result<Self, RowError> {
+        Ok(TransferRow {
+            from: StopId::make(row.get("from_stop_id")?),
+            to: StopId::make(row.get("to_stop_id")?),
+            kind: row.get_as("transfer_type")?,
+            min_time: row.opt_as("min_transfer_time")?,
+        })
+    }
+}
+
+impl FromCsv for ShapePoint {
+    const FILENAME: &'static str = "shapes.txt";
+    const REQUIRED: &'static [&'static str] =
+        &["shape_id", "shape_pt_sequence", "shape_pt_lat", "shape_pt_lon"];
+    fn parse(row: CsvRow) -> Result<Self, RowError> {
+        Ok(ShapePoint {
+            shape_id: row.get("shape_id")?.to_owned(),
+            seq: row.get_as("shape_pt_sequence")?,
+            location: (row.get_as("shape_pt_lat")?, row.get_as("shape_pt_lon")?),
+        })
+    }
+}
@@ -0,0 +1,64 @@
+use crate::msg::{Route, StopId, Time, TripDir, TripParts};
+use super::{csv::{FromCsv, CsvRow}, RowError};
+
+#[derive(Debug, Clone)]
+pub struct TripRow {
+    pub route: Route,
+    /// The full static id, e.g. 'AFA23GEN-1038-Sunday-00_000600_1..S03R'
+    pub trip_id: String,
+    pub service_id: String,
+    pub headsign: String,
+    pub dir: TripDir,
+    pub shape_id: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct StopTimeRow {
+    pub trip_id: String,
+    pub stop: StopId,
+    pub arrival: Time,
+    pub departure: Time,
+    pub seq: u32,
+}
+
+impl TripRow {
+    /// The part shared with realtime trip ids, if it parses
+    pub fn parts(&self) -> Option<TripParts> {
+        TripParts::from_static_id(&self.trip_id).ok()
+    }
+}
+
+impl FromCsv for TripRow {
+    const FILENAME: &'static str = "trips.txt";
+    const REQUIRED: &'static [&'static str] = &["route_id", "trip_id", "service_id", "direction_id"];
+    fn parse(row: CsvRow) -> Result<Self, RowError> {
+        let dir = match row.get("direction_id")? {
+            "0" => TripDir::North,
+            "1" => TripDir::South,
+            x => return Err(row.error("direction_id", format!("expected 0 or 1, not '{x}'"))),
+        };
+        Ok(TripRow {
+            route: Route::make(row.get("route_id")?),
+            trip_id: row.get("trip_id")?.to_owned(),
+            service_id: row.get("service_id")?.to_owned(),
+            headsign: row.opt("trip_headsign").unwrap_or_default().to_owned(),
+            dir,
+            shape_id: row.opt("shape_id").map(str::to_owned),
+        })
+    }
+}
+
+impl FromCsv for StopTimeRow {
+    const FILENAME: &'static str = "stop_times.txt";
+    const REQUIRED: &'static [&'static str] =
+        &["trip_id", "stop_id", "arrival_time", "departure_time", "stop_sequence"];
+    fn parse(row: CsvRow) -> Result<Self, RowError> {
+        Ok(StopTimeRow {
+            trip_id: row.get("trip_id")?.to_owned(),
+            stop: StopId::make(row.get("stop_id")?),
+            arrival: Time::from_gtfs(row.time("arrival_time")?),
+            departure: Time::from_gtfs(row.time("departure_time")?),
+            seq: row.get_as("stop_sequence")?,
+        })
+    }
+}
@@ -2,7 +2,7 @@ use crate::Timestamp;
 use anyhow::Context as _;
 use std::{convert::TryInto as _, fmt, ops, str};
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Date(chrono::NaiveDate);
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
@@ -23,6 +23,15 @@ impl Date {
     pub fn to_naive(self) -> chrono::NaiveDate {
         self.0
     }
+    pub fn from_yyyymmdd(s: &str) -> anyhow::Result<Self> {
+        chrono::NaiveDate::parse_from_str(s, "%Y%m%d")
+            .map(Date)
+            .with_context(|| format!("Malformed yyyymmdd `{s}`"))
+    }
+    pub fn weekday(self) -> chrono::Weekday {
+        use chrono::Datelike;
+        self.0.weekday()
+    }
 }
 
 impl Time {
@@ -32,6 +41,10 @@ impl Time {
     pub fn new_with_offset(h: u8, m: u8, s: u8, offset: i8) -> Self {
         Time { h, m, s, offset }
     }
+    /// GTFS times are measured from noon minus 12h and can exceed 24:00:00
+    pub fn from_gtfs((h, m, s): (u8, u8, u8)) -> Self {
+        Time::new_with_offset(h % 24, m, s, (h / 24) as i8)
+    }
     pub fn from_trip_origin(s: &str) -> anyhow::Result<Self> {
         // 'hundredths of a minute past midnight'
         // can be negative or > 24*60*100
@@ -15,6 +15,9 @@ pub use datetime::{Date, Time};
 mod trip;
 pub use trip::{TripId, TripParts, TripDir};
 
+mod stop;
+pub use stop::{Stop, StationId, PlatformId, InvalidStop};
+
 #[derive(Debug, Clone)]
 pub enum Update {
     Alert,
@@ -24,18 +27,19 @@ pub enum Update {
 
 newt! {
     /// Route letter, excluding local/express-ness.
-    /// Only shuttles use more than 1 character.
-    /// e.g. '6' or 'SIR'.
+    /// Only shuttles and commuter rail use more than 1 character.
+    /// e.g. '6', 'SIR', or 'LIRR'.
     #[derive(serde::Serialize, serde::Deserialize)]
     #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
-    pub struct Route[3];
+    pub struct Route[4];
 }
 
 newt! {
-    /// A Station (parent) or Platform (child) e.g. 101 or 101N
+    /// A Station (parent) or Platform (child) e.g. 101 or 101N, or LI:237N on commuter rail.
+    /// Unvalidated; see `Stop` for telling them apart.
     #[derive(serde::Serialize, serde::Deserialize)]
     #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-    pub struct StopId[4];
+    pub struct StopId[8];
 }
 
 newt! {
@@ -44,19 +48,3 @@ newt! {
     #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
     pub struct TripIdStr[20];
 }
-
-impl StopId {
-    pub fn is_parent(&self) -> bool {
-        let Some(tail) = self.0.as_str().chars().rev().next() else { return false };
-        tail == 'N' || tail == 'S'
-    }
-    pub fn parent(&self) -> Self {
-        if self.is_parent() {
-            let mut parent = self.clone();
-            parent.0.pop().unwrap();
-            parent
-        } else {
-            self.clone()
-        }
-    }
-}
@@ -3,7 +3,8 @@ use anyhow::anyhow;
 use crate::{Timestamp, msg::{StopId, TripId, }};
 use std::fmt;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum PositionStatus {
     Nothing,
     At,
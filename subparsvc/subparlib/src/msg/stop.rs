@@ -0,0 +1,225 @@
+use super::{StopId, TripDir};
+use crate::routes::Agency;
+use serde::{Serialize, Deserialize};
+use std::{error::Error as StdError, fmt, str};
+
+/// A parent station, e.g. 101 or A27, or LI:237 on commuter rail
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "StopId", into = "StopId")]
+pub struct StationId(StopId);
+
+/// The platform(s) serving one direction at a station, e.g. 101N
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "StopId", into = "StopId")]
+pub struct PlatformId {
+    pub station: StationId,
+    pub dir: TripDir,
+}
+
+/// A validated `StopId`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stop {
+    Station(StationId),
+    Platform(PlatformId),
+}
+
+#[derive(Debug, Clone)]
+pub struct InvalidStop {
+    id: String,
+    reason: &'static str,
+}
+
+impl StationId {
+    pub fn stop_id(&self) -> StopId {
+        self.0
+    }
+    pub fn platform(&self, dir: TripDir) -> PlatformId {
+        PlatformId { station: *self, dir }
+    }
+    /// Both directions' platforms
+    pub fn platforms(&self) -> [PlatformId; 2] {
+        [self.platform(TripDir::North), self.platform(TripDir::South)]
+    }
+    /// A commuter rail feed's bare id, e.g. 237 => LI:237
+    pub fn namespaced(agency: Agency, id: &str) -> Result<Self, InvalidStop> {
+        agency.namespace(id).parse()
+    }
+    pub fn agency(&self) -> Agency {
+        self.0.as_ref().split_once(':').and_then(|(p, _)| Agency::from_prefix(p)).unwrap_or(Agency::Nyct)
+    }
+    fn validate(s: &str) -> Result<(), &'static str> {
+        let s = match s.split_once(':') {
+            Some((prefix, id)) if Agency::from_prefix(prefix).is_some() => id,
+            Some(_) => return Err("unknown agency prefix"),
+            None => s,
+        };
+        let Some(last) = s.chars().last() else { return Err("empty") };
+        if !s.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit()) {
+            Err("expected uppercase alphanumerics")
+        } else if !last.is_ascii_digit() {
+            Err("station should end in a digit")
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl PlatformId {
+    pub fn stop_id(&self) -> StopId {
+        StopId::make(&format!("{}{}", self.station, self.dir))
+    }
+}
+
+impl Stop {
+    pub fn station(&self) -> StationId {
+        match self {
+            Stop::Station(s) => *s,
+            Stop::Platform(p) => p.station,
+        }
+    }
+    pub fn platform(self) -> Option<PlatformId> {
+        match self {
+            Stop::Station(_) => None,
+            Stop::Platform(p) => Some(p),
+        }
+    }
+}
+
+impl StopId {
+    pub fn kind(&self) -> Result<Stop, InvalidStop> {
+        self.as_ref().parse()
+    }
+    /// The parent station of a station or platform
+    pub fn station(&self) -> Result<StationId, InvalidStop> {
+        self.kind().map(|s| s.station())
+    }
+}
+
+impl str::FromStr for Stop {
+    type Err = InvalidStop;
+    fn from_str(s: &str) -> Result<Self, InvalidStop> {
+        let dir = match s.chars().last() {
+            Some('N') => Some(TripDir::North),
+            Some('S') => Some(TripDir::South),
+            _ => None,
+        };
+        match dir {
+            Some(dir) => {
+                let station = s[..s.len() - 1].parse()?;
+                Ok(Stop::Platform(PlatformId { station, dir }))
+            },
+            None => s.parse().map(Stop::Station),
+        }
+    }
+}
+
+impl str::FromStr for StationId {
+    type Err = InvalidStop;
+    fn from_str(s: &str) -> Result<Self, InvalidStop> {
+        Self::validate(s).map_err(|reason| InvalidStop::new(s, reason))?;
+        Ok(StationId(StopId::make(s)))
+    }
+}
+
+impl str::FromStr for PlatformId {
+    type Err = InvalidStop;
+    fn from_str(s: &str) -> Result<Self, InvalidStop> {
+        s.parse::<Stop>()?.platform().ok_or_else(|| InvalidStop::new(s, "not a platform"))
+    }
+}
+
+impl TryFrom<StopId> for StationId {
+    type Error = InvalidStop;
+    fn try_from(id: StopId) -> Result<Self, InvalidStop> {
+        id.as_ref().parse()
+    }
+}
+
+impl TryFrom<StopId> for PlatformId {
+    type Error = InvalidStop;
+    fn try_from(id: StopId) -> Result<Self, InvalidStop> {
+        id.as_ref().parse()
+    }
+}
+
+impl From<StationId> for StopId {
+    fn from(s: StationId) -> StopId {
+        s.0
+    }
+}
+
+impl From<PlatformId> for StopId {
+    fn from(p: PlatformId) -> StopId {
+        p.stop_id()
+    }
+}
+
+impl AsRef<str> for StationId {
+    fn as_ref(&self) -> &str {
+        self.0.as_ref()
+    }
+}
+
+impl fmt::Display for StationId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl fmt::Display for PlatformId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.station, self.dir)
+    }
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stop::Station(s) => s.fmt(f),
+            Stop::Platform(p) => p.fmt(f),
+        }
+    }
+}
+
+// impl InvalidStop
+
+impl InvalidStop {
+    pub fn new(id: &str, reason: &'static str) -> Self {
+        InvalidStop { id: id.to_string(), reason }
+    }
+}
+
+impl StdError for InvalidStop {}
+
+impl fmt::Display for InvalidStop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid stop id '{}': {}", self.id, self.reason)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Stop, StationId, PlatformId, TripDir};
+    use crate::routes::Agency;
+
+    #[test]
+    fn parse_stops() {
+        let p = str::parse::<Stop>;
+        let station: StationId = "A27".parse().unwrap();
+        assert_eq!(p("A27").unwrap(), Stop::Station(station));
+        assert_eq!(p("A27S").unwrap(), Stop::Platform(PlatformId { station, dir: TripDir::South }));
+        assert_eq!(p("101N").unwrap().station().as_ref(), "101");
+        assert!(p("").is_err());
+        assert!(p("N").is_err());
+        assert!(p("A27X").is_err());
+        assert!(p("101NS").is_err());
+        assert!("101N".parse::<StationId>().is_err());
+        assert!("101".parse::<PlatformId>().is_err());
+        let jamaica = StationId::namespaced(Agency::Lirr, "102").unwrap();
+        assert_eq!((jamaica.as_ref(), jamaica.agency()), ("LI:102", Agency::Lirr));
+        assert!(StationId::namespaced(Agency::Lirr, "237").unwrap().stop_id().is_inline());
+        assert!(jamaica.platform(TripDir::North).stop_id().is_inline());
+        assert_eq!(p("MN:1").unwrap().station().agency(), Agency::Mnr);
+        assert!(p("XX:1").is_err());
+    }
+}
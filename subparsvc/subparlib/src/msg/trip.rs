@@ -1,6 +1,6 @@
 // use super::types as t;
 use super::{Date, Time, TripIdStr, Route};
-use crate::Timestamp;
+use crate::{Timestamp, Locale, routes::Agency};
 use anyhow::{anyhow, Context as _};
 use std::{fmt, str};
 
@@ -19,7 +19,7 @@ pub struct TripParts {
     pub time: Time,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
 pub enum TripDir {
     North, // also East
     South,
@@ -55,12 +55,30 @@ impl str::FromStr for TripParts {
     }
 }
 
+impl TripParts {
+    /// Static GTFS trip ids prefix the realtime trip id with service info,
+    /// e.g. 'AFA23GEN-1038-Sunday-00_000600_1..S03R'
+    pub fn from_static_id(s: &str) -> anyhow::Result<Self> {
+        let tail = match s.rmatch_indices('_').nth(1) {
+            Some((i, _)) => &s[i + 1..],
+            None => s,
+        };
+        tail.parse()
+    }
+}
+
 impl TripId {
     pub fn parse(s: &str, day: Date) -> anyhow::Result<Self> {
         let data = s.parse().with_context(|| format!("tokenize {s}"))?;
         let text = s.parse().with_context(|| format!("copy trip_id {s}"))?;
         Ok(TripId { text, day, data })
     }
+    /// Commuter rail trip ids don't encode anything, so the parts come from elsewhere in the feed
+    pub fn commuter(agency: Agency, id: &str, day: Date, dir: TripDir, time: Time) -> anyhow::Result<Self> {
+        let rt = agency.route().ok_or_else(|| anyhow!("{agency:?} isn't commuter rail"))?.route();
+        let text = agency.namespace(id).parse().with_context(|| format!("copy trip_id {id}"))?;
+        Ok(TripId { text, day, data: TripParts { rt, dir, time } })
+    }
     pub fn name(&self) -> TripIdStr {
         self.text
     }
@@ -74,10 +92,10 @@ impl TripId {
         self.data.clone()
     }
     pub fn route(&self) -> Route {
-        self.data.rt.clone()
+        self.data.rt
     }
     pub fn dir(&self) -> TripDir {
-        self.data.dir.clone()
+        self.data.dir
     }
     pub fn date(&self) -> chrono::NaiveDate {
         self.day.to_naive()
@@ -120,6 +138,24 @@ impl fmt::Display for TripId {
     }
 }
 
+impl TripDir {
+    /// Which way a train is headed, e.g. "northbound". Commuter rail's North is outbound, away
+    /// from Penn Station and Grand Central, and South inbound (see `proto::parse1`)
+    pub fn word(self, agency: Agency, locale: Locale) -> &'static str {
+        let commuter = agency != Agency::Nyct;
+        match (locale, commuter, self) {
+            (Locale::En, false, TripDir::North) => "northbound",
+            (Locale::En, false, TripDir::South) => "southbound",
+            (Locale::En, true, TripDir::North) => "outbound",
+            (Locale::En, true, TripDir::South) => "inbound",
+            (Locale::Es, false, TripDir::North) => "hacia el norte",
+            (Locale::Es, false, TripDir::South) => "hacia el sur",
+            (Locale::Es, true, TripDir::North) => "de salida",
+            (Locale::Es, true, TripDir::South) => "de entrada",
+        }
+    }
+}
+
 impl fmt::Display for TripDir {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -135,7 +171,8 @@ impl fmt::Display for TripDir {
 
 #[cfg(test)]
 mod tests {
-    use super::{Time, TripDir, TripParts};
+    use super::{Time, TripDir, TripId, TripParts};
+    use crate::{msg::{Date, Route}, routes::Agency};
 
     #[test]
     fn tokenize_trip_parts() {
@@ -156,5 +193,17 @@ mod tests {
         assert_eq!(p("134200_L..N").unwrap(), tp("L", 'N', 134200));
         assert_eq!(p("134200_GS.S").unwrap(), tp("GS", 'S', 134200));
         assert_eq!(p("101200_GS.S04R").unwrap(), tp("GS", 'S', 101200));
+        let p = TripParts::from_static_id;
+        assert_eq!(p("AFA23GEN-1038-Sunday-00_000600_1..S03R").unwrap(), tp("1", 'S', 600));
+        assert_eq!(p("000600_1..S03R").unwrap(), tp("1", 'S', 600));
+    }
+
+    #[test]
+    fn commuter() {
+        let day = Date::make(2024, 7, 8);
+        let id = TripId::commuter(Agency::Mnr, "1234", day, TripDir::South, Time::new(8, 30, 0)).unwrap();
+        assert_eq!((id.as_str(), id.route(), id.dir()), ("MN:1234", Route::make("MNR"), TripDir::South));
+        assert_eq!(id.departed(), day + Time::new(8, 30, 0));
+        assert!(TripId::commuter(Agency::Nyct, "1234", day, TripDir::South, Time::new(8, 30, 0)).is_err());
     }
 }
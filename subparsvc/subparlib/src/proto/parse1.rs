@@ -1,10 +1,14 @@
 use super::gtfs_realtime;
 use anyhow::{anyhow, Context as _};
-use crate::{msg, Timestamp};
+use crate::{msg, Timestamp, routes::Agency};
 
 pub trait FromGtfs: Sized {
     type In;
-    fn parse(g: &Self::In) -> anyhow::Result<Self>;
+    fn parse(g: &Self::In) -> anyhow::Result<Self> {
+        Self::parse_for(g, Agency::Nyct)
+    }
+    /// Commuter rail ids are namespaced by `agency`
+    fn parse_for(g: &Self::In, agency: Agency) -> anyhow::Result<Self>;
     // fn check(x: Self::In) -> anyhow::Result< () > { Ok( () ) }
 }
 
@@ -34,23 +38,23 @@ macro_rules! pbget {
 
 impl FromGtfs for msg::Batch {
     type In = gtfs_realtime::FeedMessage;
-    fn parse(g: &Self::In) -> anyhow::Result<Self> {
+    fn parse_for(g: &Self::In, agency: Agency) -> anyhow::Result<Self> {
         let head = pbget!( g.has_header() => g.get_header(), "{g:?}" );
         let time = pbget!( head.has_timestamp() => head.get_timestamp() );
         let time = Timestamp::from_unix(time.try_into().unwrap());
-        let msgs = g.get_entity().iter().map(Update::parse).collect();
+        let msgs = g.get_entity().iter().map(|e| Update::parse_for(e, agency)).collect();
         Ok(msg::Batch { time, msgs })
     }
 }
 
 impl FromGtfs for Update {
     type In = gtfs_realtime::FeedEntity;
-    fn parse(g: &Self::In) -> anyhow::Result<Self> {
+    fn parse_for(g: &Self::In, agency: Agency) -> anyhow::Result<Self> {
         const T: bool = true;
         const F: bool = false;
         match [g.has_trip_update(), g.has_vehicle(), g.has_alert()] {
-            [T, F, F] => Ok(Update::Schedule(Schedule::parse(g.get_trip_update())?)),
-            [F, T, F] => Ok(Update::Position(Position::parse(g.get_vehicle())?)),
+            [T, F, F] => Ok(Update::Schedule(Schedule::parse_for(g.get_trip_update(), agency)?)),
+            [F, T, F] => Ok(Update::Position(Position::parse_for(g.get_vehicle(), agency)?)),
             [F, F, T] => Ok(Update::Alert),
             [F, F, F] => Err(anyhow!("FeedEntity unrecognized")),
             [t, v, a] => Err(anyhow!("FeedEntity multiple: trip={t} pos={v} alrt={a}")),
@@ -74,21 +78,15 @@ impl FromGtfs for Update {
 
 impl FromGtfs for Position {
     type In = gtfs_realtime::VehiclePosition;
-    fn parse(g: &Self::In) -> anyhow::Result<Self> {
+    fn parse_for(g: &Self::In, agency: Agency) -> anyhow::Result<Self> {
         let trip = pbget!( g.has_trip() => g.get_trip() );
-        let id = pbget!( trip.has_trip_id() => trip.get_trip_id() );
-        let start = {
-            let s = pbget!( trip.has_start_date() => trip.get_start_date() );
-            let t = Timestamp::from_yyyymmdd(s)?;
-            t.date()
-        };
-        let trip = msg::TripId::parse(id, start)?;
+        let trip = trip_id(trip, agency)?;
         // let stop_n = common::StopN::from(pbget!( g.has_current_stop_sequence() => g.get_current_stop_sequence()));
         let stop_n = match (g.has_current_stop_sequence(), g.get_current_stop_sequence()) {
             (false, _) => None,
             (true, n) => Some(n),
         };
-        let stop = pbget!( g.has_stop_id() => g.get_stop_id()).parse()?;
+        let stop = stop_id(pbget!( g.has_stop_id() => g.get_stop_id()), agency);
         let time = Timestamp::from_unix(pbget!( g.has_timestamp() => g.get_timestamp().try_into().unwrap() ));
         use gtfs_realtime::VehiclePosition_VehicleStopStatus as SS;
         let status = match (g.has_current_status(), g.get_current_status()) {
@@ -101,6 +99,43 @@ impl FromGtfs for Position {
     }
 }
 
+fn trip_id(trip: &gtfs_realtime::TripDescriptor, agency: Agency) -> anyhow::Result<msg::TripId> {
+    let id = pbget!( trip.has_trip_id() => trip.get_trip_id() );
+    let start = {
+        let s = pbget!( trip.has_start_date() => trip.get_start_date() );
+        let t = Timestamp::from_yyyymmdd(s)?;
+        t.date()
+    };
+    if agency == Agency::Nyct {
+        return msg::TripId::parse(id, start);
+    }
+    // GTFS leaves direction_id's meaning to each agency. In LIRR's and Metro-North's static trips.txt,
+    // direction 0 trips run away from Penn Station and Grand Central, e.g. to Ronkonkoma or Poughkeepsie,
+    // and direction 1 toward them. Outbound is what we call north (or east)
+    let dir = match pbget!( trip.has_direction_id() => trip.get_direction_id(), "trip {id}" ) {
+        0 => msg::TripDir::North,
+        _ => msg::TripDir::South,
+    };
+    let time = match trip.has_start_time() {
+        true => start_time(trip.get_start_time()).with_context(|| format!("trip {id}"))?,
+        false => msg::Time::new(0, 0, 0),
+    };
+    msg::TripId::commuter(agency, id, start, dir, time)
+}
+
+/// HH:MM:SS, where HH can pass 24
+fn start_time(s: &str) -> anyhow::Result<msg::Time> {
+    let mut hms = s.split(':').map(str::parse::<u8>);
+    match (hms.next(), hms.next(), hms.next(), hms.next()) {
+        (Some(Ok(h)), Some(Ok(m)), Some(Ok(s)), None) => Ok(msg::Time::from_gtfs((h, m, s))),
+        _ => Err(anyhow!("bad start_time '{s}'")),
+    }
+}
+
+fn stop_id(id: &str, agency: Agency) -> msg::StopId {
+    msg::StopId::make(&agency.namespace(id))
+}
+
 fn opt<T, F, R>(cond: bool, val: T, func: F) -> anyhow::Result<Option<R>>
 where
     F: FnOnce(T) -> anyhow::Result<R>,
@@ -118,22 +153,16 @@ fn make_time(g: &gtfs_realtime::TripUpdate_StopTimeEvent) -> anyhow::Result<Time
 
 impl FromGtfs for Schedule {
     type In = gtfs_realtime::TripUpdate;
-    fn parse(g: &Self::In) -> anyhow::Result<Self> {
+    fn parse_for(g: &Self::In, agency: Agency) -> anyhow::Result<Self> {
         let trip = pbget!( g.has_trip() => g.get_trip() );
         // assert!(g.has_timestamp() == false);
         let time = Timestamp::epoch();
-        let id = pbget!( trip.has_trip_id() => trip.get_trip_id() );
-        let start = {
-            let s = pbget!( trip.has_start_date() => trip.get_start_date() );
-            let t = Timestamp::from_yyyymmdd(s)?;
-            t.date()
-        };
-        let trip_id = msg::TripId::parse(id, start)?;
+        let trip_id = trip_id(trip, agency)?;
         let upds = g
             .get_stop_time_update()
             .iter()
             .map(|x| {
-                let id = pbget!( x.has_stop_id() => x.get_stop_id() ).parse()?;
+                let id = stop_id(pbget!( x.has_stop_id() => x.get_stop_id() ), agency);
                 // if x.has_stop_sequence() { log::info!("Found a stop update w/ stop_n: {x:?}") }
                 let arr = opt(x.has_arrival(), x.get_arrival(), make_time)?;
                 let dep = opt(x.has_departure(), x.get_departure(), make_time)?;
@@ -158,7 +187,32 @@ impl FromGtfs for Schedule {
 
 #[cfg(test)]
 mod tests {
-    
+    use super::{gtfs_realtime, start_time, trip_id};
+    use crate::{msg::{Route, Time, TripDir}, routes::Agency};
+
+    #[test]
+    fn start_times() {
+        assert_eq!(start_time("07:05:30").unwrap(), Time::new(7, 5, 30));
+        assert_eq!(start_time("25:00:00").unwrap(), Time::new_with_offset(1, 0, 0, 1));
+        assert!(start_time("7:05").is_err());
+    }
+
+    #[test]
+    fn commuter_trips() {
+        let mut trip = gtfs_realtime::TripDescriptor::new();
+        trip.set_trip_id("2034".into());
+        trip.set_start_date("20240708".into());
+        trip.set_start_time("25:10:00".into());
+        trip.set_direction_id(0);
+        let id = trip_id(&trip, Agency::Lirr).unwrap();
+        assert_eq!(id.as_str(), "LI:2034");
+        assert_eq!((id.route(), id.dir()), (Route::make("LIRR"), TripDir::North), "outbound");
+        trip.set_direction_id(1);
+        assert_eq!(trip_id(&trip, Agency::Mnr).unwrap().dir(), TripDir::South, "inbound");
+        trip.clear_direction_id();
+        assert!(trip_id(&trip, Agency::Lirr).is_err());
+    }
+
     #[test]
     fn getter_a() -> anyhow::Result<()> {
         let a = pbget!( 42 > 10, true != false, 4 == 4, true
@@ -0,0 +1,237 @@
+//! Static knowledge about each route: display info, variants, and which feed carries it.
+//! This is the one place to add a route or fix a color.
+
+use crate::{Feed, msg::Route};
+use serde::Serialize;
+use std::{collections::{HashMap, HashSet}, sync::OnceLock};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum Division {
+    A, // IRT (numbered)
+    B, // BMT & IND (lettered)
+    SIR,
+}
+
+/// Who runs the route. Commuter rail stop and trip ids are namespaced by agency, e.g. LI:237,
+/// since their feeds reuse numbers the subway already has.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Agency {
+    Nyct,
+    Lirr,
+    Mnr,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RouteInfo {
+    pub id: &'static str,
+    pub name: &'static str,
+    /// Text on the bullet; all shuttles are just 'S'
+    pub bullet: &'static str,
+    pub color: &'static str,
+    pub text_color: &'static str,
+    /// Express/diamond service, e.g. 6X
+    pub variants: &'static [&'static str],
+    /// Other names for this route in MTA datasets, e.g. SIR for SI
+    pub aliases: &'static [&'static str],
+    /// Realtime feed name as accepted by `Feed::from_static`
+    pub feed: Option<&'static str>,
+    pub division: Option<Division>,
+    pub agency: Agency,
+}
+
+const RED: &str = "#EE352E";
+const GREEN: &str = "#00933C";
+const PURPLE: &str = "#B933AD";
+const BLUE: &str = "#0039A6";
+const ORANGE: &str = "#FF6319";
+const LIME: &str = "#6CBE45";
+const BROWN: &str = "#996633";
+const GRAY: &str = "#A7A9AC";
+const YELLOW: &str = "#FCCC0A";
+const DARK_GRAY: &str = "#808183";
+const WHITE: &str = "#FFFFFF";
+const BLACK: &str = "#000000";
+const LIRR_BLUE: &str = "#0F61A9";
+const MNR_BLUE: &str = "#0039A6";
+
+macro_rules! route {
+    ( $id:literal, $name:literal, $color:expr, $text:expr, $feed:expr, $div:expr
+      $( , variants = [ $( $v:literal ),* ] )?
+      $( , aliases = [ $( $a:literal ),* ] )?
+      $( , bullet = $b:literal )?
+      $( , agency = $ag:expr )?
+    ) => {
+        RouteInfo {
+            id: $id,
+            name: $name,
+            bullet: { let _b = $id; $( let _b = $b; )? _b },
+            color: $color,
+            text_color: $text,
+            variants: &[ $( $( $v ),* )? ],
+            aliases: &[ $( $( $a ),* )? ],
+            feed: $feed,
+            division: $div,
+            agency: { let _a = Agency::Nyct; $( let _a = $ag; )? _a },
+        }
+    };
+}
+
+use Division::{A, B, SIR as SI};
+
+const NYCT: &[RouteInfo] = &[
+    route!("1", "Broadway-7 Av Local", RED, WHITE, Some("1234567"), Some(A)),
+    route!("2", "7 Av Express", RED, WHITE, Some("1234567"), Some(A)),
+    route!("3", "7 Av Express", RED, WHITE, Some("1234567"), Some(A)),
+    route!("4", "Lexington Av Express", GREEN, WHITE, Some("1234567"), Some(A)),
+    route!("5", "Lexington Av Express", GREEN, WHITE, Some("1234567"), Some(A)),
+    route!("6", "Lexington Av Local", GREEN, WHITE, Some("1234567"), Some(A), variants = ["6X"]),
+    route!("7", "Flushing Local", PURPLE, WHITE, Some("1234567"), Some(A), variants = ["7X"]),
+    route!("GS", "42 St Shuttle", DARK_GRAY, WHITE, Some("1234567"), Some(A), bullet = "S"),
+    route!("A", "8 Av Express", BLUE, WHITE, Some("ace"), Some(B)),
+    route!("C", "8 Av Local", BLUE, WHITE, Some("ace"), Some(B)),
+    route!("E", "8 Av Local", BLUE, WHITE, Some("ace"), Some(B)),
+    route!("H", "Rockaway Park Shuttle", DARK_GRAY, WHITE, Some("ace"), Some(B), bullet = "S"),
+    route!("B", "6 Av Express", ORANGE, WHITE, Some("bdfm"), Some(B)),
+    route!("D", "6 Av Express", ORANGE, WHITE, Some("bdfm"), Some(B)),
+    route!("F", "Queens Blvd Express/6 Av Local", ORANGE, WHITE, Some("bdfm"), Some(B), variants = ["FX"]),
+    route!("M", "Queens Blvd Local/6 Av Local", ORANGE, WHITE, Some("bdfm"), Some(B)),
+    route!("FS", "Franklin Av Shuttle", DARK_GRAY, WHITE, Some("bdfm"), Some(B), bullet = "S"),
+    route!("G", "Brooklyn-Queens Crosstown", LIME, WHITE, Some("g"), Some(B)),
+    route!("J", "Nassau St Local", BROWN, WHITE, Some("jz"), Some(B)),
+    route!("Z", "Nassau St Express", BROWN, WHITE, Some("jz"), Some(B)),
+    route!("L", "14 St-Canarsie Local", GRAY, WHITE, Some("l"), Some(B)),
+    route!("N", "Broadway Express", YELLOW, BLACK, Some("nqrw"), Some(B)),
+    route!("Q", "2 Av/Broadway Express", YELLOW, BLACK, Some("nqrw"), Some(B)),
+    route!("R", "Broadway Local", YELLOW, BLACK, Some("nqrw"), Some(B)),
+    route!("W", "Broadway Local", YELLOW, BLACK, Some("nqrw"), Some(B)),
+    route!("SI", "Staten Island Railway", BLUE, WHITE, Some("si"), Some(SI), aliases = ["SIR"], bullet = "SIR"),
+    // data.ny.gov lists every shuttle as plain 'S'
+    route!("S", "Shuttle", DARK_GRAY, WHITE, None, None),
+];
+
+const COMMUTER: &[RouteInfo] = &[
+    route!("LIRR", "Long Island Rail Road", LIRR_BLUE, WHITE, Some("lirr"), None,
+        aliases = ["LI"], bullet = "LIRR", agency = Agency::Lirr),
+    route!("MNR", "Metro-North Railroad", MNR_BLUE, WHITE, Some("mnr"), None,
+        aliases = ["METRO-NORTH", "MNRR"], bullet = "MNR", agency = Agency::Mnr),
+];
+
+pub struct RouteCatalog {
+    routes: &'static [RouteInfo],
+    // every id, variant, and alias => index into `routes`
+    names: HashMap<&'static str, usize>,
+}
+
+/// The catalog of every route we know about
+pub fn catalog() -> &'static RouteCatalog {
+    static CATALOG: OnceLock<RouteCatalog> = OnceLock::new();
+    static ROUTES: OnceLock<Vec<RouteInfo>> = OnceLock::new();
+    let routes = ROUTES.get_or_init(|| NYCT.iter().chain(COMMUTER).cloned().collect());
+    CATALOG.get_or_init(|| RouteCatalog::new(routes))
+}
+
+impl RouteCatalog {
+    fn new(routes: &'static [RouteInfo]) -> Self {
+        let mut names = HashMap::new();
+        for (i, r) in routes.iter().enumerate() {
+            for &name in std::iter::once(&r.id).chain(r.variants).chain(r.aliases) {
+                if let Some(j) = names.insert(name, i) {
+                    panic!("route name '{name}' claimed by {} and {}", routes[j].id, r.id);
+                }
+            }
+        }
+        RouteCatalog { routes, names }
+    }
+    /// Look up a route by id, variant, or alias (e.g. "6", "6X", or "SIR")
+    pub fn get(&self, name: &str) -> Option<&'static RouteInfo> {
+        let routes = self.routes;
+        self.names.get(name).map(|&i| &routes[i])
+    }
+    pub fn route(&self, route: &Route) -> Option<&'static RouteInfo> {
+        self.get(route.as_ref())
+    }
+    /// The route without its express variant or alias, e.g. 6X => 6
+    pub fn canonical(&self, route: &Route) -> Option<Route> {
+        self.route(route).map(RouteInfo::route)
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &'static RouteInfo> {
+        self.routes.iter()
+    }
+    /// Every distinct realtime feed, in catalog order
+    pub fn feed_names(&self) -> Vec<&'static str> {
+        let mut seen = HashSet::new();
+        self.routes.iter().filter_map(|r| r.feed).filter(|f| seen.insert(*f)).collect()
+    }
+    pub fn feeds(&self) -> Vec<Feed> {
+        self.feed_names().into_iter().map(Feed::from_static).collect()
+    }
+    pub fn feed(&self, route: &Route) -> Option<Feed> {
+        self.route(route)?.feed()
+    }
+    /// The agency whose routes a feed carries
+    pub fn feed_agency(&self, feed: &str) -> Agency {
+        self.routes.iter().find(|r| r.feed == Some(feed)).map_or(Agency::Nyct, |r| r.agency)
+    }
+}
+
+impl Agency {
+    pub const COMMUTER: [Agency; 2] = [Agency::Lirr, Agency::Mnr];
+    /// Namespace for stop and trip ids; the subway's are bare
+    pub fn prefix(self) -> Option<&'static str> {
+        match self {
+            Agency::Nyct => None,
+            Agency::Lirr => Some("LI"),
+            Agency::Mnr => Some("MN"),
+        }
+    }
+    pub fn from_prefix(s: &str) -> Option<Agency> {
+        Agency::COMMUTER.into_iter().find(|a| a.prefix() == Some(s))
+    }
+    /// e.g. 237 => LI:237
+    pub fn namespace(self, id: &str) -> String {
+        match self.prefix() {
+            Some(p) => format!("{p}:{id}"),
+            None => id.to_owned(),
+        }
+    }
+    /// Commuter agencies run as a single route each
+    pub fn route(self) -> Option<&'static RouteInfo> {
+        catalog().iter().find(|r| r.agency == self && self != Agency::Nyct)
+    }
+}
+
+impl RouteInfo {
+    pub fn route(&self) -> Route {
+        Route::make(self.id)
+    }
+    pub fn feed(&self) -> Option<Feed> {
+        self.feed.map(Feed::from_static)
+    }
+    /// Filename of the bullet image served by the site
+    pub fn bullet_img(&self) -> String {
+        format!("R{}.svg", self.bullet)
+    }
+    pub fn is_shuttle(&self) -> bool {
+        self.bullet == "S"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{catalog, Agency};
+
+    #[test]
+    fn lookup() {
+        let c = catalog();
+        assert_eq!(c.get("6X").unwrap().id, "6");
+        assert_eq!(c.get("SIR").unwrap().id, "SI");
+        assert_eq!(c.get("GS").unwrap().bullet, "S");
+        assert_eq!(c.get("METRO-NORTH").unwrap().agency, Agency::Mnr);
+        assert_eq!(c.get("6").unwrap().agency, Agency::Nyct);
+        assert_eq!(c.feed_names(), ["1234567", "ace", "bdfm", "g", "jz", "l", "nqrw", "si", "lirr", "mnr"]);
+        assert_eq!(c.feed_agency("lirr"), Agency::Lirr);
+        assert_eq!(Agency::Lirr.namespace("237"), "LI:237");
+        assert_eq!(Agency::Mnr.route().unwrap().id, "MNR");
+    }
+}
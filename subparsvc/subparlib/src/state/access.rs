@@ -0,0 +1,385 @@
+//! Step-free reachability inside a complex: street, mezzanine, and each platform, linked by elevators.
+//! Built from equipment descriptions like "Street to Brooklyn-bound platform",
+//! plus pathways.txt when the static feed has one.
+
+use crate::{api::{self, ComplexId, EquipmentId}, manifest::StaticSchedule, msg::{PlatformId, StationId, TripDir}};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use tracing::debug;
+
+// Direction words are heuristics: "Queens-bound" is north on most lines, but not the Rockaway A.
+// Platforms we can't place, like "Manhattan-bound", count as both.
+const NORTH: &[&str] = &[
+    "uptown", "northbound", "bronx-bound", "queens-bound", "astoria-bound", "flushing-bound",
+    "jamaica-bound", "forest hills-bound", "8 av-bound", "inwood-bound", "harlem-bound",
+];
+const SOUTH: &[&str] = &[
+    "downtown", "southbound", "brooklyn-bound", "coney island-bound", "canarsie-bound",
+    "rockaway-bound", "far rockaway-bound", "flatbush-bound", "new lots-bound", "bay ridge-bound",
+    "south ferry-bound", "tottenville-bound", "hudson yards-bound", "world trade center-bound",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Node {
+    Street,
+    Mezzanine,
+    Platform(PlatformId),
+    /// A pathways.txt node that's none of the above
+    Other(String),
+}
+
+#[derive(Debug, Clone)]
+struct Edge {
+    a: Node,
+    b: Node,
+    /// Closes with this equipment's outage
+    equipment: Option<EquipmentId>,
+    bidirectional: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Graph {
+    edges: Vec<Edge>,
+    platforms: Vec<PlatformId>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reach {
+    StepFree,
+    /// Step-free when everything works, but not right now
+    Blocked,
+    /// No step-free path we know of, outages or not
+    Unknown,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlatformAccess {
+    pub platform: PlatformId,
+    pub reach: Reach,
+    /// Out-of-service equipment that would reconnect the platform on its own
+    pub blocked_by: Vec<EquipmentId>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ComplexAccess {
+    pub complex_id: ComplexId,
+    pub platforms: Vec<PlatformAccess>,
+    /// The best of each direction's platforms
+    pub north: Reach,
+    pub south: Reach,
+    /// e.g. "southbound platform L06S unreachable (EL293 out)"
+    pub notes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AccessGraphs {
+    graphs: HashMap<ComplexId, Graph>,
+}
+
+impl AccessGraphs {
+    /// Equipment that isn't ADA accessible is no step-free path, though its platforms are still listed
+    pub fn new(equipment: &[api::AccessEquipment]) -> Self {
+        let mut graphs: HashMap<ComplexId, Graph> = HashMap::new();
+        let mut redundant = HashSet::new();
+        for eq in equipment.iter().filter(|e| e.equipmenttype != "ES") {
+            let graph = graphs.entry(eq.complex_id).or_default();
+            for p in eq.stop_ids.iter().flat_map(StationId::platforms) {
+                if !graph.platforms.contains(&p) {
+                    graph.platforms.push(p);
+                }
+            }
+            if !eq.ada {
+                debug!("{} isn't ADA accessible", eq.equipmentno);
+                continue;
+            }
+            if eq.redundant != 0 {
+                redundant.insert(eq.equipmentno);
+            }
+            let parsed = levels(&eq.shortdescription, &eq.stop_ids)
+                .or_else(|| levels(&eq.serving, &eq.stop_ids));
+            let Some((from, to)) = parsed else {
+                debug!("can't tell what {} connects: '{}'", eq.equipmentno, eq.shortdescription);
+                continue;
+            };
+            for a in &from {
+                for b in &to {
+                    graph.edges.push(Edge {
+                        a: a.clone(),
+                        b: b.clone(),
+                        equipment: Some(eq.equipmentno),
+                        bidirectional: true,
+                    });
+                }
+            }
+        }
+        // redundant equipment has a backup; if we can't tell which, assume one that's always open
+        for graph in graphs.values_mut() {
+            let backups: Vec<Edge> = graph.edges.iter()
+                .filter(|e| e.equipment.is_some_and(|id| redundant.contains(&id)))
+                .filter(|e| !graph.edges.iter().any(|o| o.equipment != e.equipment && o.joins(e)))
+                .map(|e| Edge { equipment: None, ..e.clone() })
+                .collect();
+            graph.edges.extend(backups);
+        }
+        AccessGraphs { graphs }
+    }
+    /// Add step-free pathways, placing each in a complex by its stops' parent stations
+    pub fn add_pathways(&mut self, schedule: &StaticSchedule, complex_of: impl Fn(&StationId) -> Option<ComplexId>) {
+        let complex = |id: &str| {
+            let mut id = id;
+            for _ in 0..4 {
+                let station = match id.parse::<PlatformId>() {
+                    Ok(p) => Some(p.station),
+                    Err(_) => id.parse::<StationId>().ok(),
+                };
+                if let Some(c) = station.as_ref().and_then(&complex_of) {
+                    return Some(c);
+                }
+                id = schedule.node(id)?.parent.as_deref()?;
+            }
+            None
+        };
+        let node = |id: &str| match (schedule.node(id).map(|n| n.location_type), id.parse::<PlatformId>()) {
+            (Some(2), _) => Node::Street,
+            (_, Ok(p)) => Node::Platform(p),
+            _ => Node::Other(id.to_owned()),
+        };
+        let mut added = 0;
+        for pw in schedule.pathways().iter().filter(|p| p.mode.step_free()) {
+            let Some(cplx) = complex(&pw.from).or_else(|| complex(&pw.to)) else { continue };
+            let graph = self.graphs.entry(cplx).or_default();
+            let equipment = graph.edges.iter()
+                .filter_map(|e| e.equipment.as_ref())
+                .find(|id| id.as_ref() == pw.pathway_id)
+                .cloned();
+            let (a, b) = (node(&pw.from), node(&pw.to));
+            for n in [&a, &b] {
+                if let Node::Platform(p) = n {
+                    if !graph.platforms.contains(p) {
+                        graph.platforms.push(*p);
+                    }
+                }
+            }
+            graph.edges.push(Edge { a, b, equipment, bidirectional: pw.bidirectional });
+            added += 1;
+        }
+        debug!("added {added} step-free pathways");
+    }
+    /// Per-platform reachability given which equipment is `working`
+    pub fn reach(&self, id: ComplexId, working: impl Fn(&EquipmentId) -> bool) -> Option<ComplexAccess> {
+        let graph = self.graphs.get(&id)?;
+        let open = |e: &Edge| e.equipment.as_ref().is_none_or(&working);
+        let now = graph.reachable(open);
+        let ever = graph.reachable(|_| true);
+        let out: Vec<&EquipmentId> = graph.edges.iter()
+            .filter_map(|e| e.equipment.as_ref())
+            .filter(|id| !working(id))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        let mut platforms: Vec<PlatformAccess> = graph.platforms.iter()
+            .map(|p| {
+                let node = Node::Platform(*p);
+                let reach = match (now.contains(&node), ever.contains(&node)) {
+                    (true, _) => Reach::StepFree,
+                    (false, true) => Reach::Blocked,
+                    (false, false) => Reach::Unknown,
+                };
+                let mut blocked_by: Vec<EquipmentId> = match reach {
+                    Reach::Blocked => out.iter()
+                        .filter(|&&x| graph.reachable(|e| open(e) || e.equipment.as_ref() == Some(x)).contains(&node))
+                        .map(|&x| *x)
+                        .collect(),
+                    _ => vec![],
+                };
+                blocked_by.sort();
+                PlatformAccess { platform: *p, reach, blocked_by }
+            })
+            .collect();
+        platforms.sort_by_key(|p| p.platform.to_string());
+        let best = |dir| {
+            let all: Vec<Reach> = platforms.iter().filter(|p| p.platform.dir == dir).map(|p| p.reach).collect();
+            [Reach::StepFree, Reach::Blocked].into_iter().find(|r| all.contains(r)).unwrap_or(Reach::Unknown)
+        };
+        let notes = platforms.iter()
+            .filter(|p| p.reach == Reach::Blocked)
+            .map(|p| {
+                let dir = match p.platform.dir {
+                    TripDir::North => "northbound",
+                    TripDir::South => "southbound",
+                };
+                let ids: Vec<&str> = p.blocked_by.iter().map(AsRef::as_ref).collect();
+                match ids.is_empty() {
+                    true => format!("{dir} platform {} unreachable", p.platform),
+                    false => format!("{dir} platform {} unreachable ({} out)", p.platform, ids.join(", ")),
+                }
+            })
+            .collect();
+        Some(ComplexAccess { complex_id: id, north: best(TripDir::North), south: best(TripDir::South), platforms, notes })
+    }
+}
+
+impl Edge {
+    /// Links the same two nodes
+    fn joins(&self, other: &Edge) -> bool {
+        (self.a == other.a && self.b == other.b) || (self.a == other.b && self.b == other.a)
+    }
+}
+
+impl Graph {
+    /// Everything reachable from the street over usable edges
+    fn reachable(&self, usable: impl Fn(&Edge) -> bool) -> HashSet<Node> {
+        let mut seen = HashSet::from([Node::Street]);
+        let mut queue = VecDeque::from([Node::Street]);
+        while let Some(n) = queue.pop_front() {
+            for e in self.edges.iter().filter(|e| usable(e)) {
+                let next = if e.a == n {
+                    &e.b
+                } else if e.b == n && e.bidirectional {
+                    &e.a
+                } else {
+                    continue
+                };
+                if seen.insert(next.clone()) {
+                    queue.push_back(next.clone());
+                }
+            }
+        }
+        seen
+    }
+}
+
+/// The nodes on each side of "X to Y"
+fn levels(desc: &str, stations: &[StationId]) -> Option<(Vec<Node>, Vec<Node>)> {
+    let lower = desc.to_lowercase();
+    let (from, to) = lower.split_once(" to ")?;
+    let side = |s: &str| -> Option<Vec<Node>> {
+        if s.contains("street") || s.contains("sidewalk") || s.contains("entrance") {
+            Some(vec![Node::Street])
+        } else if s.contains("platform") {
+            let dirs: Vec<TripDir> = match (NORTH.iter().any(|w| s.contains(w)), SOUTH.iter().any(|w| s.contains(w))) {
+                (true, false) => vec![TripDir::North],
+                (false, true) => vec![TripDir::South],
+                _ => vec![TripDir::North, TripDir::South],
+            };
+            Some(stations.iter()
+                .flat_map(|st| dirs.iter().map(|&d| Node::Platform(st.platform(d))))
+                .collect())
+        } else if ["mezzanine", "fare control", "concourse", "passageway", "underpass", "overpass", "bridge"]
+            .iter().any(|w| s.contains(w))
+        {
+            Some(vec![Node::Mezzanine])
+        } else {
+            None
+        }
+    };
+    let (a, b) = (side(from)?, side(to)?);
+    (!a.is_empty() && !b.is_empty()).then_some((a, b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{levels, AccessGraphs, Edge, Graph, Node, Reach};
+    use crate::{api::{EquipmentId, fixtures::equipment}, manifest::{fixtures::zipped, Policy, StaticSchedule}, msg::{StationId, TripDir}};
+    use serde_json::json;
+
+    #[test]
+    fn platforms() {
+        let station: StationId = "L06".parse().unwrap();
+        let (n, s) = (station.platform(TripDir::North), station.platform(TripDir::South));
+        let stations = [station];
+        let (a, b) = levels("Street to Brooklyn-bound platform", &stations).unwrap();
+        assert_eq!((a, b), (vec![Node::Street], vec![Node::Platform(s)]));
+        let (_, b) = levels("Mezzanine to platforms", &stations).unwrap();
+        assert_eq!(b.len(), 2);
+        assert!(levels("Elevator EL293", &stations).is_none());
+
+        let el = |id: &str| EquipmentId::make(id);
+        let edge = |a: &Node, b: &Node, id: &str| Edge { a: a.clone(), b: b.clone(), equipment: Some(el(id)), bidirectional: true };
+        let (street, mezz) = (Node::Street, Node::Mezzanine);
+        let graph = Graph {
+            edges: vec![
+                edge(&street, &mezz, "EL1"),
+                edge(&mezz, &Node::Platform(n), "EL2"),
+                edge(&street, &Node::Platform(s), "EL3"),
+            ],
+            platforms: vec![n, s],
+        };
+        let id = serde_json::from_str("119").unwrap();
+        let graphs = AccessGraphs { graphs: [(id, graph)].into() };
+        let access = graphs.reach(id, |e| e.as_ref() != "EL1").unwrap();
+        assert_eq!((access.north, access.south), (Reach::Blocked, Reach::StepFree));
+        assert_eq!(access.platforms[0].blocked_by, [el("EL1")]);
+        assert_eq!(access.notes, ["northbound platform L06N unreachable (EL1 out)"]);
+    }
+
+    #[test]
+    fn ada_and_redundant() {
+        let el = |id: &str, cplx: &str, desc: &str, ada: &str, redundant: i32| equipment(json!({
+            "equipmentno": id, "stationcomplexid": cplx, "shortdescription": desc, "ADA": ada, "redundant": redundant,
+        }));
+        let graphs = AccessGraphs::new(&[
+            // EL2 is the only way down; EL3 has a twin we can't see
+            el("EL1", "119", "Street to mezzanine", "N", 0),
+            el("EL2", "119", "Mezzanine to platforms", "Y", 0),
+            el("EL3", "119", "Street to mezzanine", "Y", 1),
+            // twins we can see
+            el("EL5", "120", "Street to mezzanine", "Y", 1),
+            el("EL6", "120", "Street to mezzanine", "Y", 1),
+            el("EL7", "120", "Mezzanine to platforms", "Y", 0),
+            el("EL8", "121", "Street to platforms", "N", 0),
+        ]);
+        let id = |s: &str| serde_json::from_str(s).unwrap();
+        let reach = |cplx: &str, out: &[&str]| {
+            let a = graphs.reach(id(cplx), |e| !out.contains(&e.as_ref())).unwrap();
+            (a.north, a.south)
+        };
+        let (free, blocked) = ((Reach::StepFree, Reach::StepFree), (Reach::Blocked, Reach::Blocked));
+        assert_eq!(reach("119", &["EL1"]), free);
+        assert_eq!(reach("119", &["EL3"]), free, "its twin is working");
+        assert_eq!(reach("119", &["EL2"]), blocked);
+        assert_eq!(reach("120", &["EL5"]), free);
+        assert_eq!(reach("120", &["EL5", "EL6"]), blocked);
+        let access = graphs.reach(id("120"), |e| !["EL5", "EL6"].contains(&e.as_ref())).unwrap();
+        assert_eq!(access.platforms[0].blocked_by, [EquipmentId::make("EL5"), EquipmentId::make("EL6")]);
+        assert_eq!(reach("121", &[]), (Reach::Unknown, Reach::Unknown), "not ADA accessible");
+    }
+
+    #[test]
+    fn pathways() {
+        let schedule = zipped(&[
+            ("stops.txt", "stop_id,stop_name,stop_lat,stop_lon,location_type,parent_station\n\
+                L06,1 Av,40.730953,-73.981628,1,\n\
+                L06N,1 Av,40.730953,-73.981628,0,L06\n\
+                L06S,1 Av,40.730953,-73.981628,0,L06\n\
+                L06_E1,1 Av,40.730584,-73.981245,2,L06\n\
+                L06_M,1 Av,40.730953,-73.981628,3,L06\n"),
+            ("routes.txt", "route_id,route_short_name\nL,L\n"),
+            ("trips.txt", "route_id,trip_id,service_id,trip_headsign,direction_id\n"),
+            ("stop_times.txt", "trip_id,stop_id,arrival_time,departure_time,stop_sequence\n"),
+            ("calendar.txt", "service_id,monday,tuesday,wednesday,thursday,friday,saturday,sunday,start_date,end_date\n"),
+            ("pathways.txt", "pathway_id,from_stop_id,to_stop_id,pathway_mode,is_bidirectional\n\
+                EL293,L06_E1,L06_M,5,1\n\
+                W1,L06_M,L06N,1,1\n\
+                S1,L06_M,L06S,2,1\n"),
+        ]);
+        let (schedule, reports) = StaticSchedule::try_from_source(&schedule, Policy::FailFast).unwrap();
+        assert!(reports.iter().all(|r| r.is_clean()), "entrances and nodes aren't bad stops");
+        let cplx = serde_json::from_str("119").unwrap();
+        let l06: StationId = "L06".parse().unwrap();
+        // EL293 goes from the street to the Brooklyn-bound platform
+        let mut graphs = AccessGraphs::new(&[equipment(json!({}))]);
+        let reach = |graphs: &AccessGraphs, out: bool| {
+            let a = graphs.reach(cplx, |e| !out || e.as_ref() != "EL293").unwrap();
+            (a.north, a.south)
+        };
+        assert_eq!(reach(&graphs, false), (Reach::Unknown, Reach::StepFree));
+        graphs.add_pathways(&schedule, |s| (s == &l06).then_some(cplx));
+        assert_eq!(reach(&graphs, false), (Reach::StepFree, Reach::StepFree), "by the walkway, not the stairs");
+        assert_eq!(reach(&graphs, true), (Reach::Blocked, Reach::Blocked), "the pathway is EL293 too");
+        let mut none = AccessGraphs::new(&[]);
+        none.add_pathways(&schedule, |_| None);
+        assert!(none.reach(cplx, |_| true).is_none(), "pathways outside any complex are dropped");
+    }
+}
@@ -0,0 +1,121 @@
+//! Where to go instead when an elevator is out: the next accessible stations along its lines.
+
+use crate::{api::{ComplexId, EquipmentId}, msg::{Route, TripDir}, routes};
+use super::{States, Elevator, Upcoming, nearby::haversine};
+use serde::Serialize;
+
+/// Upcoming trains to show per alternative
+const UPCOMING: usize = 3;
+
+#[derive(Serialize)]
+pub struct Alternative {
+    pub complex_id: ComplexId,
+    pub name: Option<String>,
+    pub route: Route,
+    /// Meters from the complex with the outage
+    pub distance: Option<f64>,
+    /// The next few trains there on `route`, headed the same way
+    pub upcoming: Vec<Upcoming>,
+}
+
+#[derive(Serialize)]
+pub struct Alternatives {
+    pub equipment: EquipmentId,
+    pub working: bool,
+    pub north: Vec<Alternative>,
+    pub south: Vec<Alternative>,
+    pub buses: Vec<String>,
+    pub alt_desc: String,
+}
+
+impl States {
+    /// For one elevator at a complex, or else every one that's out
+    pub fn alternatives(&self, id: ComplexId, equipment: Option<&EquipmentId>) -> Option<Vec<Alternatives>> {
+        let els = self.elevators.get(id)?;
+        Some(els.iter()
+            .filter(|e| match equipment {
+                Some(eq) => e.id() == eq,
+                None => !e.is_working(),
+            })
+            .map(|e| Alternatives {
+                equipment: *e.id(),
+                working: e.is_working(),
+                north: self.ranked(id, e, TripDir::North),
+                south: self.ranked(id, e, TripDir::South),
+                buses: e.buses().to_vec(),
+                alt_desc: e.alt_desc().to_owned(),
+            })
+            .collect())
+    }
+    /// Nearest first, only stations whose own elevators are all working; one with none listed may
+    /// not be accessible at all
+    fn ranked(&self, from: ComplexId, el: &Elevator, dir: TripDir) -> Vec<Alternative> {
+        let here = self.complexes.get_ref(from).map(|m| m.coord());
+        let canonical = |r: &Route| routes::catalog().canonical(r).unwrap_or(*r);
+        let mut alts: Vec<Alternative> = el.next_ada(dir).iter()
+            .filter(|(id, _)| self.elevators.elevators_working(*id) == Some(true))
+            .map(|(id, route)| {
+                let meta = self.complexes.get_ref(*id);
+                let upcoming = self.trains.get(*id).unwrap_or_default().into_iter()
+                    .filter(|u| u.dir() == dir && u.route() == &canonical(route))
+                    .take(UPCOMING)
+                    .collect();
+                Alternative {
+                    complex_id: *id,
+                    name: meta.map(|m| m.name().to_owned()),
+                    route: *route,
+                    distance: here.zip(meta.map(|m| m.coord())).map(|(a, b)| haversine(a, b)),
+                    upcoming,
+                }
+            })
+            .collect();
+        let far = |a: &Alternative| a.distance.unwrap_or(f64::INFINITY);
+        alts.sort_by(|a, b| far(a).total_cmp(&far(b)));
+        alts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{api::fixtures::{complex, equipment, outage}, state::States};
+    use serde_json::json;
+
+    #[test]
+    fn alternatives() {
+        let cplx = |id: &str, name: &str, lat: &str, lon: &str| complex(json!({
+            "complex_id": id, "stop_name": name, "latitude": lat, "longitude": lon,
+        }));
+        let complexes = [
+            cplx("119", "1 Av", "40.730953", "-73.981628"),
+            cplx("116", "Nearby", "40.7325", "-73.9845"),
+            cplx("117", "8 Av", "40.739777", "-74.002578"),
+            cplx("118", "6 Av", "40.737335", "-73.996786"),
+            cplx("120", "Bedford Av", "40.717304", "-73.956872"),
+        ];
+        let el = |id: &str, cplx: &str| equipment(json!({"equipmentno": id, "stationcomplexid": cplx}));
+        let equip = [
+            equipment(json!({"equipmentno": "EL293", "nextadanorth": "118, L; 117, L; 116, L", "nextadasouth": "120, L"})),
+            el("EL294", "119"),
+            el("EL117", "117"),
+            el("EL118", "118"),
+        ];
+        let outages = [outage(json!({"equipment": "EL293"})), outage(json!({"equipment": "EL118", "station": "6 Av"}))];
+        let state = States::new(&complexes, &equip, &outages, &[]);
+        let id = |s: &str| serde_json::from_str(s).unwrap();
+        let alts = state.alternatives(id("119"), None).unwrap();
+        assert_eq!(alts.len(), 1, "just the one that's out");
+        let a = &alts[0];
+        assert_eq!((a.equipment.as_ref(), a.working), ("EL293", false));
+        assert_eq!(a.buses, ["M15", "M15 SBS", "M14A SBS"]);
+        // 6 Av's elevator is out too, and the closer one and Bedford Av have none listed
+        let north: Vec<_> = a.north.iter().map(|a| a.complex_id.to_string()).collect();
+        assert_eq!(north, ["117"]);
+        assert_eq!(a.north[0].name.as_deref(), Some("8 Av"));
+        assert!(a.north[0].distance.is_some_and(|d| (1900.0..2100.0).contains(&d)), "{:?}", a.north[0].distance);
+        assert!(a.south.is_empty());
+        let el294 = "EL294".parse().unwrap();
+        let alts = state.alternatives(id("119"), Some(&el294)).unwrap();
+        assert!(matches!(&alts[..], [a] if a.working && a.north.len() == 1), "the fixture's 117, L");
+        assert!(state.alternatives(id("999"), None).is_none());
+    }
+}
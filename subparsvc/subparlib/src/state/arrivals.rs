@@ -0,0 +1,138 @@
+//! Upcoming trains the way a platform sign shows them: by route and direction, soonest first.
+
+use crate::{Humanize, Locale, routes::{self, Agency}, msg::{Route, TripDir}};
+use super::Upcoming;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+
+/// Trains at most this far apart run on frequency, e.g. "every 3-4 min"
+const FREQUENT: i64 = 5 * 60;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ArrivalGroup {
+    pub route: Route,
+    pub dir: TripDir,
+    /// The most common headsign, else e.g. "Northbound"
+    pub label: String,
+    /// Soonest first
+    pub next: Vec<Upcoming>,
+    /// Seconds between consecutive upcoming trains, including those past `next`
+    pub headways: Vec<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frequency: Option<Frequency>,
+}
+
+/// Shown instead of exact times when trains come often enough
+#[derive(Debug, Clone, Serialize)]
+pub struct Frequency {
+    pub min_headway: i64,
+    pub max_headway: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+}
+
+/// e.g. `?n=3`
+#[derive(Debug, Clone, Deserialize)]
+pub struct ArrivalsQuery {
+    #[serde(default = "ArrivalsQuery::default_n")]
+    pub n: usize,
+}
+
+impl ArrivalsQuery {
+    fn default_n() -> usize {
+        3
+    }
+}
+
+/// One group per route and direction, in catalog order, then north before south
+pub fn group(upcoming: Vec<Upcoming>, n: usize) -> Vec<ArrivalGroup> {
+    let mut by: HashMap<(Route, TripDir), Vec<Upcoming>> = HashMap::new();
+    for u in upcoming {
+        by.entry((*u.route(), u.dir())).or_default().push(u);
+    }
+    let order = |r: &Route| routes::catalog().iter().position(|info| info.id == r.as_ref()).unwrap_or(usize::MAX);
+    let mut groups: Vec<ArrivalGroup> = by.into_iter()
+        .map(|((route, dir), mut trains)| {
+            trains.sort_by_key(Upcoming::arrival);
+            let label = label(&route, dir, &trains);
+            let headways: Vec<i64> = trains.windows(2)
+                .map(|w| w[1].arrival().seconds_since(&w[0].arrival()))
+                .collect();
+            let frequency = match (headways.iter().min(), headways.iter().max()) {
+                (Some(&lo), Some(&hi)) if hi <= FREQUENT => Some(Frequency { min_headway: lo, max_headway: hi, text: None }),
+                _ => None,
+            };
+            trains.truncate(n.max(1));
+            ArrivalGroup { route, dir, label, next: trains, headways, frequency }
+        })
+        .collect();
+    groups.sort_by(|a, b| (order(&a.route), &a.route, a.dir).cmp(&(order(&b.route), &b.route, b.dir)));
+    groups
+}
+
+fn label(route: &Route, dir: TripDir, trains: &[Upcoming]) -> String {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for h in trains.iter().filter_map(Upcoming::headsign) {
+        *counts.entry(h).or_default() += 1;
+    }
+    if let Some((h, _)) = counts.into_iter().max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(a.0))) {
+        return h.to_owned();
+    }
+    let agency = routes::catalog().route(route).map_or(Agency::Nyct, |r| r.agency);
+    let way = dir.word(agency, Locale::En);
+    way[..1].to_uppercase() + &way[1..]
+}
+
+impl ArrivalGroup {
+    pub fn humanize(&mut self, h: &Humanize) {
+        self.next.iter_mut().for_each(|u| u.humanize(h));
+        if let Some(f) = self.frequency.as_mut() {
+            f.text = Some(h.every(f.min_headway, f.max_headway));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{group, Upcoming};
+    use crate::{Timestamp, msg::{Route, TripDir}};
+    use std::time::Duration;
+
+    #[test]
+    fn groups() {
+        let now = Timestamp::now();
+        let train = |route: &str, dir, mins: u64, headsign: Option<&str>| Upcoming {
+            trip: format!("{route}{dir}{mins}").parse().unwrap(),
+            route: Route::make(route),
+            dir,
+            headsign: headsign.map(str::to_owned),
+            stop: "L06".parse().unwrap(),
+            arrival: now + Duration::from_secs(60 * mins),
+            message: now,
+            scheduled: None,
+            delay: None,
+            text: None,
+            stale: false,
+            feed: "l",
+        };
+        let trains = vec![
+            train("L", TripDir::South, 9, None),
+            train("L", TripDir::North, 4, Some("8 Av")),
+            train("G", TripDir::North, 6, None),
+            train("L", TripDir::North, 1, Some("8 Av")),
+            train("L", TripDir::South, 2, None),
+            train("L", TripDir::North, 7, Some("8 Av")),
+            train("L", TripDir::North, 10, None),
+        ];
+        let groups = group(trains.clone(), 3);
+        let summary: Vec<_> = groups.iter().map(|g| (g.route.to_string(), g.label.as_str(), g.next.len())).collect();
+        assert_eq!(summary, [("G".into(), "Northbound", 1), ("L".into(), "8 Av", 3), ("L".into(), "Southbound", 2)]);
+        assert_eq!(groups[1].headways, [180, 180, 180]);
+        assert!(groups[1].frequency.as_ref().is_some_and(|f| f.max_headway == 180));
+        let one = group(trains, 1);
+        assert_eq!((one[1].next.len(), one[1].headways.len()), (1, 3), "headways aren't cut to `n`");
+        assert!(one[1].frequency.is_some());
+        assert!(groups[2].frequency.is_none(), "7 min apart");
+        assert!(groups[0].frequency.is_none(), "one train");
+    }
+}
@@ -1,6 +1,11 @@
 
 use std::{sync::{Arc}, collections::{HashMap}};
-use crate::{msg::{StopId, Route, }, api::{self, ComplexId}};
+use crate::{msg::{StationId, PlatformId, Route, }, api::{self, ComplexId, EquipmentId, crosswalk}, manifest::{ManifestStops, StopRow}, routes::{Agency, RouteInfo}};
+use super::nearby::{Coord, Nearby, NearbyEntrance, haversine};
+use tracing::warn;
+
+// Commuter rail stations this close to a like-named subway complex are part of it
+const HUB_METERS: f64 = 400.0;
 
 type ComplexMap = HashMap<ComplexId, ComplexMeta>;
 
@@ -12,12 +17,14 @@ pub struct ComplexStates {
 #[derive(serde::Serialize, Clone)]
 pub struct ComplexMeta {
     name: String,
+    borough: String,
     ada: api::AdaStatus,
     ada_notes: Option<String>,
     coord: (f64, f64),
     routes: Vec<Route>,
-    stops: Vec<StopId>,
+    stops: Vec<StationId>,
     entrances: Vec<api::SubwayEntrance>,
+    agencies: Vec<Agency>,
 }
 
 impl ComplexStates {
@@ -38,18 +45,180 @@ impl ComplexStates {
     pub fn get(&self, id: ComplexId) -> Option<ComplexMeta> {
         self.meta.get(&id).cloned()
     }
+    pub fn get_ref(&self, id: ComplexId) -> Option<&ComplexMeta> {
+        self.meta.get(&id)
+    }
+    pub fn complex_of(&self, station: &StationId) -> Option<ComplexId> {
+        self.meta.iter().find(|(_, m)| m.stops.contains(station)).map(|(&id, _)| id)
+    }
+    /// Commuter rail stations join the subway complex they share a building with, or else get their own.
+    /// Returns the complex each station ended up in
+    pub fn add_commuter(&mut self, agency: Agency, stops: &ManifestStops) -> Vec<(StationId, ComplexId)> {
+        let route = agency.route().map(RouteInfo::route);
+        let subway: Vec<(ComplexId, String, Coord)> = self.meta.iter()
+            .filter(|(_, m)| m.agencies.contains(&Agency::Nyct))
+            .map(|(&id, m)| (id, m.name.clone(), m.coord))
+            .collect();
+        let meta = Arc::make_mut(&mut self.meta);
+        let mut placed = vec![];
+        for row in stops.iter().filter(|r| r.parent.is_none()) {
+            let Ok(station) = StationId::namespaced(agency, row.stop.as_ref()) else {
+                warn!("bad {agency:?} stop id {}", row.stop);
+                continue
+            };
+            let hub = subway.iter()
+                .map(|(id, name, coord)| (haversine(row.location, *coord), id, name))
+                .filter(|(d, _, name)| *d <= HUB_METERS && crosswalk::same_station(&row.name, name))
+                .min_by(|a, b| a.0.total_cmp(&b.0))
+                .map(|(_, &id, _)| id);
+            let own = || ComplexId::commuter(agency, row.stop.as_ref().parse().ok()?);
+            let Some(id) = hub.or_else(own) else {
+                warn!("no complex for {agency:?} station {station} '{}'", row.name);
+                continue
+            };
+            let m = meta.entry(id).or_insert_with(|| ComplexMeta::commuter(row));
+            m.stops.push(station);
+            if let Some(r) = route.as_ref().filter(|r| !m.routes.contains(r)) {
+                m.routes.push(*r);
+            }
+            if !m.agencies.contains(&agency) {
+                m.agencies.push(agency);
+            }
+            placed.push((station, id));
+        }
+        placed
+    }
+    /// Every platform of every station in the complex
+    pub fn platforms(&self, id: ComplexId) -> Option<Vec<PlatformId>> {
+        let meta = self.meta.get(&id)?;
+        Some(meta.stops.iter().flat_map(StationId::platforms).collect())
+    }
+}
+
+impl ComplexMeta {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn coord(&self) -> (f64, f64) {
+        self.coord
+    }
+    /// M, Bk, Bx, Q, or SI; empty for commuter rail stations
+    pub fn borough(&self) -> &str {
+        &self.borough
+    }
+    pub fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+    /// Agencies with a station in the complex
+    pub fn agencies(&self) -> &[Agency] {
+        &self.agencies
+    }
+    fn commuter(row: &StopRow) -> Self {
+        ComplexMeta {
+            name: row.name.clone(),
+            borough: String::new(),
+            ada: match row.wheelchair_boarding {
+                Some(1) => api::AdaStatus::Full,
+                _ => api::AdaStatus::No,
+            },
+            ada_notes: None,
+            coord: row.location,
+            routes: vec![],
+            stops: vec![],
+            entrances: vec![],
+            agencies: vec![],
+        }
+    }
+    /// `street` lists the street elevators serving some stations, and whether each is in service
+    pub(super) fn nearby(&self, id: ComplexId, distance: f64, at: Coord, street: impl Fn(&[StationId]) -> Vec<(EquipmentId, bool)>) -> Nearby {
+        let entrance = |e: &api::SubwayEntrance| {
+            let coord = (e.entrance_latitude, e.entrance_longitude);
+            let elevators = match e.entrance_type.contains("Elevator") {
+                true => street(&e.stop_ids),
+                false => vec![],
+            };
+            NearbyEntrance {
+                coord,
+                distance: haversine(at, coord),
+                entrance_type: e.entrance_type.clone(),
+                working: (!elevators.is_empty()).then(|| elevators.iter().all(|(_, working)| *working)),
+                equipment: elevators.into_iter().map(|(id, _)| id).collect(),
+            }
+        };
+        let closest = |es: Vec<NearbyEntrance>| es.into_iter().min_by(|a, b| a.distance.total_cmp(&b.distance));
+        Nearby {
+            complex_id: id,
+            name: self.name.clone(),
+            distance,
+            ada: self.ada,
+            routes: self.routes.clone(),
+            entrance: closest(self.entrances.iter().map(entrance).collect()),
+            elevator_entrance: closest(self.entrances.iter()
+                .filter(|e| e.entrance_type.contains("Elevator"))
+                .map(entrance)
+                .collect()),
+        }
+    }
 }
 
 impl From<&api::ComplexInfo> for ComplexMeta {
     fn from(c: &api::ComplexInfo) -> Self {
         ComplexMeta {
             name: c.stop_name.clone(),
+            borough: c.borough.clone(),
             ada: c.ada,
             ada_notes: c.ada_notes.clone(),
             coord: (c.latitude, c.longitude),
             routes: c.routes.clone(),
             stops: c.stop_ids.clone(),
             entrances: vec![],
+            agencies: vec![Agency::Nyct],
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::ComplexStates;
+    use crate::{api::{fixtures::complex, AdaStatus, ComplexId}, manifest::{fixtures::zipped, ManifestStops}, msg::{Route, StationId}, routes::Agency};
+    use serde_json::json;
+
+    #[test]
+    fn add_commuter() {
+        let subway = |id: &str, name: &str, lat: &str, lon: &str| complex(json!({
+            "complex_id": id, "stop_name": name, "latitude": lat, "longitude": lon,
+        }));
+        let mut cplxs = ComplexStates::new(&[
+            subway("40", "Sutphin Blvd-Archer Av-JFK Airport", "40.700486", "-73.807969"),
+            subway("616", "61 St-Woodside", "40.74563", "-73.902984"),
+            subway("261", "Forest Hills-71 Av", "40.721691", "-73.844521"),
+        ], &[]);
+        let stops = zipped(&[("stops.txt", "stop_id,stop_name,stop_lat,stop_lon,parent_station,wheelchair_boarding\n\
+            102,Jamaica,40.699769,-73.808962,,1\n\
+            214,Woodside,40.74585,-73.90297,,1\n\
+            179,Ronkonkoma,40.808,-73.106,,1\n\
+            61,Forest Hills,40.719573,-73.844863,,2\n\
+            63,Kew Gardens,40.709,-73.8307,,\n")]);
+        let stops = ManifestStops::from_source(&stops);
+        let mut placed = cplxs.add_commuter(Agency::Lirr, &stops);
+        placed.sort_by_key(|(s, _)| s.to_string());
+        let id = |n: u32| serde_json::from_value::<ComplexId>(json!(n)).unwrap();
+        let st = |s: &str| StationId::namespaced(Agency::Lirr, s).unwrap();
+        assert_eq!(placed, [
+            (st("102"), id(40)),
+            (st("179"), id(100_179)),
+            (st("214"), id(616)),
+            (st("61"), id(261)),
+            (st("63"), id(100_063)),
+        ], "Jamaica and Woodside by alias, Forest Hills by name, the rest on their own");
+        let jamaica = cplxs.get_ref(id(40)).unwrap();
+        assert_eq!(jamaica.agencies(), [Agency::Nyct, Agency::Lirr]);
+        assert!(jamaica.routes().contains(&Route::make("LIRR")));
+        assert_eq!(cplxs.complex_of(&st("102")), Some(id(40)));
+        let ronk = cplxs.get_ref(id(100_179)).unwrap();
+        assert_eq!((ronk.name(), ronk.borough()), ("Ronkonkoma", ""));
+        assert!(matches!(ronk.ada, AdaStatus::Full));
+        assert_eq!((ronk.agencies(), ronk.routes()), (&[Agency::Lirr][..], &[Route::make("LIRR")][..]));
+        assert!(matches!(cplxs.get_ref(id(100_063)).unwrap().ada, AdaStatus::No));
+    }
+}
@@ -1,22 +1,52 @@
 
-use crate::{api::{self, EquipmentId, ComplexId}, msg::{Route}};
+use crate::{Timestamp, api::{self, EquipmentId, ComplexId, Crosswalk, Unmatched}, msg::{Route, StationId, TripDir}};
+use super::events::{EventLog, OutageEvent, OutageSink, Span};
+use super::stats::{self, Reliability, ElevatorReliability, ComplexReliability};
+use super::access::{AccessGraphs, ComplexAccess};
+use super::snapshot::{Published, Version};
+use crate::manifest::StaticSchedule;
 use chrono::{DateTime, FixedOffset};
 use serde::Serialize;
-use std::{sync::{Arc, RwLock}, collections::{HashSet, HashMap}};
+use tracing::{debug, info, warn};
+use std::{sync::{Arc, Mutex}, collections::{HashSet, HashMap}};
 
 type Complexes = HashMap< EquipmentId, ComplexId >;
 type Elevators = HashMap< ComplexId, Vec< Elevator > >;
 
 #[derive(Clone)]
 pub struct ElevatorStates {
-    elevators: Arc<RwLock< Elevators >>,
+    current: Published< Current >,
     complexes: Arc< Complexes >,
-    summary_cache: Arc<RwLock<Option<ElevatorSummary>>>,
+    sink: Option<Arc<dyn OutageSink>>,
+    // keeps concurrent updates from publishing over each other
+    writer: Arc<Mutex<()>>,
+    crosswalk: Option<Arc<Crosswalk>>,
+}
+
+/// Everything from one outage poll
+struct Current {
+    elevators: Elevators,
+    // outages we couldn't place
+    unmatched: Vec<Unmatched>,
+    summary: ElevatorSummary,
+    events: Arc<EventLog>,
+    // from `events`, for reliability
+    spans: Arc<Vec<Span>>,
+    access: Arc<AccessGraphs>,
 }
 
 #[derive(Clone, Serialize)]
 pub struct ElevatorSummary {
+    /// Outages are from the cache at startup, not yet polled
+    #[serde(skip_serializing_if = "super::is_false")]
+    stale: bool,
+    /// Equipment is from the cache at startup and may be out of date, until a refresh finds it
+    /// unchanged; changes apply on restart
+    #[serde(skip_serializing_if = "super::is_false")]
+    datasets_stale: bool,
     outages: Vec<ComplexId>,
+    /// What each outage cuts off, per complex
+    access: Vec<ComplexAccess>,
 }
 
 impl ElevatorStates {
@@ -25,66 +55,222 @@ impl ElevatorStates {
         for eq in equipment {
             els.entry(eq.complex_id).or_default().push(eq.into());
         }
-        let elevators = Arc::new(RwLock::new(els));
         let complexes = equipment.iter().map(|e| (e.equipmentno, e.complex_id)).collect();
         let complexes = Arc::new(complexes);
-        let summary_cache = Arc::new(RwLock::new(None));
-        ElevatorStates { elevators, complexes, summary_cache }
+        let summary = ElevatorSummary { stale: false, datasets_stale: false, outages: vec![], access: vec![] };
+        let current = Published::new(Current {
+            elevators: els,
+            unmatched: vec![],
+            summary,
+            events: Default::default(),
+            spans: Default::default(),
+            access: Arc::new(AccessGraphs::new(equipment)),
+        });
+        ElevatorStates { current, complexes, sink: None, writer: Default::default(), crosswalk: None }
+    }
+    /// Place outages for equipment missing from the equipment list by station name
+    pub fn with_crosswalk(mut self, crosswalk: Crosswalk) -> Self {
+        self.crosswalk = Some(Arc::new(crosswalk));
+        self
     }
+    /// Persist events as they happen, after loading `history` into the log
+    pub fn with_sink(mut self, sink: Arc<dyn OutageSink>, history: Vec<OutageEvent>) -> Self {
+        let current = self.current.load();
+        let mut log = (*current.data.events).clone();
+        log.restore(history);
+        self.publish(current.data.elevators.clone(), current.data.unmatched.clone(), Arc::new(log), current.data.access.clone());
+        self.sink = Some(sink);
+        self
+    }
+    /// Outages from before we started, e.g. cached; they aren't a poll, so nothing's logged
     pub fn with_outages(self, outages: &[api::AccessOutage]) -> Self {
-        self.update(outages);
+        let (map, unmatched) = self.place(outages);
+        let current = self.current.load();
+        self.publish(map, unmatched, current.data.events.clone(), current.data.access.clone());
         self
     }
+    /// Writes new events to the sink after publishing, so neither readers nor the next update wait on it
     pub fn update(&self, outages: &[api::AccessOutage]) {
-        *self.summary_cache.write().unwrap() = None;
-        let mut map = self.elevators.write().unwrap();
+        let writer = self.writer.lock().unwrap();
+        let (map, unmatched) = self.place(outages);
+        let current = map.values().flatten()
+            .filter_map(|e| e.outage.as_ref().map(|o| (e.id, (e.complex_id, o.clone()))))
+            .collect();
+        let last = self.current.load();
+        let mut log = (*last.data.events).clone();
+        let new = log.observe(Timestamp::now(), current);
+        self.publish(map, unmatched, Arc::new(log), last.data.access.clone());
+        drop(writer);
+        if let Some(sink) = self.sink.as_ref().filter(|_| !new.is_empty()) {
+            sink.record(&new);
+        }
+    }
+    /// The elevators with `outages` in place of the last ones, and the outages that fit none
+    fn place(&self, outages: &[api::AccessOutage]) -> (Elevators, Vec<Unmatched>) {
+        let mut map = self.current.load().data.elevators.clone();
         for els in map.values_mut() {
+            els.retain(|e| !e.from_outage);
             for el in els {
                 el.outage = None;
+                el.stale = false;
             }
         }
+        let mut unmatched = vec![];
         for update in outages {
             let equip_id = &update.equipment;
             let Some(cplx) = self.complexes.get(equip_id) else {
-                println!("equipment id {equip_id} not found");
+                match self.crosswalk.as_ref().map(|cw| cw.outage(update)) {
+                    Some(Ok(m)) => {
+                        info!("Outage for unlisted {equip_id} at '{}' placed in {} ({:.2})", update.station, m.complex_id, m.confidence);
+                        map.entry(m.complex_id).or_default().push(Elevator::from_outage(update, m.complex_id));
+                    },
+                    Some(Err(u)) => {
+                        debug!("Unmatched outage for {equip_id} at '{}': {}", update.station, u.reason);
+                        unmatched.push(u);
+                    },
+                    None => {
+                        debug!("Outage for unlisted {equip_id} at '{}'", update.station);
+                        unmatched.push(Unmatched::outage(update, format!("{equip_id} isn't in the equipment list"), None));
+                    },
+                }
                 continue
             };
             let Some(els) = map.get_mut(cplx) else {
-                println!("complex id {cplx} not found");
+                warn!("Outage for {equip_id} in unknown complex {cplx}");
+                unmatched.push(Unmatched::outage(update, format!("complex {cplx} has no equipment"), None));
                 continue
             };
             let Some(el) = els.iter_mut().find(|e| &e.id == equip_id) else {
-                println!("(complex, equip) ({cplx}, {equip_id}) not found");
+                warn!("Outage for {equip_id} not among complex {cplx}'s equipment");
+                unmatched.push(Unmatched::outage(update, format!("{equip_id} isn't among complex {cplx}'s equipment"), None));
                 continue
             };
             el.outage = Some(update.into());
         }
+        (map, unmatched)
+    }
+    /// Step-free paths from pathways.txt, on top of the equipment's
+    pub fn add_pathways(&self, schedule: &StaticSchedule, complex_of: impl Fn(&StationId) -> Option<ComplexId>) {
+        let _writer = self.writer.lock().unwrap();
+        let current = self.current.load();
+        let mut access = (*current.data.access).clone();
+        access.add_pathways(schedule, complex_of);
+        self.publish(current.data.elevators.clone(), current.data.unmatched.clone(), current.data.events.clone(), Arc::new(access));
+    }
+    /// Until the next poll, e.g. when outages came from the cache at startup
+    pub fn mark_stale(&self) {
+        let _writer = self.writer.lock().unwrap();
+        let current = self.current.load();
+        let mut elevators = current.data.elevators.clone();
+        elevators.values_mut().flatten().for_each(|e| e.stale = true);
+        self.publish(elevators, current.data.unmatched.clone(), current.data.events.clone(), current.data.access.clone());
+    }
+    /// Whether the equipment list was loaded from the cache and not yet confirmed current
+    pub fn mark_datasets_stale(&self, stale: bool) {
+        let _writer = self.writer.lock().unwrap();
+        let current = self.current.load();
+        let Current { elevators, unmatched, summary, events, spans, access } = &current.data;
+        let summary = ElevatorSummary { datasets_stale: stale, ..summary.clone() };
+        self.current.publish(Current {
+            elevators: elevators.clone(),
+            unmatched: unmatched.clone(),
+            summary,
+            events: events.clone(),
+            spans: spans.clone(),
+            access: access.clone(),
+        });
+    }
+    fn publish(&self, elevators: Elevators, unmatched: Vec<Unmatched>, events: Arc<EventLog>, access: Arc<AccessGraphs>) {
+        let datasets_stale = self.current.load().data.summary.datasets_stale;
+        let summary = summarize(&access, &elevators, datasets_stale);
+        let spans = Arc::new(events.spans());
+        self.current.publish(Current { elevators, unmatched, summary, events, spans, access });
+    }
+    pub fn version(&self) -> Version {
+        self.current.version()
+    }
+    /// Which platforms are reachable step-free right now
+    pub fn access(&self, id: ComplexId) -> Option<ComplexAccess> {
+        let current = self.current.load();
+        reach(&current.data.access, &current.data.elevators, id)
+    }
+    /// Whether every elevator (not escalator) is in service; None if there aren't any
+    pub fn elevators_working(&self, id: ComplexId) -> Option<bool> {
+        let current = self.current.load();
+        let mut els = current.data.elevators.get(&id)?.iter().filter(|e| !e.is_escalator).peekable();
+        els.peek()?;
+        Some(els.all(Elevator::is_working))
+    }
+    /// Equipment and outages we couldn't attach to a complex
+    pub fn unmatched(&self) -> Vec<Unmatched> {
+        let mut all: Vec<Unmatched> = self.crosswalk.iter().flat_map(|cw| cw.unmatched()).cloned().collect();
+        all.extend(self.current.load().data.unmatched.iter().cloned());
+        all
+    }
+    /// Outage transitions seen while polling; None for an unknown complex
+    pub fn events(&self, id: ComplexId, equipment: Option<&EquipmentId>) -> Option<Vec<OutageEvent>> {
+        let current = self.current.load();
+        if !current.data.elevators.contains_key(&id) {
+            return None;
+        }
+        Some(current.data.events.get(id, equipment))
     }
     pub fn get(&self, id: ComplexId) -> Option<Vec<Elevator>> {
-        let map = self.elevators.read().unwrap();
-        map.get(&id).cloned()
+        self.current.load().data.elevators.get(&id).cloned()
     }
     pub fn get_summary(&self) -> ElevatorSummary {
-        if let Some(s) = &*self.summary_cache.read().unwrap() {
-            return s.clone();
-        }
-        let s = self.make_summary();
-        *self.summary_cache.write().unwrap() = Some(s.clone());
-        s
-    }
-    fn make_summary(&self) -> ElevatorSummary {
-        let mut ids: HashSet<ComplexId> = HashSet::new();
-        {
-            let els = self.elevators.read().unwrap();
-            for e in els.values().flatten() {
-                if let Some(_) = &e.outage {
-                    ids.insert(e.complex_id);
-                }
-            }
+        self.current.load().data.summary.clone()
+    }
+    /// The complex's elevators from the street to any of `stations`, and whether each is in service
+    pub fn street_elevators(&self, id: ComplexId, stations: &[StationId]) -> Vec<(EquipmentId, bool)> {
+        let current = self.current.load();
+        current.data.elevators.get(&id).into_iter().flatten()
+            .filter(|e| !e.is_escalator && e.desc.to_lowercase().contains("street"))
+            .filter(|e| e.stations.iter().any(|s| stations.contains(s)))
+            .map(|e| (e.id, e.is_working()))
+            .collect()
+    }
+    pub fn reliability(&self, id: ComplexId) -> Option<ComplexReliability> {
+        let current = self.current.load();
+        let els = current.data.elevators.get(&id)?;
+        let (now, spans, since) = (Timestamp::now(), &current.data.spans, current.data.events.since());
+        let mine: Vec<&Span> = spans.iter().filter(|s| s.complex_id == id).collect();
+        let equipment = els.iter().map(|e| e.reliability(&mine, now, since, 30)).collect();
+        Some(ComplexReliability { complex_id: id, overall: Reliability::new(&mine, els.len(), now, since), equipment })
+    }
+    /// Every elevator `keep` accepts, least reliable over `days` first
+    pub fn rank(&self, days: i64, keep: impl Fn(&Elevator) -> bool) -> Vec<ElevatorReliability> {
+        let current = self.current.load();
+        let (now, since) = (Timestamp::now(), current.data.events.since());
+        let spans: Vec<&Span> = current.data.spans.iter().collect();
+        let mut ranked: Vec<_> = current.data.elevators.values().flatten()
+            .filter(|e| keep(e))
+            .map(|e| e.reliability(&spans, now, since, days))
+            .collect();
+        ranked.sort_by(|a, b| a.uptime.total_cmp(&b.uptime).then(b.stats.outages.cmp(&a.stats.outages)));
+        ranked
+    }
+}
+
+fn reach(access: &AccessGraphs, map: &Elevators, id: ComplexId) -> Option<ComplexAccess> {
+    let working: HashMap<&EquipmentId, bool> = map.get(&id)?.iter()
+        .map(|e| (&e.id, e.is_working()))
+        .collect();
+    access.reach(id, |e| working.get(e).copied().unwrap_or(true))
+}
+
+fn summarize(access: &AccessGraphs, els: &Elevators, datasets_stale: bool) -> ElevatorSummary {
+    let mut ids: HashSet<ComplexId> = HashSet::new();
+    for e in els.values().flatten() {
+        if let Some(_) = &e.outage {
+            ids.insert(e.complex_id);
         }
-        let outages = ids.into_iter().collect();
-        ElevatorSummary { outages }
     }
+    let mut outages: Vec<ComplexId> = ids.into_iter().collect();
+    outages.sort_by_key(|id| id.to_string());
+    let access = outages.iter().filter_map(|&id| reach(access, els, id)).collect();
+    let stale = els.values().flatten().any(|e| e.stale);
+    ElevatorSummary { stale, datasets_stale, outages, access }
 }
 
 #[derive(Serialize, Clone)]
@@ -92,27 +278,87 @@ pub struct Elevator {
     id: EquipmentId,
     complex_id: ComplexId,
     lines: Vec<Route>,
+    stations: Vec<StationId>,
     is_escalator: bool,
     ada: bool,
     is_active: bool,
     desc: String,
     serving: String,
-    nearby: Vec<(ComplexId, Route)>,
-    buses: String,
+    /// Nearest accessible stations in each direction
+    next_north: Vec<(ComplexId, Route)>,
+    next_south: Vec<(ComplexId, Route)>,
+    buses: Vec<String>,
     alt_desc: String,
     outage: Option<Outage>,
+    /// Not in the equipment list; all we know is from its outage
+    from_outage: bool,
+    /// Its outage is from the cache at startup, not yet polled
+    #[serde(skip_serializing_if = "super::is_false")]
+    stale: bool,
 }
 
 
+impl Elevator {
+    pub fn id(&self) -> &EquipmentId {
+        &self.id
+    }
+    pub fn complex_id(&self) -> ComplexId {
+        self.complex_id
+    }
+    pub fn lines(&self) -> &[Route] {
+        &self.lines
+    }
+    pub fn is_escalator(&self) -> bool {
+        self.is_escalator
+    }
+    pub fn next_ada(&self, dir: TripDir) -> &[(ComplexId, Route)] {
+        match dir {
+            TripDir::North => &self.next_north,
+            TripDir::South => &self.next_south,
+        }
+    }
+    pub fn buses(&self) -> &[String] {
+        &self.buses
+    }
+    pub fn alt_desc(&self) -> &str {
+        &self.alt_desc
+    }
+    fn reliability(&self, spans: &[&Span], now: Timestamp, since: Option<Timestamp>, days: i64) -> ElevatorReliability {
+        let mine: Vec<&Span> = spans.iter().copied().filter(|s| s.equipment == self.id).collect();
+        ElevatorReliability {
+            id: self.id,
+            complex_id: self.complex_id,
+            station: None,
+            borough: None,
+            lines: self.lines.clone(),
+            desc: self.desc.clone(),
+            is_escalator: self.is_escalator,
+            uptime: stats::uptime(&mine, 1, now, days, since),
+            stats: Reliability::new(&mine, 1, now, since),
+        }
+    }
+    /// Active, and not out of service right now
+    pub fn is_working(&self) -> bool {
+        self.is_active && self.outage.as_ref().is_none_or(|o| o.upcoming)
+    }
+}
+
 #[derive(Serialize, Clone)]
 pub struct Outage {
     id: EquipmentId,
-    start: DateTime<FixedOffset>,
+    pub(super) start: DateTime<FixedOffset>,
     ada: bool,
-    est_return: DateTime<FixedOffset>,
-    reason: String,
-    upcoming: bool,
-    maintenance: bool,
+    pub(super) est_return: DateTime<FixedOffset>,
+    pub(super) reason: String,
+    pub(super) upcoming: bool,
+    pub(super) maintenance: bool,
+}
+
+impl Outage {
+    /// From a logged `Started`, which doesn't say whether it's ADA; that's never diffed
+    pub(super) fn restored(id: EquipmentId, start: DateTime<FixedOffset>, est_return: DateTime<FixedOffset>, reason: String, maintenance: bool) -> Self {
+        Outage { id, start, ada: false, est_return, reason, upcoming: false, maintenance }
+    }
 }
 
 impl From<&api::AccessOutage> for Outage {
@@ -139,11 +385,72 @@ impl From<&api::AccessEquipment> for Elevator {
             ada: x.ada,
             serving: x.serving.clone(),
             lines: x.linesservedbyelevator.clone(),
+            stations: x.stop_ids.clone(),
             desc: x.shortdescription.clone(),
-            nearby: vec![],
+            next_north: x.nextadanorth.clone(),
+            next_south: x.nextadasouth.clone(),
             buses: x.busconnections.clone(),
             alt_desc: x.alternativeroute.clone(),
             outage: None,
+            from_outage: false,
+            stale: false,
         }
     }
 }
+
+impl Elevator {
+    /// For equipment that's only in the outage list
+    fn from_outage(x: &api::AccessOutage, complex_id: ComplexId) -> Self {
+        Elevator {
+            id: x.equipment,
+            complex_id,
+            is_escalator: x.equipmenttype == "ES",
+            is_active: true,
+            ada: x.ada,
+            serving: x.serving.clone(),
+            lines: x.routes.clone(),
+            stations: vec![],
+            desc: x.serving.clone(),
+            next_north: vec![],
+            next_south: vec![],
+            buses: vec![],
+            alt_desc: String::new(),
+            outage: Some(x.into()),
+            from_outage: true,
+            stale: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ElevatorStates;
+    use crate::{api::fixtures::{equipment, outage}, state::events::{OutageEvent, OutageSink}};
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Recorded(Mutex<Vec<OutageEvent>>);
+
+    impl OutageSink for Recorded {
+        fn record(&self, events: &[OutageEvent]) {
+            self.0.lock().unwrap().extend_from_slice(events);
+        }
+    }
+
+    #[test]
+    fn warm_start() {
+        let sink = Arc::new(Recorded::default());
+        let states = ElevatorStates::new(&[equipment(json!({}))])
+            .with_sink(sink.clone(), vec![])
+            .with_outages(&[outage(json!({}))]);
+        let id = serde_json::from_value(json!(119)).unwrap();
+        assert!(states.get(id).unwrap().iter().all(|e| !e.is_working()), "cached outages are served");
+        assert_eq!(states.events(id, None).unwrap().len(), 0);
+        // the first poll is the baseline, not the cached outage ending
+        states.update(&[]);
+        states.update(&[outage(json!({}))]);
+        let events = sink.0.lock().unwrap();
+        assert!(matches!(&events[..], [e] if e.equipment.to_string() == "EL293"), "{events:?}");
+    }
+}
@@ -0,0 +1,367 @@
+use crate::{Timestamp, api::{EquipmentId, ComplexId}};
+use super::elevators::Outage;
+use chrono::{DateTime, FixedOffset};
+use serde::{Serialize, Deserialize};
+use std::{collections::{HashMap, HashSet, VecDeque}, fs::{File, OpenOptions}, io::{BufRead, BufReader, Write}, path::PathBuf, sync::Mutex};
+use tracing::warn;
+
+/// How an elevator's outage changed between two polls
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum OutageChange {
+    Started {
+        since: DateTime<FixedOffset>,
+        est_return: DateTime<FixedOffset>,
+        reason: String,
+        maintenance: bool,
+        /// Announced as upcoming before it started
+        planned: bool,
+    },
+    /// Repeats what the outage was, so an `Ended` alone is a complete outage
+    Ended {
+        since: DateTime<FixedOffset>,
+        reason: String,
+        maintenance: bool,
+        planned: bool,
+    },
+    ReturnEstimateChanged { from: DateTime<FixedOffset>, to: DateTime<FixedOffset> },
+    ReasonChanged { from: String, to: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutageEvent {
+    /// When we noticed, i.e. the poll time
+    pub at: Timestamp,
+    pub equipment: EquipmentId,
+    pub complex_id: ComplexId,
+    #[serde(flatten)]
+    pub change: OutageChange,
+}
+
+/// Somewhere to keep events longer than the in-memory log does
+pub trait OutageSink: Send + Sync {
+    fn record(&self, events: &[OutageEvent]);
+}
+
+/// Appends one JSON event per line
+pub struct JsonlSink {
+    path: PathBuf,
+    file: Mutex<Option<File>>,
+}
+
+impl JsonlSink {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        JsonlSink { path: path.into(), file: Mutex::new(None) }
+    }
+    /// Events from a previous run, skipping lines that don't parse
+    pub fn read_all(&self) -> Vec<OutageEvent> {
+        let Ok(file) = File::open(&self.path) else { return vec![] };
+        BufReader::new(file).lines()
+            .map_while(Result::ok)
+            .filter_map(|line| serde_json::from_str(&line)
+                .map_err(|e| warn!("{}: skipping '{line}': {e}", self.path.display()))
+                .ok())
+            .collect()
+    }
+}
+
+impl OutageSink for JsonlSink {
+    fn record(&self, events: &[OutageEvent]) {
+        let mut file = self.file.lock().unwrap();
+        if file.is_none() {
+            match OpenOptions::new().create(true).append(true).open(&self.path) {
+                Ok(f) => *file = Some(f),
+                Err(e) => return warn!("Can't open {}: {e}", self.path.display()),
+            }
+        }
+        let f = file.as_mut().unwrap();
+        for ev in events {
+            let res = serde_json::to_string(ev).map_err(std::io::Error::from)
+                .and_then(|line| writeln!(f, "{line}"));
+            if let Err(e) = res {
+                warn!("Failed to write to {}: {e}", self.path.display());
+            }
+        }
+    }
+}
+
+type Current = HashMap<EquipmentId, (ComplexId, Outage)>;
+
+/// An outage in progress
+#[derive(Clone)]
+struct Ongoing {
+    complex_id: ComplexId,
+    outage: Outage,
+    planned: bool,
+}
+
+/// One outage from start to end, or to now if it's ongoing
+#[derive(Debug, Clone)]
+pub struct Span {
+    pub equipment: EquipmentId,
+    pub complex_id: ComplexId,
+    pub start: Timestamp,
+    pub end: Option<Timestamp>,
+    pub reason: String,
+    pub maintenance: bool,
+    pub planned: bool,
+}
+
+/// The most recent `cap` events, oldest first
+#[derive(Clone)]
+pub struct EventLog {
+    events: VecDeque<OutageEvent>,
+    cap: usize,
+    // None until the first poll or a restored log, which is what's already out, not a change
+    current: Option<HashMap<EquipmentId, Ongoing>>,
+    upcoming: HashSet<EquipmentId>,
+    since: Option<Timestamp>,
+}
+
+impl Default for EventLog {
+    fn default() -> Self {
+        EventLog::new(10_000)
+    }
+}
+
+impl EventLog {
+    pub fn new(cap: usize) -> Self {
+        EventLog {
+            events: VecDeque::new(),
+            cap,
+            current: None,
+            upcoming: HashSet::new(),
+            since: None,
+        }
+    }
+    /// Old events, e.g. from `JsonlSink::read_all`. The outages they leave open are
+    /// what the next poll is diffed against, as if the log's last poll came right before it
+    pub fn restore(&mut self, events: Vec<OutageEvent>) {
+        if events.is_empty() {
+            return;
+        }
+        let mut open = HashMap::new();
+        for ev in events {
+            self.since = Some(self.since.map_or(ev.at, |s| s.min(ev.at)));
+            match &ev.change {
+                OutageChange::Started { since, est_return, reason, maintenance, planned } => {
+                    let outage = Outage::restored(ev.equipment, *since, *est_return, reason.clone(), *maintenance);
+                    open.insert(ev.equipment, Ongoing { complex_id: ev.complex_id, outage, planned: *planned });
+                },
+                OutageChange::Ended { .. } => {
+                    open.remove(&ev.equipment);
+                },
+                OutageChange::ReturnEstimateChanged { to, .. } => {
+                    if let Some(o) = open.get_mut(&ev.equipment) {
+                        o.outage.est_return = *to;
+                    }
+                },
+                OutageChange::ReasonChanged { to, .. } => {
+                    if let Some(o) = open.get_mut(&ev.equipment) {
+                        o.outage.reason = to.clone();
+                    }
+                },
+            }
+            self.push(ev);
+        }
+        self.current = Some(open);
+    }
+    /// When we started watching, as far as the log knows
+    pub fn since(&self) -> Option<Timestamp> {
+        self.since
+    }
+    /// Diff a poll's outages, current and upcoming, against the last poll's, and log the changes.
+    /// Returns them for the caller to persist
+    pub fn observe(&mut self, at: Timestamp, outages: Current) -> Vec<OutageEvent> {
+        let (upcoming, active): (Vec<_>, Vec<_>) = outages.into_iter().partition(|(_, (_, o))| o.upcoming);
+        let was_upcoming = std::mem::replace(&mut self.upcoming, upcoming.into_iter().map(|(id, _)| id).collect());
+        let prev = self.current.take();
+        let now: HashMap<_, _> = active.into_iter()
+            .map(|(id, (complex_id, outage))| {
+                let planned = match prev.as_ref().and_then(|p| p.get(&id)) {
+                    Some(p) => p.planned,
+                    None => was_upcoming.contains(&id),
+                };
+                (id, Ongoing { complex_id, outage, planned })
+            })
+            .collect();
+        self.current = Some(now);
+        let Some(prev) = prev else {
+            self.since = Some(self.since.map_or(at, |s| s.min(at)));
+            return vec![];
+        };
+        let now = self.current.as_ref().unwrap();
+        let mut new = vec![];
+        let mut emit = |equipment: &EquipmentId, complex_id, change| {
+            new.push(OutageEvent { at, equipment: *equipment, complex_id, change });
+        };
+        for (id, cur) in now {
+            let (cplx, o) = (cur.complex_id, &cur.outage);
+            match prev.get(id).map(|p| &p.outage) {
+                None => emit(id, cplx, OutageChange::Started {
+                    since: o.start,
+                    est_return: o.est_return,
+                    reason: o.reason.clone(),
+                    maintenance: o.maintenance,
+                    planned: cur.planned,
+                }),
+                Some(p) => {
+                    if p.est_return != o.est_return {
+                        emit(id, cplx, OutageChange::ReturnEstimateChanged { from: p.est_return, to: o.est_return });
+                    }
+                    if p.reason != o.reason {
+                        emit(id, cplx, OutageChange::ReasonChanged { from: p.reason.clone(), to: o.reason.clone() });
+                    }
+                },
+            }
+        }
+        for (id, p) in &prev {
+            if !now.contains_key(id) {
+                emit(id, p.complex_id, OutageChange::Ended {
+                    since: p.outage.start,
+                    reason: p.outage.reason.clone(),
+                    maintenance: p.outage.maintenance,
+                    planned: p.planned,
+                });
+            }
+        }
+        new.sort_by_key(|e| e.equipment);
+        for ev in &new {
+            self.push(ev.clone());
+        }
+        new
+    }
+    fn push(&mut self, ev: OutageEvent) {
+        if self.events.len() == self.cap {
+            self.events.pop_front();
+        }
+        self.events.push_back(ev);
+    }
+    /// Ended outages still in the log, then ongoing ones
+    pub fn spans(&self) -> Vec<Span> {
+        let ended = self.events.iter().filter_map(|e| match &e.change {
+            OutageChange::Ended { since, reason, maintenance, planned } => Some(Span {
+                equipment: e.equipment,
+                complex_id: e.complex_id,
+                start: Timestamp::from_utc(since.to_utc()),
+                end: Some(e.at),
+                reason: reason.clone(),
+                maintenance: *maintenance,
+                planned: *planned,
+            }),
+            _ => None,
+        });
+        let ongoing = self.current.iter().flatten().map(|(id, o)| Span {
+            equipment: *id,
+            complex_id: o.complex_id,
+            start: Timestamp::from_utc(o.outage.start.to_utc()),
+            end: None,
+            reason: o.outage.reason.clone(),
+            maintenance: o.outage.maintenance,
+            planned: o.planned,
+        });
+        ended.chain(ongoing).collect()
+    }
+    /// A complex's events, optionally just one elevator's
+    pub fn get(&self, complex_id: ComplexId, equipment: Option<&EquipmentId>) -> Vec<OutageEvent> {
+        self.events.iter()
+            .filter(|e| e.complex_id == complex_id && equipment.is_none_or(|id| &e.equipment == id))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EventLog, OutageChange, Current};
+    use crate::{Timestamp, api::{AccessOutage, ComplexId}};
+
+    fn outages(rows: &[(&str, &str, &str)]) -> Current {
+        rows.iter().map(|(equip, ret, reason)| {
+            let (equip, upcoming) = match equip.strip_suffix('?') {
+                Some(e) => (e, "Y"),
+                None => (*equip, "N"),
+            };
+            let x: AccessOutage = serde_json::from_value(serde_json::json!({
+                "station": "1 Av", "trainno": "L", "equipment": equip, "equipmenttype": "EL",
+                "serving": "", "ADA": "Y", "outagedate": "06/26/2023 09:35:00 AM",
+                "estimatedreturntoservice": ret, "reason": reason,
+                "isupcomingoutage": upcoming, "ismaintenanceoutage": "N",
+            })).unwrap();
+            let cplx: ComplexId = serde_json::from_str("119").unwrap();
+            (x.equipment, (cplx, (&x).into()))
+        }).collect()
+    }
+
+    #[test]
+    fn transitions() {
+        let mut log = EventLog::new(3);
+        let t = Timestamp::now();
+        let cplx: ComplexId = serde_json::from_str("119").unwrap();
+        log.observe(t, outages(&[("EL293", "06/27/2023 09:00:00 AM", "Repair")]));
+        assert!(log.get(cplx, None).is_empty(), "first poll is the baseline");
+        log.observe(t, outages(&[
+            ("EL293", "06/28/2023 09:00:00 AM", "Capital Replacement"),
+            ("EL294", "06/27/2023 09:00:00 AM", "Repair"),
+            ("EL295?", "06/27/2023 09:00:00 AM", "Repair"),
+        ]));
+        let kinds = |log: &EventLog| log.get(cplx, None).into_iter()
+            .map(|e| (e.equipment.to_string(), e.change))
+            .collect::<Vec<_>>();
+        let evs = kinds(&log);
+        assert_eq!(evs.len(), 3);
+        assert!(matches!(evs[0], (ref id, OutageChange::ReturnEstimateChanged { .. }) if id == "EL293"));
+        assert!(matches!(evs[1], (_, OutageChange::ReasonChanged { .. })));
+        assert!(matches!(evs[2], (ref id, OutageChange::Started { planned: false, .. }) if id == "EL294"));
+        log.observe(t, outages(&[("EL295", "06/27/2023 09:00:00 AM", "Repair")]));
+        let evs = kinds(&log);
+        assert_eq!(evs.len(), 3, "capped");
+        assert!(matches!(evs[..], [
+            (_, OutageChange::Ended { .. }),
+            (_, OutageChange::Ended { .. }),
+            (ref id, OutageChange::Started { planned: true, .. }),
+        ] if id == "EL295"));
+        let el294 = "EL294".parse().unwrap();
+        assert_eq!(log.get(cplx, Some(&el294)).len(), 1);
+        let spans = log.spans();
+        assert_eq!(spans.len(), 3, "two ended and EL295 ongoing");
+        assert!(spans.iter().any(|s| s.end.is_none() && s.planned));
+    }
+
+    #[test]
+    fn restored() {
+        let t = Timestamp::now();
+        let ev = |equip: &str, change: serde_json::Value| {
+            let mut ev = serde_json::json!({"at": t, "equipment": equip, "complex_id": 119});
+            ev.as_object_mut().unwrap().extend(change.as_object().unwrap().clone());
+            serde_json::from_value(ev).unwrap()
+        };
+        // as the outage list would have said it
+        let at = |ret: &str| outages(&[("EL1", ret, "")]).into_values().next().unwrap().1;
+        let (before, after) = (at("06/27/2023 09:00:00 AM"), at("06/28/2023 09:00:00 AM"));
+        let started = serde_json::json!({
+            "kind": "started", "since": before.start, "est_return": before.est_return,
+            "reason": "Repair", "maintenance": false, "planned": false,
+        });
+        let mut log = EventLog::new(100);
+        log.restore(vec![
+            ev("EL293", started.clone()),
+            ev("EL294", started),
+            ev("EL294", serde_json::json!({
+                "kind": "ended", "since": before.start, "reason": "Repair", "maintenance": false, "planned": false,
+            })),
+            ev("EL293", serde_json::json!({
+                "kind": "return_estimate_changed", "from": before.est_return, "to": after.est_return,
+            })),
+        ]);
+        assert_eq!(log.spans().iter().filter(|s| s.end.is_none()).count(), 1, "EL293 is still out");
+        // not a baseline: EL293 is unchanged since the log's last word on it, and EL295 is new
+        let new = log.observe(t, outages(&[
+            ("EL293", "06/28/2023 09:00:00 AM", "Repair"),
+            ("EL295", "06/28/2023 09:00:00 AM", "Repair"),
+        ]));
+        assert!(matches!(&new[..], [e] if e.equipment.to_string() == "EL295" && matches!(e.change, OutageChange::Started { .. })), "{new:?}");
+        let new = log.observe(t, outages(&[("EL295", "06/28/2023 09:00:00 AM", "Repair")]));
+        assert!(matches!(&new[..], [e] if e.equipment.to_string() == "EL293" && matches!(e.change, OutageChange::Ended { .. })), "{new:?}");
+    }
+}
@@ -0,0 +1,257 @@
+//! A route's line diagram: each branch's stops in order, with the trains along it placed on them.
+
+use crate::{Timestamp, Humanize, api::ComplexId, manifest::StaticSchedule, msg::{PositionStatus, Route, StationId, StopId, TripDir, TripIdStr}};
+use super::LiveTrip;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderSource {
+    /// Every stop pattern in the static schedule
+    Static,
+    /// Inferred from the stop lists of trains running now, which can miss stops
+    Realtime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Placement {
+    /// On its way to the stop
+    Approaching,
+    At,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LineStop {
+    pub station: StationId,
+    pub complex_id: Option<ComplexId>,
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TrainOnLine {
+    pub trip: TripIdStr,
+    /// Index into the branch's `stops`
+    pub stop: usize,
+    pub placement: Placement,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<PositionStatus>,
+    /// At the stop it's placed at
+    pub arrival: Option<Timestamp>,
+    /// Stops to the train ahead on the branch; the biggest numbers are the gaps in service
+    pub gap: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub countdown: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LineDirection {
+    pub dir: TripDir,
+    pub source: OrderSource,
+    /// Branches share the stops before or after their junction, and trains there
+    pub branches: Vec<LineBranch>,
+}
+
+/// One way through the line, e.g. the A to Far Rockaway
+#[derive(Debug, Clone, Serialize)]
+pub struct LineBranch {
+    /// In travel order
+    pub stops: Vec<LineStop>,
+    /// Furthest along first
+    pub trains: Vec<TrainOnLine>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LineStatus {
+    pub route: Route,
+    pub directions: Vec<LineDirection>,
+}
+
+impl LineDirection {
+    pub fn new(route: &Route, dir: TripDir, schedule: Option<&StaticSchedule>, live: &[LiveTrip]) -> Self {
+        let planned: Vec<Vec<StationId>> = schedule.into_iter()
+            .flat_map(|s| s.stop_patterns(route, dir))
+            .map(stations)
+            .collect();
+        Self::with_patterns(dir, planned, live)
+    }
+    fn with_patterns(dir: TripDir, planned: Vec<Vec<StationId>>, live: &[LiveTrip]) -> Self {
+        let live: Vec<&LiveTrip> = live.iter().filter(|t| t.dir == dir).collect();
+        let remaining = |t: &LiveTrip| stations(t.remaining.iter().map(|s| &s.stop));
+        let (orders, source) = match planned.is_empty() {
+            false => (branches(planned), OrderSource::Static),
+            true => (branches(live.iter().map(|t| remaining(t)).collect()), OrderSource::Realtime),
+        };
+        // a train is on every branch its remaining stops are, else the one with most of them
+        let on: Vec<Vec<usize>> = live.iter()
+            .map(|t| {
+                let ahead = remaining(t);
+                let shared = |o: &Vec<StationId>| ahead.iter().filter(|s| o.contains(s)).count();
+                let all: Vec<usize> = (0..orders.len()).filter(|&b| shared(&orders[b]) == ahead.len()).collect();
+                match all.is_empty() {
+                    false => all,
+                    true => (0..orders.len()).max_by_key(|&b| (shared(&orders[b]), std::cmp::Reverse(b))).into_iter().collect(),
+                }
+            })
+            .collect();
+        let branches = orders.into_iter().enumerate()
+            .map(|(b, order)| {
+                let mut trains: Vec<TrainOnLine> = live.iter().zip(&on)
+                    .filter(|(_, on)| on.contains(&b))
+                    .filter_map(|(t, _)| place(&order, t))
+                    .collect();
+                trains.sort_by_key(|t| std::cmp::Reverse((t.stop, t.placement)));
+                for i in 1..trains.len() {
+                    trains[i].gap = Some(trains[i - 1].stop - trains[i].stop);
+                }
+                let stops = order.into_iter().map(|station| LineStop { station, complex_id: None, name: None }).collect();
+                LineBranch { stops, trains }
+            })
+            .collect();
+        LineDirection { dir, source, branches }
+    }
+}
+
+impl LineStatus {
+    /// Fill in station names, given each station's complex and name
+    pub fn label(&mut self, lookup: impl Fn(&StationId) -> Option<(ComplexId, String)>) {
+        for s in self.directions.iter_mut().flat_map(|d| d.branches.iter_mut()).flat_map(|b| b.stops.iter_mut()) {
+            if let Some((id, name)) = lookup(&s.station) {
+                s.complex_id = Some(id);
+                s.name = Some(name);
+            }
+        }
+    }
+    pub fn humanize(&mut self, h: &Humanize) {
+        for t in self.directions.iter_mut().flat_map(|d| d.branches.iter_mut()).flat_map(|b| b.trains.iter_mut()) {
+            t.countdown = t.arrival.map(|a| h.countdown(a));
+        }
+    }
+}
+
+fn stations<'a>(stops: impl IntoIterator<Item = &'a StopId>) -> Vec<StationId> {
+    stops.into_iter().filter_map(|s| s.station().ok()).collect()
+}
+
+/// Where a train is: its reported position, else the next stop it's predicted at
+fn place(order: &[StationId], t: &LiveTrip) -> Option<TrainOnLine> {
+    let index = |stop: &StopId| {
+        let station = stop.station().ok()?;
+        order.iter().position(|s| s == &station)
+    };
+    let (stop, placement, status) = match &t.position {
+        Some(p) => {
+            let placement = match p.status {
+                PositionStatus::At => Placement::At,
+                _ => Placement::Approaching,
+            };
+            (index(&p.stop)?, placement, Some(p.status))
+        },
+        None => (t.remaining.iter().find_map(|s| index(&s.stop))?, Placement::Approaching, None),
+    };
+    let arrival = t.remaining.iter()
+        .find(|s| index(&s.stop) == Some(stop))
+        .and_then(|s| s.arrival.or(s.departure));
+    Some(TrainOnLine { trip: t.trip, stop, placement, status, arrival, gap: None, countdown: None })
+}
+
+/// Sequences grouped into branches, each merged into one order. A sequence joins a branch if it
+/// only adds stops between the branch's or past its ends; one that leaves it partway, e.g. at a
+/// junction, starts another
+fn branches(mut seqs: Vec<Vec<StationId>>) -> Vec<Vec<StationId>> {
+    longest_first(&mut seqs);
+    let mut out: Vec<Vec<StationId>> = vec![];
+    for seq in seqs.into_iter().filter(|s| !s.is_empty()) {
+        match out.iter_mut().find(|b| fits(b, &seq)) {
+            Some(b) => *b = merge_orders(vec![std::mem::take(b), seq]),
+            None => out.push(seq),
+        }
+    }
+    out
+}
+
+fn fits(branch: &[StationId], seq: &[StationId]) -> bool {
+    let shared: Vec<(usize, usize)> = seq.iter().enumerate()
+        .filter_map(|(i, s)| Some((i, branch.iter().position(|b| b == s)?)))
+        .collect();
+    let (Some(&(first_in_seq, first)), Some(&(last_in_seq, last))) = (shared.first(), shared.last()) else { return false };
+    shared.windows(2).all(|w| w[0].1 < w[1].1)
+        && (first_in_seq == 0 || first == 0)
+        && (last_in_seq == seq.len() - 1 || last == branch.len() - 1)
+}
+
+fn longest_first(seqs: &mut [Vec<StationId>]) {
+    seqs.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.iter().map(AsRef::as_ref).cmp(b.iter().map(AsRef::<str>::as_ref))));
+}
+
+/// One order holding every sequence: the longest, with other sequences' stops
+/// inserted after the stop that precedes them there, e.g. a local's between an express's
+fn merge_orders(mut seqs: Vec<Vec<StationId>>) -> Vec<StationId> {
+    longest_first(&mut seqs);
+    let mut order: Vec<StationId> = vec![];
+    for seq in seqs {
+        let mut at = 0;
+        for station in seq {
+            match order.iter().position(|s| s == &station) {
+                Some(i) => at = i + 1,
+                None => {
+                    order.insert(at, station);
+                    at += 1;
+                },
+            }
+        }
+    }
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{branches, merge_orders, LineDirection, LiveTrip};
+    use crate::{Timestamp, msg::{StationId, TripDir}};
+    use serde_json::json;
+
+    fn seq(s: &str) -> Vec<StationId> {
+        s.split(' ').map(|id| id.parse().unwrap()).collect()
+    }
+
+    fn ids(order: &[StationId]) -> String {
+        order.iter().map(AsRef::as_ref).collect::<Vec<&str>>().join(" ")
+    }
+
+    #[test]
+    fn orders() {
+        let merged = merge_orders(vec![seq("A55 A57 A61"), seq("A53 A54 A55 A57"), seq("A57 A59 A61")]);
+        assert_eq!(ids(&merged), "A53 A54 A55 A57 A59 A61");
+        // the A's Lefferts and Far Rockaway branches split after A55
+        let split = branches(vec![
+            seq("A53 A54 A55 A57 A59 A61"),
+            seq("A53 A54 A55 H01 H02 H03 H04"),
+            seq("A54 A55 A57"),
+            seq("A53 A55 A57 A61"),
+        ]);
+        let split: Vec<String> = split.iter().map(|b| ids(b)).collect();
+        assert_eq!(split, ["A53 A54 A55 H01 H02 H03 H04", "A53 A54 A55 A57 A59 A61"]);
+    }
+
+    #[test]
+    fn gaps_by_branch() {
+        let trip = |id: &str, stops: &str| -> LiveTrip {
+            let remaining: Vec<_> = stops.split(' ').map(|s| json!({"stop": format!("{s}S")})).collect();
+            serde_json::from_value(json!({
+                "trip": id, "route": "A", "dir": "South", "last_seen": Timestamp::now(), "remaining": remaining,
+            })).unwrap()
+        };
+        let live = [
+            trip("far", "H02 H03 H04"),
+            trip("lefferts", "A59 A61"),
+            trip("trunk", "A54 A55"),
+        ];
+        let planned = [seq("A53 A54 A55 A57 A59 A61"), seq("A53 A54 A55 H01 H02 H03 H04")];
+        let line = LineDirection::with_patterns(TripDir::South, planned.to_vec(), &live);
+        let on: Vec<Vec<(&str, Option<usize>)>> = line.branches.iter()
+            .map(|b| b.trains.iter().map(|t| (t.trip.as_ref(), t.gap)).collect())
+            .collect();
+        // the trunk train is on both; each gap is to the train ahead on its own branch
+        assert_eq!(on, [vec![("far", None), ("trunk", Some(3))], vec![("lefferts", None), ("trunk", Some(3))]]);
+    }
+}
@@ -1,5 +1,12 @@
 
-use crate::api::{self, ComplexId};
+use crate::{Humanize, routes::{self, Agency}, api::{self, ComplexId}, manifest::{ManifestStops, StaticSchedule}, msg::{Route, StationId, TripDir}};
+use std::{path::Path, sync::Arc};
+
+pub mod snapshot;
+pub use snapshot::{Published, Snapshot, Version};
+
+pub mod persist;
+pub use persist::Saved;
 
 pub mod complex;
 pub use complex::{ComplexStates, ComplexMeta};
@@ -10,6 +17,32 @@ pub use trains::{ TrainStates, Upcoming };
 pub mod elevators;
 pub use elevators::{Elevator, ElevatorStates, ElevatorSummary};
 
+pub mod trips;
+pub use trips::{TripStates, LiveTrip, TripPosition, RemainingStop, TripQuery};
+
+pub mod line;
+pub use line::{LineStatus, LineDirection, LineBranch, LineStop, TrainOnLine};
+
+pub mod service;
+pub use service::{ServiceMonitor, ServiceAlert, Expectation};
+
+pub mod arrivals;
+pub use arrivals::{ArrivalGroup, ArrivalsQuery, Frequency};
+
+pub mod events;
+pub use events::{OutageEvent, OutageChange, OutageSink, JsonlSink};
+
+pub mod stats;
+pub use stats::{Reliability, ElevatorReliability, ComplexReliability, RankQuery};
+
+pub mod access;
+pub use access::{ComplexAccess, PlatformAccess, Reach};
+
+pub mod nearby;
+pub mod alternatives;
+pub use alternatives::{Alternative, Alternatives};
+pub use nearby::{Nearby, NearbyIndex, NearbyQuery};
+
 // pub mod upcoming;
 
 
@@ -17,8 +50,11 @@ pub use elevators::{Elevator, ElevatorStates, ElevatorSummary};
 #[derive(Clone)]
 pub struct /*United*/ States {
     pub trains: TrainStates,
+    pub trips: TripStates,
+    pub service: ServiceMonitor,
     pub elevators: ElevatorStates,
     pub complexes: ComplexStates,
+    pub nearby: NearbyIndex,
 }
 
 impl States {
@@ -30,15 +66,133 @@ impl States {
     ) -> Self {
         States {
             trains: TrainStates::new(&complexes),
-            elevators: ElevatorStates::new(elevators).with_outages(e_outages),
+            trips: TripStates::default(),
+            service: ServiceMonitor::default(),
+            elevators: ElevatorStates::new(elevators)
+                .with_crosswalk(api::Crosswalk::new(complexes, elevators))
+                .with_outages(e_outages),
             complexes: ComplexStates::new(&complexes, &entrances),
+            nearby: NearbyIndex::new(complexes, entrances),
+        }
+    }
+    pub fn with_schedule(mut self, schedule: Arc<StaticSchedule>) -> Self {
+        if !schedule.pathways().is_empty() {
+            self.elevators.add_pathways(&schedule, |s| self.complexes.complex_of(s));
+        }
+        self.service = self.service.with_schedule(schedule.clone());
+        self.trains = self.trains.with_schedule(schedule);
+        self
+    }
+    /// Commuter rail stations from the agency's static stops, so its realtime feed has somewhere to go
+    pub fn with_commuter(mut self, agency: Agency, stops: &ManifestStops) -> Self {
+        let placed = self.complexes.add_commuter(agency, stops);
+        let coords: Vec<_> = placed.iter()
+            .filter_map(|(_, id)| Some((self.complexes.get_ref(*id)?.coord(), *id)))
+            .collect();
+        self.nearby.add_stations(coords);
+        self.trains.add_stops(placed);
+        self
+    }
+    /// The newest of the live state's versions; any change to what's served bumps it
+    pub fn version(&self) -> Version {
+        [self.trips.version(), self.service.version(), self.elevators.version()].into_iter()
+            .fold(self.trains.version(), Version::max)
+    }
+    /// A complex's upcoming trains by route and direction, the next `n` of each
+    pub fn arrivals(&self, id: ComplexId, n: usize) -> Option<Vec<ArrivalGroup>> {
+        Some(arrivals::group(self.trains.get(id)?, n))
+    }
+    /// A train by trip id, with station names and optionally its ETA to `to`.
+    /// None if the trip's unknown, or it doesn't stop at `to`
+    pub fn trip(&self, trip: &str, to: Option<&StationId>) -> Option<LiveTrip> {
+        let mut live = self.trips.get(trip)?;
+        if let Some(to) = to {
+            live.to(to).then_some(())?;
+        }
+        live.label(|s| self.station_name(s));
+        Some(live)
+    }
+    /// A route's stops in order with its trains placed along them, by direction
+    pub fn line(&self, route: &Route) -> Option<LineStatus> {
+        let route = routes::catalog().canonical(route)?;
+        let live = self.trips.on_route(&route);
+        let directions = [TripDir::North, TripDir::South].into_iter()
+            .map(|dir| LineDirection::new(&route, dir, self.trains.schedule(), &live))
+            .collect();
+        let mut line = LineStatus { route, directions };
+        line.label(|s| self.station_name(s));
+        Some(line)
+    }
+    fn station_name(&self, station: &StationId) -> Option<(ComplexId, String)> {
+        let id = self.trains.complex_of(station)?;
+        Some((id, self.complexes.get_ref(id)?.name().to_owned()))
+    }
+    /// Replace the snapshot at `path` with the live state
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        Saved::new(self.trains.all(), self.trips.all(), self.service.baseline()).save(path)
+    }
+    /// Serve what was saved until the feeds catch up; trains and trips are marked stale till then
+    pub fn restore(&self, mut saved: Saved) {
+        self.trains.restore(saved.trains());
+        self.trips.restore(saved.trips());
+        self.service.restore_baseline(std::mem::take(&mut saved.baseline));
+    }
+    /// Keep outage events in `sink` too, starting from what it already has
+    pub fn with_outage_log(mut self, sink: Arc<JsonlSink>) -> Self {
+        let history = sink.read_all();
+        self.elevators = self.elevators.with_sink(sink, history);
+        self
+    }
+    /// Complexes near a point, nearest first
+    pub fn nearby(&self, q: &NearbyQuery) -> Vec<Nearby> {
+        if q.validate().is_err() {
+            return vec![];
+        }
+        self.nearby.complexes(q).into_iter()
+            .filter_map(|(distance, id)| {
+                let meta = self.complexes.get_ref(id)?;
+                Some(meta.nearby(id, distance, (q.lat, q.lon), |stations| self.elevators.street_elevators(id, stations)))
+            })
+            .filter(|n| !q.accessible || n.elevator_entrance.as_ref().is_some_and(|e| e.working == Some(true)))
+            .take(q.limit)
+            .collect()
+    }
+    /// Outage stats for a complex and each of its elevators
+    pub fn reliability(&self, id: ComplexId) -> Option<ComplexReliability> {
+        let mut r = self.elevators.reliability(id)?;
+        r.equipment.iter_mut().for_each(|e| self.label(e));
+        Some(r)
+    }
+    /// The least reliable elevators, optionally in one borough or on one route
+    pub fn worst_elevators(&self, q: &RankQuery) -> Vec<ElevatorReliability> {
+        let route = q.route.as_ref().map(|r| routes::catalog().canonical(r).unwrap_or(*r));
+        let borough = |id| self.complexes.get_ref(id).map(ComplexMeta::borough);
+        let mut ranked = self.elevators.rank(q.days, |e| {
+            (q.escalators || !e.is_escalator())
+                && q.borough.as_ref().is_none_or(|b| borough(e.complex_id()).is_some_and(|x| x.eq_ignore_ascii_case(b)))
+                && route.as_ref().is_none_or(|r| e.lines().iter().any(|l| routes::catalog().canonical(l).as_ref() == Some(r)))
+        });
+        ranked.truncate(q.limit);
+        ranked.iter_mut().for_each(|e| self.label(e));
+        ranked
+    }
+    fn label(&self, e: &mut ElevatorReliability) {
+        if let Some(meta) = self.complexes.get_ref(e.complex_id) {
+            e.station = Some(meta.name().to_owned());
+            e.borough = Some(meta.borough().to_owned());
         }
     }
     pub fn get_full(&self, id: ComplexId) -> Option<ComplexFull> {
         let meta = self.complexes.get(id);
-        let elevators = self.elevators.get(id)?;
+        // commuter rail stations often have no elevator data
+        let elevators = match (self.elevators.get(id), &meta) {
+            (Some(e), _) => e,
+            (None, Some(_)) => vec![],
+            (None, None) => return None,
+        };
         let upcoming = self.trains.get(id)?;
-        Some(ComplexFull { meta, upcoming, elevators })
+        let service = meta.as_ref().map(|m| self.service.on_routes(m.routes())).unwrap_or_default();
+        Some(ComplexFull { meta, upcoming, elevators, service })
     }
 }
 
@@ -47,5 +201,69 @@ pub struct ComplexFull {
     meta: Option<ComplexMeta>,
     upcoming: Vec<Upcoming>,
     elevators: Vec<Elevator>,
+    /// Gaps in service on the complex's routes
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    service: Vec<ServiceAlert>,
 }
 
+impl ComplexFull {
+    pub fn humanize(&mut self, h: &Humanize) {
+        self.upcoming.iter_mut().for_each(|u| u.humanize(h));
+        self.service.iter_mut().for_each(|a| a.humanize(h));
+    }
+}
+
+fn is_false(b: &bool) -> bool {
+    !b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{States, NearbyQuery};
+    use crate::api::fixtures::{complex, equipment, outage, entrance};
+    use serde_json::json;
+
+    #[test]
+    fn nearby_entrances() {
+        // 14 St-Union Sq: an outage at the 4/5/6's street elevator says nothing about the L's
+        let complexes = [
+            complex(json!({
+                "complex_id": "602", "stop_name": "14 St-Union Sq", "gtfs_stop_ids": "L03; 635",
+                "daytime_routes": "L 4 5 6", "latitude": "40.734673", "longitude": "-73.989951",
+            })),
+            // an elevator entrance, but no equipment listed to say whether it works
+            complex(json!({
+                "complex_id": "603", "stop_name": "Nowhere Listed", "gtfs_stop_ids": "R20",
+                "daytime_routes": "N", "latitude": "40.7345", "longitude": "-73.9899",
+            })),
+        ];
+        let street = |id: &str, stop: &str| equipment(json!({
+            "equipmentno": id, "stationcomplexid": "602", "elevatorsgtfsstopid": stop,
+            "shortdescription": "Street to mezzanine",
+        }));
+        let equipment = [street("EL1", "L03"), street("EL2", "635")];
+        let outages = [outage(json!({"equipment": "EL2", "station": "14 St-Union Sq"}))];
+        let at = |stop: &str, lat: &str| entrance(json!({
+            "complex_id": "602", "gtfs_stop_id": stop, "entrance_latitude": lat, "entrance_longitude": "-73.9899",
+        }));
+        let entrances = [at("L03", "40.7350"), at("635", "40.7340"), entrance(json!({
+            "complex_id": "603", "gtfs_stop_id": "R20", "entrance_latitude": "40.7345", "entrance_longitude": "-73.9899",
+        }))];
+        let state = States::new(&complexes, &equipment, &outages, &entrances);
+        let q = |lat| NearbyQuery { lat, lon: -73.9899, radius: 500.0, accessible: false, limit: 5 };
+        let near = state.nearby(&q(40.7351));
+        let e = near[0].elevator_entrance.as_ref().unwrap();
+        assert_eq!((e.equipment[0].as_ref(), e.working), ("EL1", Some(true)));
+        let near = state.nearby(&q(40.7339));
+        let e = near[0].elevator_entrance.as_ref().unwrap();
+        assert_eq!((e.equipment[0].as_ref(), e.working), ("EL2", Some(false)));
+        assert!(state.nearby(&NearbyQuery { lat: f64::NAN, ..q(0.0) }).is_empty());
+        let ids = |lat| -> Vec<String> {
+            let all = state.nearby(&q(lat));
+            assert!(all.iter().any(|n| n.complex_id.to_string() == "603" && n.elevator_entrance.as_ref().unwrap().working.is_none()));
+            state.nearby(&NearbyQuery { accessible: true, ..q(lat) }).iter().map(|n| n.complex_id.to_string()).collect()
+        };
+        assert_eq!(ids(40.7351), ["602"], "an elevator we can't vouch for isn't accessible");
+        assert!(ids(40.7339).is_empty(), "the closest elevator is out");
+    }
+}
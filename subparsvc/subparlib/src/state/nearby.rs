@@ -0,0 +1,253 @@
+use crate::api::{self, ComplexId, EquipmentId};
+use serde::Serialize;
+use std::{sync::Arc, collections::{HashMap, HashSet}};
+
+/// (latitude, longitude) in degrees
+pub type Coord = (f64, f64);
+
+const EARTH_RADIUS_M: f64 = 6_371_000.0;
+// no two points are farther apart
+const HALF_EARTH_M: f64 = std::f64::consts::PI * EARTH_RADIUS_M + 1.0;
+const M_PER_DEG_LAT: f64 = 111_320.0;
+// ~550m; a station plus its entrances usually spans one or two cells
+const CELL_DEG: f64 = 0.005;
+
+/// Great-circle distance in meters
+pub fn haversine((lat1, lon1): Coord, (lat2, lon2): Coord) -> f64 {
+    let (p1, p2) = (lat1.to_radians(), lat2.to_radians());
+    let dp = p2 - p1;
+    let dl = (lon2 - lon1).to_radians();
+    let a = (dp / 2.0).sin().powi(2) + p1.cos() * p2.cos() * (dl / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_M * a.sqrt().asin()
+}
+
+/// Buckets points into a lat/lon grid for k-nearest and radius queries
+#[derive(Debug)]
+pub struct GridIndex<T> {
+    points: Vec<(Coord, T)>,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+}
+
+impl<T> GridIndex<T> {
+    pub fn new(points: Vec<(Coord, T)>) -> Self {
+        let mut cells = HashMap::<_, Vec<usize>>::new();
+        for (i, (coord, _)) in points.iter().enumerate() {
+            cells.entry(cell(*coord)).or_default().push(i);
+        }
+        GridIndex { points, cells }
+    }
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+    /// Everything within `meters`, nearest first. Nothing is near an invalid coordinate
+    pub fn within(&self, at: Coord, meters: f64) -> Vec<(f64, &T)> {
+        if !valid(at) || meters.is_nan() {
+            return vec![];
+        }
+        let meters = meters.min(HALF_EARTH_M);
+        let dlat = meters / M_PER_DEG_LAT;
+        let dlon = meters / (M_PER_DEG_LAT * at.0.to_radians().cos().max(0.01));
+        let (lat, lon) = ((at.0 - dlat, at.0 + dlat), (at.1 - dlon, at.1 + dlon));
+        // over a pole or the antimeridian, any longitude could be in range
+        let lon = match lat.0 < -90.0 || lat.1 > 90.0 || lon.0 < -180.0 || lon.1 > 180.0 {
+            true => (-180.0, 180.0),
+            false => lon,
+        };
+        let (lo, hi) = (cell((lat.0, lon.0)), cell((lat.1, lon.1)));
+        let span = (hi.0 as i64 - lo.0 as i64 + 1) * (hi.1 as i64 - lo.1 as i64 + 1);
+        // past a point it's cheaper to check every occupied cell than every cell in range
+        let cells: Vec<&Vec<usize>> = match span > self.cells.len() as i64 {
+            true => self.cells.iter()
+                .filter(|((x, y), _)| (lo.0..=hi.0).contains(x) && (lo.1..=hi.1).contains(y))
+                .map(|(_, v)| v)
+                .collect(),
+            false => (lo.0..=hi.0)
+                .flat_map(|x| (lo.1..=hi.1).map(move |y| (x, y)))
+                .filter_map(|c| self.cells.get(&c))
+                .collect(),
+        };
+        let mut hits: Vec<(f64, &T)> = cells.into_iter()
+            .flatten()
+            .map(|&i| (haversine(at, self.points[i].0), &self.points[i].1))
+            .filter(|(d, _)| *d <= meters)
+            .collect();
+        hits.sort_by(|a, b| a.0.total_cmp(&b.0));
+        hits
+    }
+    /// The `k` nearest, nearest first
+    pub fn nearest(&self, at: Coord, k: usize) -> Vec<(f64, &T)> {
+        if self.is_empty() || !valid(at) {
+            return vec![];
+        }
+        // grow the search until it holds k points, the whole index, or the whole earth
+        let mut meters = CELL_DEG * M_PER_DEG_LAT;
+        loop {
+            let mut hits = self.within(at, meters);
+            if hits.len() >= k || hits.len() == self.len() || meters >= HALF_EARTH_M {
+                hits.truncate(k);
+                return hits;
+            }
+            meters = (meters * 2.0).min(HALF_EARTH_M);
+        }
+    }
+}
+
+fn valid((lat, lon): Coord) -> bool {
+    (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon)
+}
+
+fn cell((lat, lon): Coord) -> (i32, i32) {
+    ((lat / CELL_DEG).floor() as i32, (lon / CELL_DEG).floor() as i32)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Place {
+    Station(ComplexId),
+    Entrance(ComplexId),
+}
+
+impl Place {
+    fn complex_id(&self) -> ComplexId {
+        match *self {
+            Place::Station(c) | Place::Entrance(c) => c,
+        }
+    }
+}
+
+/// Stations and their entrances, by location
+#[derive(Clone)]
+pub struct NearbyIndex {
+    index: Arc<GridIndex<Place>>,
+}
+
+/// A complex near the query point
+#[derive(Debug, Clone, Serialize)]
+pub struct Nearby {
+    pub complex_id: ComplexId,
+    pub name: String,
+    /// Meters to the station or its closest entrance
+    pub distance: f64,
+    pub ada: api::AdaStatus,
+    pub routes: Vec<crate::msg::Route>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entrance: Option<NearbyEntrance>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub elevator_entrance: Option<NearbyEntrance>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NearbyEntrance {
+    pub coord: Coord,
+    pub distance: f64,
+    pub entrance_type: String,
+    /// For elevator entrances: the street elevators at the entrance's own station
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub equipment: Vec<EquipmentId>,
+    /// Whether they're all in service; None if we don't know which they are
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub working: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+pub struct NearbyQuery {
+    pub lat: f64,
+    pub lon: f64,
+    /// Meters
+    #[serde(default = "NearbyQuery::default_radius")]
+    pub radius: f64,
+    /// Only complexes with a working elevator entrance
+    #[serde(default)]
+    pub accessible: bool,
+    #[serde(default = "NearbyQuery::default_limit")]
+    pub limit: usize,
+}
+
+impl NearbyQuery {
+    pub const MAX_RADIUS: f64 = 5000.0;
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if !valid((self.lat, self.lon)) {
+            return Err("bad lat or lon");
+        }
+        if !(0.0..=Self::MAX_RADIUS).contains(&self.radius) {
+            return Err("bad radius (max 5000m)");
+        }
+        Ok(())
+    }
+    fn default_radius() -> f64 {
+        800.0
+    }
+    fn default_limit() -> usize {
+        10
+    }
+}
+
+impl NearbyIndex {
+    pub fn new(cplxs: &[api::ComplexInfo], entrs: &[api::SubwayEntrance]) -> Self {
+        let stations = cplxs.iter()
+            .map(|c| ((c.latitude, c.longitude), Place::Station(c.complex_id)));
+        let entrances = entrs.iter()
+            .map(|e| ((e.entrance_latitude, e.entrance_longitude), Place::Entrance(e.complex_id)));
+        let points = stations.chain(entrances).collect();
+        NearbyIndex { index: Arc::new(GridIndex::new(points)) }
+    }
+    /// More stations, e.g. commuter rail's
+    pub fn add_stations(&mut self, stations: impl IntoIterator<Item = (Coord, ComplexId)>) {
+        let mut points = self.index.points.clone();
+        points.extend(stations.into_iter().map(|(coord, id)| (coord, Place::Station(id))));
+        self.index = Arc::new(GridIndex::new(points));
+    }
+    /// Complexes within the query radius, nearest first, as (distance, complex)
+    pub(super) fn complexes(&self, q: &NearbyQuery) -> Vec<(f64, ComplexId)> {
+        let mut seen = HashSet::new();
+        let mut out = vec![];
+        for (d, place) in self.index.within((q.lat, q.lon), q.radius) {
+            let id = place.complex_id();
+            if seen.insert(id) {
+                out.push((d, id));
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GridIndex, NearbyQuery, haversine};
+
+    #[test]
+    fn grid() {
+        // Times Sq, Grand Central, Union Sq, Coney Island
+        let idx = GridIndex::new(vec![
+            ((40.755477, -73.987691), "TSQ"),
+            ((40.751776, -73.976848), "GCT"),
+            ((40.735736, -73.990568), "USQ"),
+            ((40.577422, -73.981233), "CI"),
+        ]);
+        let d = haversine((40.755477, -73.987691), (40.751776, -73.976848));
+        assert!((d - 995.0).abs() < 10.0, "{d}");
+        let near = |k| idx.nearest((40.7540, -73.9840), k).into_iter().map(|(_, n)| *n).collect::<Vec<_>>();
+        assert_eq!(near(2), ["TSQ", "GCT"]);
+        assert_eq!(near(9), ["TSQ", "GCT", "USQ", "CI"]);
+        let within: Vec<_> = idx.within((40.7540, -73.9840), 1000.0).into_iter().map(|(_, n)| *n).collect();
+        assert_eq!(within, ["TSQ", "GCT"]);
+    }
+
+    #[test]
+    fn far_and_invalid() {
+        let idx = GridIndex::new(vec![((40.755477, -73.987691), "TSQ")]);
+        // Sydney: the search has to grow most of the way around the earth
+        assert_eq!(idx.nearest((-33.8688, 151.2093), 5).len(), 1);
+        assert_eq!(idx.within((-33.8688, 151.2093), f64::INFINITY).len(), 1);
+        for at in [(f64::NAN, -73.98), (40.75, f64::INFINITY), (91.0, 0.0)] {
+            assert!(idx.nearest(at, 1).is_empty());
+            assert!(idx.within(at, 1000.0).is_empty());
+        }
+        let q = |lat, lon, radius| NearbyQuery { lat, lon, radius, accessible: false, limit: 10 }.validate();
+        assert!(q(40.75, -73.98, 800.0).is_ok());
+        assert!(q(f64::NAN, -73.98, 800.0).is_err());
+        assert!(q(40.75, -73.98, f64::INFINITY).is_err());
+    }
+}
@@ -0,0 +1,108 @@
+//! Live state saved to a file now and then, so a restart can serve it at once.
+
+use crate::{Timestamp, api, routes, msg::{Route, TripDir}};
+use super::{Upcoming, LiveTrip};
+use anyhow::{Context as _, bail};
+use serde::{Serialize, Deserialize};
+use std::{fs, io, path::Path};
+
+/// Bump when anything saved changes shape; snapshots with another schema are ignored
+pub const SCHEMA: u32 = 1;
+
+/// What `States` can't quickly get back from the dataset cache and static GTFS
+#[derive(Serialize, Deserialize)]
+pub struct Saved {
+    schema: u32,
+    pub saved: Timestamp,
+    /// With their feeds, which the API doesn't show
+    pub(super) trains: Vec<(String, Upcoming)>,
+    pub(super) trips: Vec<(String, LiveTrip)>,
+    /// Trains usually running on each route, which takes a week to learn
+    pub(super) baseline: Vec<(Route, TripDir, Vec<Option<f32>>)>,
+}
+
+#[derive(Deserialize)]
+struct Header {
+    schema: u32,
+}
+
+impl Saved {
+    pub(super) fn new(trains: Vec<Upcoming>, trips: Vec<LiveTrip>, baseline: Vec<(Route, TripDir, Vec<Option<f32>>)>) -> Self {
+        Saved {
+            schema: SCHEMA,
+            saved: Timestamp::now(),
+            trains: trains.into_iter().map(|u| (u.feed.to_owned(), u)).collect(),
+            trips: trips.into_iter().map(|t| (t.feed.to_owned(), t)).collect(),
+            baseline,
+        }
+    }
+    /// None if there isn't one; an error if it's unreadable or from another schema
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Option<Saved>> {
+        let path = path.as_ref();
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("read {}", path.display())),
+        };
+        let header: Header = serde_json::from_str(&text).context("no schema")?;
+        if header.schema != SCHEMA {
+            bail!("schema v{} isn't v{SCHEMA}", header.schema);
+        }
+        Ok(Some(serde_json::from_str(&text)?))
+    }
+    /// Replaces `path` whole, so a crash mid-save leaves the last one
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        api::write_atomic_blocking(path.as_ref(), &serde_json::to_vec(self)?)
+    }
+    /// Those from feeds we still listen to
+    pub(super) fn trains(&mut self) -> Vec<Upcoming> {
+        self.trains.drain(..).filter_map(|(f, u)| Some(Upcoming { feed: feed(&f)?, ..u })).collect()
+    }
+    pub(super) fn trips(&mut self) -> Vec<LiveTrip> {
+        self.trips.drain(..).filter_map(|(f, t)| Some(LiveTrip { feed: feed(&f)?, ..t })).collect()
+    }
+}
+
+fn feed(name: &str) -> Option<&'static str> {
+    routes::catalog().feed_names().into_iter().find(|&f| f == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Saved, SCHEMA};
+    use crate::{Timestamp, msg::{Route, TripDir}};
+    use crate::state::Upcoming;
+    use std::fs;
+
+    #[test]
+    fn round_trip() {
+        let now = Timestamp::now();
+        let train = Upcoming {
+            trip: "100000_L..N".parse().unwrap(),
+            route: Route::make("L"),
+            dir: TripDir::North,
+            headsign: None,
+            stop: "L06".parse().unwrap(),
+            arrival: now,
+            message: now,
+            scheduled: None,
+            delay: Some(60),
+            text: None,
+            stale: false,
+            feed: "l",
+        };
+        let gone = Upcoming { feed: "mystery", ..train.clone() };
+        let path = std::env::temp_dir().join(format!("subpar-state-{}.json", std::process::id()));
+        Saved::new(vec![train, gone], vec![], vec![(Route::make("L"), TripDir::South, vec![Some(4.5)])]).save(&path).unwrap();
+        let mut saved = Saved::load(&path).unwrap().unwrap();
+        let trains = saved.trains();
+        assert_eq!(trains.len(), 1, "trains from unknown feeds are dropped");
+        assert_eq!((trains[0].feed, trains[0].delay), ("l", Some(60)));
+        assert_eq!(saved.baseline[0].2, [Some(4.5)]);
+
+        fs::write(&path, format!(r#"{{"schema": {}, "saved": "x"}}"#, SCHEMA + 1)).unwrap();
+        assert!(Saved::load(&path).is_err(), "other schemas are rejected");
+        fs::remove_file(&path).unwrap();
+        assert!(Saved::load(&path).unwrap().is_none());
+    }
+}
@@ -0,0 +1,283 @@
+//! Routes that stopped running. The feed just stops listing their trips, so gaps are
+//! found by comparing what's running with what the schedule, or a usual week, expects.
+
+use crate::{Timestamp, Humanize, NYC, routes::{self, Agency}, client::Response, manifest::StaticSchedule, msg::{Route, TripDir, Update}};
+use super::{trains::STALE, snapshot::{Published, Version}};
+use chrono::{Datelike, Timelike};
+use serde::Serialize;
+use std::{time::Duration, sync::{Arc, Mutex}, collections::HashMap};
+
+/// No trains for this long, when some were expected, is a gap in service
+const MISSING: Duration = Duration::from_secs(20 * 60);
+/// Fewer scheduled trips than this in the window could just be a quiet hour
+const MIN_SCHEDULED: usize = 2;
+/// Likewise for trains usually running
+const MIN_BASELINE: f32 = 1.0;
+/// The baseline's resolution
+const SLOT_MINS: u32 = 10;
+const SLOTS: usize = 7 * 24 * 60 / SLOT_MINS as usize;
+
+type Key = (Route, TripDir);
+/// Each route's (sum, count) of samples
+type Samples = HashMap< Key, (f32, u32) >;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Expectation {
+    /// Trips the static schedule has running in the window
+    Schedule,
+    /// Trains usually running at this time of the week
+    Baseline,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ServiceAlert {
+    pub route: Route,
+    pub dir: TripDir,
+    /// None if there hasn't been one since we started
+    pub last_seen: Option<Timestamp>,
+    /// Trains expected in the window
+    pub expected: f32,
+    pub basis: Expectation,
+    /// e.g. "no northbound 4 trains in the last 20 minutes", with `?text=true`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// The last train, or when we started watching if there hasn't been one
+    pub since: Timestamp,
+    #[serde(skip)]
+    agency: Agency,
+}
+
+#[derive(Clone)]
+pub struct ServiceMonitor {
+    /// Only writers lock this; readers get `published`
+    inner: Arc<Mutex< Monitor >>,
+    published: Published< Vec<ServiceAlert> >,
+    schedule: Option<Arc<StaticSchedule>>,
+}
+
+struct Monitor {
+    started: Timestamp,
+    /// Each feed's latest batch time
+    feeds: HashMap< &'static str, Timestamp >,
+    /// Trips in each feed's latest batch
+    active: HashMap< &'static str, HashMap<Key, usize> >,
+    last_seen: HashMap< Key, Timestamp >,
+    baseline: Baseline,
+}
+
+/// Trains usually running, by route, direction, and time of the week
+#[derive(Default)]
+struct Baseline {
+    slots: HashMap< Key, Vec<Option<f32>> >,
+    /// The slot being sampled now, and its samples so far
+    pending: Option<(usize, Samples)>,
+}
+
+impl Default for ServiceMonitor {
+    fn default() -> Self {
+        let inner = Monitor {
+            started: Timestamp::now(),
+            feeds: HashMap::new(),
+            active: HashMap::new(),
+            last_seen: HashMap::new(),
+            baseline: Baseline::default(),
+        };
+        ServiceMonitor { inner: Arc::new(Mutex::new(inner)), published: Published::new(vec![]), schedule: None }
+    }
+}
+
+impl ServiceMonitor {
+    /// Subway routes are held to the schedule instead of the baseline
+    pub fn with_schedule(mut self, schedule: Arc<StaticSchedule>) -> Self {
+        self.schedule = Some(schedule);
+        self
+    }
+    pub fn update(&self, rsp: &Response) {
+        let (feed, time) = (rsp.feed.name(), rsp.data.time);
+        let mut active: HashMap<Key, usize> = HashMap::new();
+        for elem in rsp.data.msgs.iter().flatten() {
+            let Update::Schedule(s) = elem else { continue };
+            if s.stops().is_empty() {
+                continue
+            }
+            let id = s.trip();
+            let route = routes::catalog().canonical(&id.route()).unwrap_or_else(|| id.route());
+            *active.entry((route, id.dir())).or_default() += 1;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        if inner.feeds.get(feed).is_some_and(|&last| time <= last) {
+            return;
+        }
+        inner.feeds.insert(feed, time);
+        for key in active.keys() {
+            inner.last_seen.insert(*key, time);
+        }
+        inner.active.insert(feed, active);
+    }
+    /// Compare what's running with what's expected and publish the gaps if they changed. Run periodically
+    pub fn check(&self, now: Timestamp) {
+        let from = now - MISSING;
+        let scheduled = self.schedule.as_ref().map(|s| {
+            let mut counts: HashMap<Key, usize> = HashMap::new();
+            for ((route, dir), n) in s.running(from, now) {
+                let route = routes::catalog().canonical(&route).unwrap_or(route);
+                *counts.entry((route, dir)).or_default() += n;
+            }
+            counts
+        });
+        let mut inner = self.inner.lock().unwrap();
+        let mut counts = HashMap::new();
+        let mut alerts = vec![];
+        for info in routes::catalog().iter() {
+            let Some(feed) = info.feed else { continue };
+            // a quiet feed says nothing about its routes
+            if inner.feeds.get(feed).is_none_or(|&t| t < now - STALE) {
+                continue
+            }
+            for dir in [TripDir::North, TripDir::South] {
+                let key = (info.route(), dir);
+                let active = inner.active.get(feed).and_then(|a| a.get(&key)).copied().unwrap_or(0);
+                counts.insert(key, active);
+                let last_seen = inner.last_seen.get(&key).copied();
+                let since = last_seen.unwrap_or(inner.started);
+                if active > 0 || since > from {
+                    continue
+                }
+                let expected = match &scheduled {
+                    Some(s) if info.agency == Agency::Nyct => Some(s.get(&key).copied().unwrap_or(0) as f32)
+                        .filter(|&n| n >= MIN_SCHEDULED as f32)
+                        .map(|n| (n, Expectation::Schedule)),
+                    _ => inner.baseline.expected(&key, from, now)
+                        .filter(|&n| n >= MIN_BASELINE)
+                        .map(|n| (n, Expectation::Baseline)),
+                };
+                if let Some((expected, basis)) = expected {
+                    alerts.push(ServiceAlert { route: key.0, dir, last_seen, expected, basis, message: None, since, agency: info.agency });
+                }
+            }
+        }
+        inner.baseline.sample(now, counts);
+        drop(inner);
+        // a gap's expected count drifts as the window moves; that's not news
+        let gap = |a: &ServiceAlert| (a.route, a.dir, a.basis, a.last_seen);
+        if !alerts.iter().map(gap).eq(self.published.load().data.iter().map(gap)) {
+            self.published.publish(alerts);
+        }
+    }
+    pub fn get(&self) -> Vec<ServiceAlert> {
+        self.published.load().data.clone()
+    }
+    /// Gaps on any of `routes`, e.g. those serving a complex
+    pub fn on_routes(&self, routes: &[Route]) -> Vec<ServiceAlert> {
+        let routes: Vec<Route> = routes.iter().map(|r| routes::catalog().canonical(r).unwrap_or(*r)).collect();
+        self.published.load().data.iter().filter(|a| routes.contains(&a.route)).cloned().collect()
+    }
+    pub fn version(&self) -> Version {
+        self.published.version()
+    }
+    /// Trains usually running on each route and direction, per 10 minutes of the week from Monday
+    pub fn baseline(&self) -> Vec<(Route, TripDir, Vec<Option<f32>>)> {
+        let inner = self.inner.lock().unwrap();
+        inner.baseline.slots.iter().map(|((route, dir), slots)| (*route, *dir, slots.clone())).collect()
+    }
+    /// A baseline from before a restart, since it takes a week to learn
+    pub fn restore_baseline(&self, saved: Vec<(Route, TripDir, Vec<Option<f32>>)>) {
+        let mut inner = self.inner.lock().unwrap();
+        for (route, dir, slots) in saved.into_iter().filter(|(_, _, s)| s.len() == SLOTS) {
+            inner.baseline.slots.insert((route, dir), slots);
+        }
+    }
+}
+
+impl ServiceAlert {
+    pub fn humanize(&mut self, h: &Humanize) {
+        self.message = Some(h.no_trains(self.route.as_ref(), self.dir, self.agency, h.now.seconds_since(&self.since)));
+    }
+}
+
+impl Baseline {
+    fn slot(t: Timestamp) -> usize {
+        let local = t.in_tz(NYC);
+        let mins = (local.weekday().num_days_from_monday() * 24 + local.hour()) * 60 + local.minute();
+        (mins / SLOT_MINS) as usize
+    }
+    /// Samples are averaged into their slot once it's over, so a slot never expects what it's seeing now
+    fn sample(&mut self, now: Timestamp, counts: HashMap<Key, usize>) {
+        let slot = Baseline::slot(now);
+        if let Some((done, samples)) = self.pending.take_if(|(s, _)| *s != slot) {
+            for (key, (sum, n)) in samples {
+                let mean = sum / n as f32;
+                let usual = &mut self.slots.entry(key).or_insert_with(|| vec![None; SLOTS])[done];
+                *usual = Some(usual.map_or(mean, |old| (old + mean) / 2.0));
+            }
+        }
+        let (_, samples) = self.pending.get_or_insert_with(|| (slot, Samples::new()));
+        for (key, n) in counts {
+            let (sum, count) = samples.entry(key).or_default();
+            *sum += n as f32;
+            *count += 1;
+        }
+    }
+    /// The fewest trains usually running in any slot of `[from, to]`; None until they all have history
+    fn expected(&self, key: &Key, from: Timestamp, to: Timestamp) -> Option<f32> {
+        let slots = self.slots.get(key)?;
+        let (first, last) = (Baseline::slot(from), Baseline::slot(to));
+        let n = (last + SLOTS - first) % SLOTS + 1;
+        (0..n).try_fold(f32::MAX, |min, i| Some(min.min(slots[(first + i) % SLOTS]?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ServiceMonitor, Expectation, Baseline, SLOTS};
+    use crate::{Feed, Timestamp, Humanize, client::Response, msg::{Batch, Route, Schedule, StopPlan, Times, TripDir, TripId, Update}};
+    use std::time::Duration;
+
+    #[test]
+    fn gaps() {
+        let now = Timestamp::now();
+        let monitor = ServiceMonitor::default();
+        {
+            let mut inner = monitor.inner.lock().unwrap();
+            inner.started = now - Duration::from_secs(3600);
+            for dir in [TripDir::North, TripDir::South] {
+                inner.baseline.slots.insert((Route::make("L"), dir), vec![Some(6.0); SLOTS]);
+            }
+        }
+        let trip = TripId::parse("100000_L..N", now.date()).unwrap();
+        let stop = StopPlan::new("L06N".parse().unwrap(), Times::new(Some(now + Duration::from_secs(60)), None).unwrap());
+        let msgs = vec![Ok(Update::Schedule(Schedule::new(trip, now, vec![stop])))];
+        monitor.update(&Response::new(Batch { time: now, msgs }, Feed::from_static("l"), &[], now, now));
+        monitor.check(now);
+        let alerts = monitor.get();
+        assert_eq!(alerts.len(), 1, "northbound is running, and there's no baseline for other routes yet");
+        assert_eq!((alerts[0].dir, alerts[0].basis), (TripDir::South, Expectation::Baseline));
+        assert_eq!(alerts[0].message, None, "only with ?text=true");
+        let mut alert = alerts[0].clone();
+        alert.humanize(&Humanize { now, ..Humanize::default() });
+        assert_eq!(alert.message.as_deref(), Some("no southbound L trains in the last 60 minutes"));
+        assert_eq!(monitor.on_routes(&[Route::make("G")]).len(), 0);
+        let version = monitor.version();
+        monitor.check(now + Duration::from_secs(60));
+        assert_eq!(monitor.version(), version, "the same gap isn't republished");
+        monitor.check(now + Duration::from_secs(600));
+        assert!(monitor.get().is_empty(), "the feed went quiet");
+    }
+
+    #[test]
+    fn baseline() {
+        let key = (Route::make("4"), TripDir::North);
+        // Monday 2024-07-08 08:00 EDT
+        let t = |mins: u64| Timestamp::from_unix(1720440000) + Duration::from_secs(60 * mins);
+        let mut b = Baseline::default();
+        for (m, n) in [(0, 8), (5, 10), (10, 0)] {
+            b.sample(t(m), [(key, n)].into());
+        }
+        assert_eq!(b.slots[&key][Baseline::slot(t(0))], Some(9.0));
+        assert_eq!(b.expected(&key, t(0), t(5)), Some(9.0));
+        assert_eq!(b.expected(&key, t(0), t(15)), None, "08:10 is still being sampled");
+        b.sample(t(20), [(key, 0)].into());
+        assert_eq!(b.expected(&key, t(0), t(15)), Some(0.0));
+    }
+}
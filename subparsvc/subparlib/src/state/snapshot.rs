@@ -0,0 +1,80 @@
+//! Immutable state, published whole so readers only ever copy a pointer.
+
+use crate::Timestamp;
+use arc_swap::ArcSwap;
+use std::{fmt, sync::{Arc, OnceLock, atomic::{AtomicU64, Ordering}}};
+
+/// Shared by every `Published`, so a higher version is always newer state
+static VERSION: AtomicU64 = AtomicU64::new(0);
+/// Versions restart with the process; this keeps them from repeating across restarts
+static BOOT: OnceLock<u64> = OnceLock::new();
+
+#[derive(Debug)]
+pub struct Snapshot<T> {
+    pub version: Version,
+    pub data: T,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    boot: u64,
+    number: u64,
+    pub published: Timestamp,
+}
+
+/// The latest snapshot of some state. Readers never wait: the pointer is swapped atomically,
+/// and the next snapshot is built before the swap
+pub struct Published<T> {
+    current: Arc<ArcSwap< Snapshot<T> >>,
+}
+
+impl<T> Clone for Published<T> {
+    fn clone(&self) -> Self {
+        Published { current: self.current.clone() }
+    }
+}
+
+impl<T> Published<T> {
+    pub fn new(data: T) -> Self {
+        Published { current: Arc::new(ArcSwap::from_pointee(Snapshot { version: Version::next(), data })) }
+    }
+    pub fn load(&self) -> Arc<Snapshot<T>> {
+        self.current.load_full()
+    }
+    pub fn publish(&self, data: T) {
+        self.current.store(Arc::new(Snapshot { version: Version::next(), data }));
+    }
+    pub fn version(&self) -> Version {
+        self.current.load().version
+    }
+}
+
+impl Version {
+    fn next() -> Self {
+        let boot = *BOOT.get_or_init(|| Timestamp::now().as_unix_utc());
+        Version { boot, number: VERSION.fetch_add(1, Ordering::Relaxed) + 1, published: Timestamp::now() }
+    }
+}
+
+/// Opaque, e.g. for an ETag
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:x}-{}", self.boot, self.number)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Published;
+
+    #[test]
+    fn versions() {
+        let (a, b) = (Published::new(1), Published::new("b"));
+        let before = a.load();
+        b.publish("c");
+        a.publish(2);
+        assert!(a.version() > b.version(), "versions are shared");
+        assert_eq!((before.data, a.load().data), (1, 2), "readers keep the snapshot they loaded");
+        assert_ne!(before.version.to_string(), a.version().to_string());
+    }
+}
@@ -0,0 +1,215 @@
+//! Reliability metrics from outage history.
+//! Windows start no earlier than `observed_since`, so a long one may cover less time than its name says.
+
+use crate::{Timestamp, api::{EquipmentId, ComplexId}, msg::Route};
+use super::events::Span;
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+
+const HOUR: f64 = 60.0 * 60.0;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Breakdown {
+    pub count: usize,
+    pub hours: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Reliability {
+    /// Percent of the observed time in service over each window
+    pub uptime_7d: f64,
+    pub uptime_30d: f64,
+    pub uptime_365d: f64,
+    /// Outages starting in the last year
+    pub outages: usize,
+    /// Hours in service per outage, over the last year
+    pub mtbf_hours: Option<f64>,
+    /// Mean length of outages that ended in the last year
+    pub mttr_hours: Option<f64>,
+    pub by_reason: BTreeMap<String, Breakdown>,
+    pub maintenance: Breakdown,
+    /// Announced as upcoming before they started
+    pub planned: Breakdown,
+    pub observed_since: Option<Timestamp>,
+}
+
+impl Reliability {
+    /// Stats for `units` pieces of equipment, e.g. 1 elevator or a complex's 4, from all their outages
+    pub fn new(spans: &[&Span], units: usize, now: Timestamp, observed_since: Option<Timestamp>) -> Self {
+        let year_ago = window_start(now, 365, observed_since);
+        let mut by_reason: BTreeMap<String, Breakdown> = BTreeMap::new();
+        let mut maintenance = Breakdown::default();
+        let mut planned = Breakdown::default();
+        let mut repairs = vec![];
+        let mut outages = 0;
+        for s in spans.iter().filter(|s| s.end.is_none_or(|e| e > year_ago)) {
+            let hours = down_secs(s, year_ago, now) as f64 / HOUR;
+            let add = |b: &mut Breakdown| {
+                b.count += 1;
+                b.hours += hours;
+            };
+            add(by_reason.entry(s.reason.clone()).or_default());
+            if s.maintenance {
+                add(&mut maintenance);
+            }
+            if s.planned {
+                add(&mut planned);
+            }
+            if s.start > year_ago {
+                outages += 1;
+            }
+            if let Some(end) = s.end {
+                repairs.push(end.seconds_since(&s.start) as f64 / HOUR);
+            }
+        }
+        let uptime = |days| uptime(spans, units, now, days, observed_since);
+        let up_hours = uptime(365) / 100.0 * (now.seconds_since(&year_ago) * units.max(1) as i64) as f64 / HOUR;
+        Reliability {
+            uptime_7d: uptime(7),
+            uptime_30d: uptime(30),
+            uptime_365d: uptime(365),
+            outages,
+            mtbf_hours: (outages > 0).then(|| up_hours / outages as f64),
+            mttr_hours: (!repairs.is_empty()).then(|| repairs.iter().sum::<f64>() / repairs.len() as f64),
+            by_reason,
+            maintenance,
+            planned,
+            observed_since,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ElevatorReliability {
+    pub id: EquipmentId,
+    pub complex_id: ComplexId,
+    pub station: Option<String>,
+    pub borough: Option<String>,
+    pub lines: Vec<Route>,
+    pub desc: String,
+    pub is_escalator: bool,
+    /// Uptime over the ranking's window
+    pub uptime: f64,
+    #[serde(flatten)]
+    pub stats: Reliability,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ComplexReliability {
+    pub complex_id: ComplexId,
+    /// All the complex's elevators and escalators together
+    pub overall: Reliability,
+    pub equipment: Vec<ElevatorReliability>,
+}
+
+/// e.g. `?borough=Bk&route=L&days=30&limit=20`
+#[derive(Debug, Clone, Deserialize)]
+pub struct RankQuery {
+    pub borough: Option<String>,
+    pub route: Option<Route>,
+    #[serde(default = "RankQuery::default_days")]
+    pub days: i64,
+    #[serde(default = "RankQuery::default_limit")]
+    pub limit: usize,
+    /// Escalators too
+    #[serde(default)]
+    pub escalators: bool,
+}
+
+impl RankQuery {
+    fn default_days() -> i64 {
+        30
+    }
+    fn default_limit() -> usize {
+        20
+    }
+}
+
+/// Percent of `units`' time in service over the last `days`, or as much of them as we've observed
+pub fn uptime(spans: &[&Span], units: usize, now: Timestamp, days: i64, observed_since: Option<Timestamp>) -> f64 {
+    let from = window_start(now, days, observed_since);
+    let total = now.seconds_since(&from) * units.max(1) as i64;
+    if total <= 0 {
+        return 100.0;
+    }
+    let down: i64 = spans.iter().map(|s| down_secs(s, from, now)).sum();
+    100.0 * (1.0 - down.min(total) as f64 / total as f64)
+}
+
+/// `days` ago, or when we started watching if that's later
+fn window_start(now: Timestamp, days: i64, observed_since: Option<Timestamp>) -> Timestamp {
+    let from = Timestamp::from_utc(now.as_utc() - chrono::Duration::days(days));
+    observed_since.map_or(from, |s| s.max(from))
+}
+
+/// How much of `[from, to]` the span covers
+fn down_secs(s: &Span, from: Timestamp, to: Timestamp) -> i64 {
+    let start = s.start.max(from);
+    let end = s.end.unwrap_or(to).min(to);
+    end.seconds_since(&start).max(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Reliability, uptime};
+    use crate::{Timestamp, state::events::Span};
+    use chrono::Duration;
+
+    #[test]
+    fn uptime_and_means() {
+        let now = Timestamp::now();
+        let ago = |h| Timestamp::from_utc(now.as_utc() - Duration::hours(h));
+        let span = |start, end: Option<i64>, reason: &str, maintenance| Span {
+            equipment: "EL293".parse().unwrap(),
+            complex_id: serde_json::from_str("119").unwrap(),
+            start: ago(start),
+            end: end.map(ago),
+            reason: reason.into(),
+            maintenance,
+            planned: false,
+        };
+        let spans = [
+            span(24 * 40, Some(24 * 39), "Repair", false), // a day, 40 days ago
+            span(30, Some(18), "Repair", false),
+            span(6, None, "Preventive Maintenance", true),
+        ];
+        let refs: Vec<_> = spans.iter().collect();
+        let week = 100.0 * (1.0 - 18.0 / (7.0 * 24.0));
+        assert!((uptime(&refs, 1, now, 7, None) - week).abs() < 1e-6);
+        assert!((uptime(&refs, 2, now, 7, None) - (100.0 + week) / 2.0).abs() < 1e-6);
+        let r = Reliability::new(&refs, 1, now, None);
+        assert_eq!(r.outages, 3);
+        assert_eq!(r.mttr_hours, Some(18.0));
+        assert_eq!(r.by_reason["Repair"].count, 2);
+        assert_eq!(r.maintenance.hours, 6.0);
+        let up = 365.0 * 24.0 - 42.0;
+        assert!((r.mtbf_hours.unwrap() - up / 3.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn observed_lately() {
+        let now = Timestamp::now();
+        let ago = |d| Timestamp::from_utc(now.as_utc() - Duration::days(d));
+        let span = |start, end| Span {
+            equipment: "EL293".parse().unwrap(),
+            complex_id: serde_json::from_str("119").unwrap(),
+            start: ago(start),
+            end: Some(ago(end)),
+            reason: "Repair".into(),
+            maintenance: false,
+            planned: false,
+        };
+        // watching for 10 days; the first outage was already 2 days old
+        let spans = [span(12, 9), span(5, 4)];
+        let refs: Vec<_> = spans.iter().collect();
+        let since = Some(ago(10));
+        let r = Reliability::new(&refs, 1, now, since);
+        assert!((r.uptime_30d - 80.0).abs() < 1e-6, "{}", r.uptime_30d);
+        assert!((r.uptime_365d - 80.0).abs() < 1e-6, "{}", r.uptime_365d);
+        assert!((r.uptime_7d - 100.0 * 6.0 / 7.0).abs() < 1e-6);
+        assert_eq!(r.outages, 1, "only one started while we watched");
+        assert_eq!(r.by_reason["Repair"].hours, 48.0);
+        assert!((r.mtbf_hours.unwrap() - 8.0 * 24.0).abs() < 1e-6);
+        assert_eq!(uptime(&refs, 1, now, 30, Some(now)), 100.0, "nothing observed yet");
+    }
+}
@@ -1,67 +1,225 @@
 
-use crate::{Timestamp, api::{self, ComplexId}, msg::{self, StopId, TripIdStr}, client::Response};
+use crate::{Timestamp, Humanize, routes, api::{self, ComplexId}, msg::{self, Date, Route, StationId, TripDir, TripIdStr}, client::Response};
+use crate::manifest::StaticSchedule;
+use super::snapshot::{Published, Version};
 use std::{time::Duration, sync::{Arc, Mutex, }, collections::HashMap};
-use tracing::{info, warn};
+use tracing::{debug, info, warn};
 
-type StopIds = HashMap< StopId, ComplexId >;
+/// Arrivals this long ago have left
+pub(super) const DEPARTED: Duration = Duration::from_secs(30);
+/// Trips from a feed that hasn't updated in this long are dropped
+pub(super) const STALE: Duration = Duration::from_secs(5 * 60);
+/// Soonest trains kept per complex
+const PER_COMPLEX: usize = 200;
+
+type StopIds = HashMap< StationId, ComplexId >;
 type UpcomingMsgsMap = HashMap< TripIdStr, Upcoming >;
 type ByComplex<T> = HashMap< ComplexId, T >;
 
-#[derive(serde::Serialize, Clone, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
 pub struct Upcoming {
-    trip: TripIdStr,
-    stop: StopId,
-    arrival: Timestamp,
-    message: Timestamp,
+    pub(super) trip: TripIdStr,
+    pub(super) route: Route,
+    pub(super) dir: TripDir,
+    /// From the static schedule, e.g. "Canarsie-Rockaway Pkwy"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) headsign: Option<String>,
+    pub(super) stop: StationId,
+    pub(super) arrival: Timestamp,
+    pub(super) message: Timestamp,
+    /// From the static schedule, when the trip could be matched
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) scheduled: Option<Timestamp>,
+    /// Seconds late (negative when early)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) delay: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none", skip_deserializing)]
+    pub(super) text: Option<UpcomingText>,
+    /// Restored at startup, and its feed hasn't been heard from since
+    #[serde(default, skip_serializing_if = "super::is_false")]
+    pub(super) stale: bool,
+    #[serde(skip)]
+    pub(super) feed: &'static str,
+}
+
+/// `Upcoming`'s times, formatted for people
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct UpcomingText {
+    arrival: String,
+    countdown: String,
+    updated: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scheduled: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    delay: Option<String>,
+}
+
+impl Upcoming {
+    /// Without express variants, e.g. 6X => 6
+    pub fn route(&self) -> &Route {
+        &self.route
+    }
+    pub fn dir(&self) -> TripDir {
+        self.dir
+    }
+    pub fn headsign(&self) -> Option<&str> {
+        self.headsign.as_deref()
+    }
+    pub fn arrival(&self) -> Timestamp {
+        self.arrival
+    }
+    pub fn humanize(&mut self, h: &Humanize) {
+        self.text = Some(UpcomingText {
+            arrival: h.clock(self.arrival),
+            countdown: h.countdown(self.arrival),
+            updated: h.ago(self.message),
+            scheduled: self.scheduled.map(|t| h.clock(t)),
+            delay: self.delay.map(|d| h.delay(d)),
+        });
+    }
 }
 
 #[derive(Clone)]
 pub struct TrainStates {
     stops: Arc<StopIds>,
-    trains: Arc<Mutex< ByComplex< UpcomingMsgsMap >>>,
+    /// Only writers lock this; readers get `published`
+    trains: Arc<Mutex< Trains >>,
+    /// Each complex's trains, soonest first
+    published: Published< ByComplex< Vec<Upcoming> > >,
+    schedule: Option<Arc<StaticSchedule>>,
+}
+
+#[derive(Default)]
+struct Trains {
+    by_complex: ByComplex< UpcomingMsgsMap >,
+    /// Each feed's latest batch time, to drop batches that arrive out of order
+    feeds: HashMap< &'static str, Timestamp >,
 }
 
 impl TrainStates {
     pub fn new(cplxs: &[api::ComplexInfo]) -> Self {
         let mut stops: StopIds = HashMap::new();
         for cplx in cplxs {
-            for &stop_id in &cplx.stop_ids {
-                stops.insert(stop_id, cplx.complex_id);
+            for stop_id in &cplx.stop_ids {
+                stops.insert(*stop_id, cplx.complex_id);
             }
         }
-        let trains = Arc::new(Mutex::new(HashMap::default()));
-        TrainStates { stops: Arc::new(stops), trains }
+        TrainStates::with_stops(stops)
+    }
+    fn with_stops(stops: StopIds) -> Self {
+        let trains = Arc::new(Mutex::new(Trains::default()));
+        TrainStates { stops: Arc::new(stops), trains, published: Published::new(ByComplex::new()), schedule: None }
+    }
+    /// Realtime arrivals at these stations count toward their complexes
+    pub fn add_stops(&mut self, stops: impl IntoIterator<Item = (StationId, ComplexId)>) {
+        Arc::make_mut(&mut self.stops).extend(stops);
+    }
+    pub fn complex_of(&self, station: &StationId) -> Option<ComplexId> {
+        self.stops.get(station).copied()
+    }
+    pub fn schedule(&self) -> Option<&StaticSchedule> {
+        self.schedule.as_deref()
+    }
+    /// Enables schedule adherence
+    pub fn with_schedule(mut self, schedule: Arc<StaticSchedule>) -> Self {
+        self.schedule = Some(schedule);
+        self
     }
     pub fn update(&self, rsp: &Response) {
+        let (feed, time) = (rsp.feed.name(), rsp.data.time);
         let new = self.preprocess_rsp(rsp);
         let mut inner = self.trains.lock().unwrap();
-        merge(&mut inner, &new);
+        if let Some(&last) = inner.feeds.get(feed).filter(|&&last| time <= last) {
+            return debug!("dropping {feed} batch from {time}; already have {last}");
+        }
+        inner.feeds.insert(feed, time);
+        inner.replace(feed, new);
+        inner.sweep(Timestamp::now());
+        self.published.publish(inner.snapshot());
+    }
+    /// Drop departed trains and those from feeds that went quiet. Run periodically
+    pub fn sweep(&self, now: Timestamp) {
+        let mut inner = self.trains.lock().unwrap();
+        if inner.sweep(now) {
+            self.published.publish(inner.snapshot());
+        }
     }
     pub fn get(&self, id: ComplexId) -> Option< Vec<Upcoming>  > {
-        let mut elems: Vec<Upcoming> = {
-            let lock = self.trains.lock().unwrap();
-            lock.get(&id)?.values().cloned().collect()
-        };
-        elems.sort_by_key(|u| u.arrival);
-        Some(elems)
+        let departed = Timestamp::now() - DEPARTED;
+        let snapshot = self.published.load();
+        Some(snapshot.data.get(&id)?.iter().filter(|u| u.arrival > departed).cloned().collect())
+    }
+    pub fn version(&self) -> Version {
+        self.published.version()
+    }
+    /// Every train, soonest first per complex
+    pub fn all(&self) -> Vec<Upcoming> {
+        self.published.load().data.values().flatten().cloned().collect()
+    }
+    /// Trains saved before a restart, marked stale until their feed's next batch replaces them
+    pub fn restore(&self, trains: Vec<Upcoming>) {
+        let mut inner = self.trains.lock().unwrap();
+        for mut u in trains {
+            let Some(&complex) = self.stops.get(&u.stop) else { continue };
+            u.stale = true;
+            inner.by_complex.entry(complex).or_default().insert(u.trip, u);
+        }
+        self.published.publish(inner.snapshot());
     }
     fn preprocess_rsp(&self, rsp: &Response) -> ByComplex< UpcomingMsgsMap > {
         let message = rsp.data.time;
+        let feed = rsp.feed.name();
         let mut map: ByComplex<_> = self.stops.values()
             .map(|&c| (c, UpcomingMsgsMap::new()))
             .collect();
+        // where each train is now, so stops it has passed can be dropped
+        let at: HashMap<TripIdStr, StationId> = rsp.data.msgs.iter()
+            .filter_map(|elem| match elem {
+                Ok(msg::Update::Position(p)) => Some((p.trip.name(), p.stop.station().ok()?)),
+                _ => None,
+            })
+            .collect();
         for elem in &rsp.data.msgs {
             if let Ok(msg::Update::Schedule(s)) = elem {
-                let trip = s.trip().name();
+                let trip_id = s.trip();
+                let trip = trip_id.name();
+                let route = routes::catalog().canonical(&trip_id.route()).unwrap_or_else(|| trip_id.route());
+                let planned = self.schedule.as_deref()
+                    .and_then(|sched| Some((sched, sched.match_trip(&trip_id)?)));
+                let headsign = planned.map(|(_, row)| row.headsign.clone()).filter(|h| !h.is_empty());
+                if self.schedule.is_some() && planned.is_none() {
+                    debug!("no scheduled trip for {trip_id}");
+                }
+                let current = at.get(&trip).and_then(|st| {
+                    s.stops().iter().find(|p| p.id.station().ok().as_ref() == Some(st)).map(|p| *p.times.t0())
+                });
                 for stopplan in s.stops() {
-                    let stop = stopplan.id.parent();
+                    let stop = match stopplan.id.station() {
+                        Ok(s) => s,
+                        Err(e) => { warn!("msg had bad stop: {e}"); continue },
+                    };
                     let arrival = *stopplan.times.t0();
-                    let u = Upcoming { trip, stop, message, arrival };
-                    let Some(complex) = self.stops.get(&stop) else {
+                    if current.is_some_and(|t| arrival < t) {
+                        continue
+                    }
+                    let Some(&complex) = self.stops.get(&stop) else {
                         warn!("msg had unknown stop_id {stop}");
                         continue
                     };
-                    map.entry(*complex).or_default().insert( trip, u );
+                    let scheduled = planned.and_then(|(sched, row)| {
+                        sched.scheduled_arrival(row, &stopplan.id, Date::new(trip_id.date()))
+                    });
+                    let delay = scheduled.map(|t| arrival.seconds_since(&t));
+                    let u = Upcoming {
+                        trip, route, dir: trip_id.dir(), headsign: headsign.clone(),
+                        stop, message, arrival, scheduled, delay, text: None, stale: false, feed,
+                    };
+                    // a trip can stop twice in one complex; the next stop is what matters
+                    let trips = map.entry(complex).or_default();
+                    match trips.get(&trip) {
+                        Some(prev) if prev.arrival <= u.arrival => {},
+                        _ => { trips.insert(trip, u); },
+                    }
                 }
             }
         }
@@ -69,33 +227,74 @@ impl TrainStates {
     }
 }
 
-fn merge(lhs: &mut ByComplex< UpcomingMsgsMap >, rhs: &ByComplex< UpcomingMsgsMap > ) {
-    for (cplx, msgs) in rhs {
-        match lhs.get_mut(cplx) {
-            None => { lhs.insert(*cplx, msgs.clone()); },
-            Some(ref mut old) => { 
-                for (trip, msg) in msgs {
-                    match old.get_mut(&trip) {
-                        None => { old.insert(*trip, msg.clone()); },
-                        Some(ref mut slot) if slot.message <= msg.message => {
-                            if slot.stop == msg.stop {
-                                slot.message = msg.message;
-                                slot.arrival = msg.arrival;
-                            } else {
-                                warn!("stop mismatch; {slot:?} {msg:?}");
-                            }
-                        },
-                        Some(_) => {
-                            warn!("weird; old msg time older than new msg time");
-                        },
-                    };
-                }
-                let cutoff = Timestamp::now() - Duration::from_secs(45);
-                old.retain(|k, v| {
-                    v.message > cutoff
-                });
+impl Trains {
+    /// A batch lists every trip its feed knows about, so it replaces the feed's last one
+    fn replace(&mut self, feed: &str, new: ByComplex< UpcomingMsgsMap >) {
+        for trips in self.by_complex.values_mut() {
+            trips.retain(|_, u| u.feed != feed);
+        }
+        for (cplx, trips) in new {
+            self.by_complex.entry(cplx).or_default().extend(trips);
+        }
+    }
+    /// Whether anything was dropped
+    fn sweep(&mut self, now: Timestamp) -> bool {
+        let (departed, stale) = (now - DEPARTED, now - STALE);
+        let before: usize = self.by_complex.values().map(HashMap::len).sum();
+        for trips in self.by_complex.values_mut() {
+            trips.retain(|_, u| u.arrival > departed && u.message > stale);
+            if trips.len() > PER_COMPLEX {
+                let mut arrivals: Vec<Timestamp> = trips.values().map(|u| u.arrival).collect();
+                arrivals.sort();
+                let last = arrivals[PER_COMPLEX - 1];
+                trips.retain(|_, u| u.arrival <= last);
             }
         }
+        before != self.by_complex.values().map(HashMap::len).sum::<usize>()
+    }
+    fn snapshot(&self) -> ByComplex< Vec<Upcoming> > {
+        self.by_complex.iter()
+            .map(|(&id, trips)| {
+                let mut trains: Vec<Upcoming> = trips.values().cloned().collect();
+                trains.sort_by_key(|u| u.arrival);
+                (id, trains)
+            })
+            .collect()
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::TrainStates;
+    use crate::{Feed, Timestamp, client::Response, msg::{Batch, Position, PositionStatus, Schedule, StopPlan, Times, TripId, Update}};
+    use std::time::Duration;
+
+    #[test]
+    fn lifecycle() {
+        let (l06, l05) = (serde_json::from_str("119").unwrap(), serde_json::from_str("120").unwrap());
+        let stops = [("L06".parse().unwrap(), l06), ("L05".parse().unwrap(), l05)].into();
+        let trains = TrainStates::with_stops(stops);
+        let now = Timestamp::now();
+        let mins = |m: i64| match m {
+            m if m < 0 => now - Duration::from_secs(60 * -m as u64),
+            m => now + Duration::from_secs(60 * m as u64),
+        };
+        let trip = |t: &str| TripId::parse(&format!("{t}_L..N"), now.date()).unwrap();
+        let sched = |t: &str, stops: &[(&str, i64)]| Ok(Update::Schedule(Schedule::new(trip(t), now, stops.iter()
+            .map(|&(s, m)| StopPlan::new(s.parse().unwrap(), Times::new(Some(mins(m)), None).unwrap()))
+            .collect())));
+        let batch = |at: i64, msgs| Response::new(Batch { time: mins(at), msgs }, Feed::from_static("l"), &[], now, now);
+        let trips = |id| trains.get(id).unwrap().into_iter().map(|u| u.trip.to_string()).collect::<Vec<_>>();
+
+        trains.update(&batch(0, vec![sched("100000", &[("L06", 2)]), sched("090000", &[("L06", -5)])]));
+        assert_eq!(trips(l06), ["100000_L..N"], "departed trains aren't listed");
+        trains.update(&batch(-1, vec![sched("080000", &[("L06", 1)])]));
+        assert_eq!(trips(l06), ["100000_L..N"], "older batches are dropped");
+        let at = Position::new(trip("110000"), "L06N".parse().unwrap(), None, PositionStatus::EnRoute, now);
+        trains.update(&batch(1, vec![sched("110000", &[("L05", 1), ("L06", 3)]), Ok(Update::Position(at))]));
+        assert_eq!(trips(l06), ["110000_L..N"], "a batch replaces its feed's last one");
+        assert!(trips(l05).is_empty(), "the train is past L05");
+        trains.sweep(mins(10));
+        assert!(trips(l06).is_empty());
+    }
+}
@@ -0,0 +1,226 @@
+//! Each train in the feeds, followed by trip id: where it is and the stops it has left.
+
+use crate::{Timestamp, Humanize, routes, api::ComplexId, client::Response, msg::{PositionStatus, Route, StationId, StopId, TripDir, TripIdStr, Update}};
+use super::{trains::{DEPARTED, STALE}, snapshot::{Published, Version}};
+use serde::{Serialize, Deserialize};
+use std::{collections::HashMap, sync::{Arc, Mutex}};
+use tracing::debug;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiveTrip {
+    pub trip: TripIdStr,
+    pub route: Route,
+    pub dir: TripDir,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub position: Option<TripPosition>,
+    /// The latest batch that mentioned the trip
+    pub last_seen: Timestamp,
+    /// Soonest first; the trip's current stop comes first when it has a position
+    pub remaining: Vec<RemainingStop>,
+    /// ETA to the stop asked for, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub destination: Option<RemainingStop>,
+    /// Restored at startup, and its feed hasn't been heard from since
+    #[serde(default, skip_serializing_if = "super::is_false")]
+    pub stale: bool,
+    #[serde(skip)]
+    pub(super) feed: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TripPosition {
+    pub stop: StopId,
+    pub status: PositionStatus,
+    pub stop_sequence: Option<u32>,
+    pub time: Timestamp,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemainingStop {
+    pub stop: StopId,
+    pub complex_id: Option<ComplexId>,
+    pub name: Option<String>,
+    pub arrival: Option<Timestamp>,
+    pub departure: Option<Timestamp>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub countdown: Option<String>,
+}
+
+/// e.g. `?to=L06`
+#[derive(Debug, Clone, Deserialize)]
+pub struct TripQuery {
+    pub to: Option<StationId>,
+}
+
+#[derive(Clone)]
+pub struct TripStates {
+    /// Only writers lock this; readers get `published`
+    trips: Arc<Mutex< Trips >>,
+    published: Published< HashMap<TripIdStr, LiveTrip> >,
+}
+
+impl Default for TripStates {
+    fn default() -> Self {
+        TripStates { trips: Default::default(), published: Published::new(HashMap::new()) }
+    }
+}
+
+#[derive(Default)]
+struct Trips {
+    by_id: HashMap< TripIdStr, LiveTrip >,
+    feeds: HashMap< &'static str, Timestamp >,
+}
+
+impl TripStates {
+    /// Like `TrainStates`, each batch replaces its feed's trips, and late batches are dropped
+    pub fn update(&self, rsp: &Response) {
+        let (feed, time) = (rsp.feed.name(), rsp.data.time);
+        let mut new: HashMap<TripIdStr, LiveTrip> = HashMap::new();
+        for elem in rsp.data.msgs.iter().flatten() {
+            let id = match elem {
+                Update::Schedule(s) => s.trip(),
+                Update::Position(p) => p.trip(),
+                Update::Alert => continue,
+            };
+            let live = new.entry(id.name()).or_insert_with(|| LiveTrip {
+                trip: id.name(),
+                route: routes::catalog().canonical(&id.route()).unwrap_or_else(|| id.route()),
+                dir: id.dir(),
+                position: None,
+                last_seen: time,
+                remaining: vec![],
+                destination: None,
+                stale: false,
+                feed,
+            });
+            match elem {
+                Update::Schedule(s) => {
+                    live.remaining = s.stops().iter()
+                        .map(|p| RemainingStop {
+                            stop: p.id,
+                            complex_id: None,
+                            name: None,
+                            arrival: p.times.arr().copied(),
+                            departure: p.times.dep().copied(),
+                            countdown: None,
+                        })
+                        .collect();
+                },
+                Update::Position(p) => {
+                    live.position = Some(TripPosition {
+                        stop: p.stop,
+                        status: p.status,
+                        stop_sequence: p.stop_n,
+                        time: p.time,
+                    });
+                },
+                Update::Alert => {},
+            }
+        }
+        new.values_mut().for_each(LiveTrip::trim);
+        let mut inner = self.trips.lock().unwrap();
+        if let Some(&last) = inner.feeds.get(feed).filter(|&&last| time <= last) {
+            return debug!("dropping {feed} batch from {time}; already have {last}");
+        }
+        inner.feeds.insert(feed, time);
+        inner.by_id.retain(|_, t| t.feed != feed);
+        inner.by_id.extend(new);
+        self.published.publish(inner.by_id.clone());
+    }
+    /// Drop trips from feeds that went quiet
+    pub fn sweep(&self, now: Timestamp) {
+        let stale = now - STALE;
+        let mut inner = self.trips.lock().unwrap();
+        let before = inner.by_id.len();
+        inner.by_id.retain(|_, t| t.last_seen > stale);
+        if inner.by_id.len() != before {
+            self.published.publish(inner.by_id.clone());
+        }
+    }
+    /// Trips on a route, in any direction
+    pub fn on_route(&self, route: &Route) -> Vec<LiveTrip> {
+        self.published.load().data.values().filter(|t| &t.route == route).map(LiveTrip::current).collect()
+    }
+    pub fn get(&self, trip: &str) -> Option<LiveTrip> {
+        self.published.load().data.get(&TripIdStr::make(trip)).map(LiveTrip::current)
+    }
+    pub fn version(&self) -> Version {
+        self.published.version()
+    }
+    pub fn all(&self) -> Vec<LiveTrip> {
+        self.published.load().data.values().cloned().collect()
+    }
+    /// Trips saved before a restart, marked stale until their feed's next batch replaces them
+    pub fn restore(&self, trips: Vec<LiveTrip>) {
+        let mut inner = self.trips.lock().unwrap();
+        for mut t in trips {
+            t.stale = true;
+            inner.by_id.insert(t.trip, t);
+        }
+        self.published.publish(inner.by_id.clone());
+    }
+}
+
+impl LiveTrip {
+    /// Without stops it has left since the last batch
+    fn current(&self) -> LiveTrip {
+        let mut live = self.clone();
+        let departed = Timestamp::now() - DEPARTED;
+        live.remaining.retain(|s| s.departure.or(s.arrival).is_none_or(|t| t > departed));
+        live
+    }
+    /// Without the stops before where the train is now
+    fn trim(&mut self) {
+        let Some(pos) = self.position.as_ref() else { return };
+        let here = pos.stop.station().ok();
+        if let Some(i) = self.remaining.iter().position(|s| s.stop.station().ok() == here) {
+            self.remaining.drain(..i);
+        }
+    }
+    /// Set `destination` from the remaining stops; false if the trip doesn't stop there (any more)
+    pub fn to(&mut self, station: &StationId) -> bool {
+        self.destination = self.remaining.iter().find(|s| s.stop.station().ok().as_ref() == Some(station)).cloned();
+        self.destination.is_some()
+    }
+    /// Fill in station names, given each station's complex and name
+    pub fn label(&mut self, lookup: impl Fn(&StationId) -> Option<(ComplexId, String)>) {
+        for s in self.remaining.iter_mut().chain(self.destination.as_mut()) {
+            if let Some((id, name)) = s.stop.station().ok().as_ref().and_then(&lookup) {
+                s.complex_id = Some(id);
+                s.name = Some(name);
+            }
+        }
+    }
+    pub fn humanize(&mut self, h: &Humanize) {
+        for s in self.remaining.iter_mut().chain(self.destination.as_mut()) {
+            s.countdown = s.arrival.or(s.departure).map(|t| h.countdown(t));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TripStates;
+    use crate::{Feed, Timestamp, client::Response, msg::{Batch, Position, PositionStatus, Schedule, StopPlan, Times, TripId, Update}};
+    use std::time::Duration;
+
+    #[test]
+    fn follow() {
+        let now = Timestamp::now();
+        let id = TripId::parse("100000_L..N", now.date()).unwrap();
+        let stops = [("L08N", 1), ("L06N", 3), ("L05N", 5)].iter()
+            .map(|&(s, m)| StopPlan::new(s.parse().unwrap(), Times::new(Some(now + Duration::from_secs(60 * m)), None).unwrap()))
+            .collect();
+        let at = Position::new(id.clone(), "L06N".parse().unwrap(), Some(7), PositionStatus::At, now);
+        let msgs = vec![Ok(Update::Schedule(Schedule::new(id, now, stops))), Ok(Update::Position(at))];
+        let trips = TripStates::default();
+        trips.update(&Response::new(Batch { time: now, msgs }, Feed::from_static("l"), &[], now, now));
+        let mut live = trips.get("100000_L..N").unwrap();
+        assert_eq!(live.remaining.len(), 2, "L08 is behind it");
+        assert!(live.to(&"L05".parse().unwrap()));
+        assert!(!live.to(&"L08".parse().unwrap()));
+        assert_eq!(live.position.unwrap().stop_sequence, Some(7));
+        trips.sweep(now + Duration::from_secs(600));
+        assert!(trips.get("100000_L..N").is_none());
+    }
+}
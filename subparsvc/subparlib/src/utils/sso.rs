@@ -1,8 +1,9 @@
 //! Macro for newtypes of short strings on the stack.
-//! The only validation done on such types is length checking.
-//! Resulting type is POD.
+//! They aren't validated: strings that don't fit are interned on the heap instead
+//! of failing to parse, so the types stay `Copy` either way.
 
-use std::{error::Error as StdError, fmt};
+use std::{error::Error as StdError, fmt, hash, cmp, collections::HashSet, sync::{Mutex, OnceLock, atomic::{AtomicU64, Ordering}}};
+use arrayvec::ArrayString;
 
 #[derive(Debug, Clone)]
 pub struct ShortStringOverflow {
@@ -11,6 +12,23 @@ pub struct ShortStringOverflow {
     source: Option<arrayvec::CapacityError>,
 }
 
+/// Storage for `newt!` types: inline when it fits, otherwise interned on the heap.
+/// Equality, ordering, and hashing only consider the contents.
+#[derive(Clone, Copy)]
+pub enum Sso<const N: usize> {
+    Inline(ArrayString<N>),
+    Heap(&'static str),
+}
+
+static HEAP_FALLBACKS: AtomicU64 = AtomicU64::new(0);
+/// Each distinct long string is leaked once; ids are few enough for that
+static INTERNED: OnceLock<Mutex< HashSet<&'static str> >> = OnceLock::new();
+
+/// Number of `newt!` values that were too long to store inline, across all types
+pub fn heap_fallbacks() -> u64 {
+    HEAP_FALLBACKS.load(Ordering::Relaxed)
+}
+
 #[macro_export]
 macro_rules! newt {
 
@@ -25,12 +43,19 @@ macro_rules! newt {
     ) => {
 
         $(#[$comments])* $v struct $name(
-            ::arrayvec::ArrayString< $array_length >
+            $crate::utils::sso::Sso< $array_length >
         );
 
         impl $name {
             pub fn make(s: &str) -> Self {
-                s.parse().unwrap()
+                $name($crate::utils::sso::Sso::new(stringify!( $name ), s))
+            }
+            /// Fails instead of allocating if `s` doesn't fit inline
+            pub fn try_inline(s: &str) -> Result<Self, $crate::utils::sso::ShortStringOverflow> {
+                $crate::utils::sso::Sso::try_inline(stringify!( $name ), s).map( $name )
+            }
+            pub fn is_inline(&self) -> bool {
+                self.0.is_inline()
             }
         }
 
@@ -41,16 +66,14 @@ macro_rules! newt {
         }
 
         impl core::str::FromStr for $name {
-            type Err = crate::utils::sso::ShortStringOverflow;
+            type Err = core::convert::Infallible;
             fn from_str(s: &str) -> Result<Self, Self::Err> {
-                ::arrayvec::ArrayString::from(s).map( $name )
-                    .map_err(|e| crate::utils::sso::ShortStringOverflow::with_source(
-                            stringify!( $name ), s, e.simplify()))
+                Ok($name::make(s))
             }
         }
 
         impl AsRef<str> for $name {
-            fn as_ref(&self) -> &str { self.0.as_ref() }
+            fn as_ref(&self) -> &str { self.0.as_str() }
         }
     };
 
@@ -60,20 +83,101 @@ macro_rules! newt {
  * These hardcoded sizes correspond to trends in the NYCT system, and are not
  *  guaranteed to be followed either by other spec implementations
  *  nor by NYCT in the future.
- * May replace 'struct Train(ArrayString<9>)' with
- *  'enum Train { Short(ArrayString<9>), Long(String) }'
- *  so noncompliance only costs allocations without slowing the fast case.
- * Sizeof(String) == 24 I think
- *
+ * Noncompliance only costs a lookup (and an allocation the first time)
+ *  without slowing the fast case.
  */
 
+impl<const N: usize> Sso<N> {
+    pub fn new(typename: &'static str, s: &str) -> Self {
+        match ArrayString::from(s) {
+            Ok(a) => Sso::Inline(a),
+            Err(_) => {
+                let n = HEAP_FALLBACKS.fetch_add(1, Ordering::Relaxed) + 1;
+                tracing::debug!("{typename} '{s}' too long for {N} bytes; heap fallback #{n}");
+                Sso::Heap(intern(s))
+            }
+        }
+    }
+    pub fn try_inline(typename: &'static str, s: &str) -> Result<Self, ShortStringOverflow> {
+        ArrayString::from(s)
+            .map(Sso::Inline)
+            .map_err(|e| ShortStringOverflow::with_source(typename, s, e.simplify()))
+    }
+    pub fn as_str(&self) -> &str {
+        match self {
+            Sso::Inline(a) => a.as_str(),
+            Sso::Heap(h) => h,
+        }
+    }
+    pub fn is_inline(&self) -> bool {
+        matches!(self, Sso::Inline(_))
+    }
+}
+
+fn intern(s: &str) -> &'static str {
+    let mut interned = INTERNED.get_or_init(Default::default).lock().unwrap();
+    match interned.get(s) {
+        Some(&s) => s,
+        None => {
+            let s: &'static str = Box::leak(s.into());
+            interned.insert(s);
+            s
+        }
+    }
+}
+
+impl<const N: usize> PartialEq for Sso<N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl<const N: usize> Eq for Sso<N> {}
+
+impl<const N: usize> PartialOrd for Sso<N> {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<const N: usize> Ord for Sso<N> {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl<const N: usize> hash::Hash for Sso<N> {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+impl<const N: usize> fmt::Debug for Sso<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl<const N: usize> serde::Serialize for Sso<N> {
+    fn serialize<S: serde::Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        ser.serialize_str(self.as_str())
+    }
+}
+
+impl<'de, const N: usize> serde::Deserialize<'de> for Sso<N> {
+    fn deserialize<D: serde::Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+        let s = <std::borrow::Cow<'de, str>>::deserialize(de)?;
+        Ok(Sso::new("deserialized", &s))
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::newt;
+    
 
     newt! {
         /// Basically 'struct Char(char);'
-        #[derive(Clone, Debug, PartialEq, PartialOrd)]
+        #[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
         pub struct Char[1];
     }
 
@@ -82,14 +186,28 @@ mod tests {
         let p = str::parse::<Char>;
         assert_eq!("A", p("A").unwrap().as_ref());
         assert_eq!(Char::make("A"), p("A").unwrap());
-        assert!(p("AA").is_err());
+        assert!(p("A").unwrap().is_inline());
+        assert!(Char::try_inline("AA").is_err());
+    }
+
+    #[test]
+    fn heap_fallback() {
+        let before = super::heap_fallbacks();
+        let long = Char::make("AA");
+        assert!(!long.is_inline());
+        assert_eq!("AA", long.as_ref());
+        assert_eq!(long, Char::make("AA"));
+        assert!(Char::make("A") < long && long < Char::make("B"));
+        assert_eq!("Char(\"AA\")", format!("{long:?}"));
+        let (Char(a), Char(b)) = (long, Char::make("AA"));
+        assert!(matches!((a, b), (super::Sso::Heap(a), super::Sso::Heap(b)) if std::ptr::eq(a, b)), "interned once");
+        assert!(super::heap_fallbacks() >= before + 2);
     }
 
     #[test]
     #[ignore]
     fn _sample_of_what_type_err_macro_gen_err_unwrap_looks_like() {
-        use std::str::FromStr;
-        Char::from_str("AAA").unwrap();
+        Char::try_inline("AAA").unwrap();
     }
 
     // compile-time test
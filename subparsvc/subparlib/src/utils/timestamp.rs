@@ -1,10 +1,31 @@
 use chrono::{DateTime, Utc};
 use std::{fmt, ops, time};
 use anyhow::{Result, anyhow, bail};
-use crate::msg::Date;
+use crate::{msg::{Date, TripDir}, routes::Agency};
+
+pub use chrono_tz::Tz;
+
+/// Where the trains are
+pub const NYC: Tz = chrono_tz::America::New_York;
 
 // const FMT: &str = "%F_%T"; // 2020-12-31~14:30:00
 
+/// Language for human-readable times
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Locale {
+    #[default]
+    En,
+    Es,
+}
+
+/// How to render timestamps for people: which zone, which language, and relative to when
+#[derive(Debug, Copy, Clone)]
+pub struct Humanize {
+    pub tz: Tz,
+    pub locale: Locale,
+    pub now: Timestamp,
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Serialize, serde::Deserialize)]
 pub struct Timestamp(DateTime<Utc>);
 
@@ -69,6 +90,140 @@ impl Timestamp {
     }
 }
 
+impl Timestamp {
+    pub fn in_tz(&self, tz: Tz) -> DateTime<Tz> {
+        self.0.with_timezone(&tz)
+    }
+    /// Time of day, e.g. "8:14 PM" or "20:14"
+    pub fn clock(&self, tz: Tz, locale: Locale) -> String {
+        let t = self.in_tz(tz);
+        match locale {
+            Locale::En => t.format("%-I:%M %p").to_string(),
+            Locale::Es => t.format("%-H:%M").to_string(),
+        }
+    }
+    /// Calendar day, e.g. "Mon Oct 19" or "lun 19 oct"
+    pub fn day(&self, tz: Tz, locale: Locale) -> String {
+        use chrono::Datelike;
+        let t = self.in_tz(tz);
+        let wd = t.weekday().num_days_from_monday() as usize;
+        let mo = t.month0() as usize;
+        match locale {
+            Locale::En => t.format("%a %b %-d").to_string(),
+            Locale::Es => format!("{} {} {}", ES_DAYS[wd], t.day(), ES_MONTHS[mo]),
+        }
+    }
+    /// Time until this, e.g. "in 3 min"; "now" within half a minute
+    pub fn countdown(&self, now: Timestamp, locale: Locale) -> String {
+        let secs = self.seconds_since(&now);
+        if secs.abs() < 30 {
+            return locale.now().to_string();
+        }
+        if secs < 0 {
+            return now.ago_from(*self, locale);
+        }
+        let mins = (secs + 30) / 60;
+        match locale {
+            Locale::En => format!("in {mins} min"),
+            Locale::Es => format!("en {mins} min"),
+        }
+    }
+    /// Time since this, e.g. "20 s ago" or "hace 3 min"
+    pub fn ago(&self, now: Timestamp, locale: Locale) -> String {
+        now.ago_from(*self, locale)
+    }
+    fn ago_from(&self, then: Timestamp, locale: Locale) -> String {
+        let secs = self.seconds_since(&then).max(0);
+        let amount = match secs {
+            0..=59 => format!("{secs} s"),
+            60..=3599 => format!("{} min", secs / 60),
+            _ => format!("{} h", secs / 3600),
+        };
+        match locale {
+            Locale::En => format!("{amount} ago"),
+            Locale::Es => format!("hace {amount}"),
+        }
+    }
+}
+
+const ES_DAYS: [&str; 7] = ["lun", "mar", "mié", "jue", "vie", "sáb", "dom"];
+const ES_MONTHS: [&str; 12] = [
+    "ene", "feb", "mar", "abr", "may", "jun", "jul", "ago", "sep", "oct", "nov", "dic",
+];
+
+impl Locale {
+    /// Best match for a language tag or Accept-Language header, e.g. "es-US,es;q=0.9"
+    pub fn from_lang(s: &str) -> Option<Self> {
+        s.split(',')
+            .map(|tag| tag.split(';').next().unwrap_or_default().trim())
+            .find_map(|tag| match tag.split(['-', '_']).next()?.to_lowercase().as_str() {
+                "en" => Some(Locale::En),
+                "es" => Some(Locale::Es),
+                _ => None,
+            })
+    }
+    fn now(&self) -> &'static str {
+        match self {
+            Locale::En => "now",
+            Locale::Es => "ahora",
+        }
+    }
+}
+
+impl Humanize {
+    pub fn new(tz: Tz, locale: Locale) -> Self {
+        Humanize { tz, locale, now: Timestamp::now() }
+    }
+    pub fn clock(&self, t: Timestamp) -> String {
+        t.clock(self.tz, self.locale)
+    }
+    pub fn countdown(&self, t: Timestamp) -> String {
+        t.countdown(self.now, self.locale)
+    }
+    pub fn ago(&self, t: Timestamp) -> String {
+        t.ago(self.now, self.locale)
+    }
+    /// Train frequency from the shortest and longest gap, e.g. "every 3-4 min"
+    pub fn every(&self, min_secs: i64, max_secs: i64) -> String {
+        let (lo, hi) = (((min_secs + 30) / 60).max(1), ((max_secs + 30) / 60).max(1));
+        let mins = match lo == hi {
+            true => lo.to_string(),
+            false => format!("{lo}-{hi}"),
+        };
+        match self.locale {
+            Locale::En => format!("every {mins} min"),
+            Locale::Es => format!("cada {mins} min"),
+        }
+    }
+    /// A gap in service, e.g. "no northbound 4 trains in the last 20 minutes"
+    pub fn no_trains(&self, route: &str, dir: TripDir, agency: Agency, secs: i64) -> String {
+        let mins = (secs + 30) / 60;
+        let way = dir.word(agency, self.locale);
+        match self.locale {
+            Locale::En => format!("no {way} {route} trains in the last {mins} minutes"),
+            Locale::Es => format!("no hay trenes {route} {way} en los últimos {mins} minutos"),
+        }
+    }
+    /// Schedule adherence, e.g. "3 min late"; within a minute is on time
+    pub fn delay(&self, secs: i64) -> String {
+        let mins = (secs.abs() + 30) / 60;
+        match (self.locale, secs) {
+            (Locale::En, -59..=59) => "on time".into(),
+            (Locale::Es, -59..=59) => "a tiempo".into(),
+            (Locale::En, 60..) => format!("{mins} min late"),
+            (Locale::Es, 60..) => format!("{mins} min de retraso"),
+            (Locale::En, _) => format!("{mins} min early"),
+            (Locale::Es, _) => format!("{mins} min adelantado"),
+        }
+    }
+}
+
+impl Default for Humanize {
+    fn default() -> Self {
+        Humanize::new(NYC, Locale::default())
+    }
+}
+
 impl fmt::Display for Timestamp {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use chrono::{Datelike, Timelike};
@@ -112,3 +267,34 @@ impl ops::Sub<time::Duration> for Timestamp {
         Timestamp(self.0 - dur)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Timestamp, Humanize, Locale, NYC};
+    use crate::{msg::TripDir, routes::Agency};
+
+    #[test]
+    fn humanize() {
+        // 2024-07-04 20:14:00 EDT
+        let t = Timestamp::from_unix(1720138440);
+        assert_eq!(t.clock(NYC, Locale::En), "8:14 PM");
+        assert_eq!(t.clock(NYC, Locale::Es), "20:14");
+        assert_eq!(t.day(NYC, Locale::En), "Thu Jul 4");
+        assert_eq!(t.day(NYC, Locale::Es), "jue 4 jul");
+        let later = |s| t.plus(chrono::Duration::seconds(s));
+        assert_eq!(later(170).countdown(t, Locale::En), "in 3 min");
+        assert_eq!(later(10).countdown(t, Locale::Es), "ahora");
+        assert_eq!(t.countdown(later(300), Locale::En), "5 min ago");
+        assert_eq!(t.ago(later(20), Locale::Es), "hace 20 s");
+        assert_eq!(Locale::from_lang("fr-CA,es-US;q=0.9"), Some(Locale::Es));
+        assert_eq!(Locale::from_lang("de"), None);
+        let h = Humanize { tz: NYC, locale: Locale::En, now: t };
+        assert_eq!(h.delay(290), "5 min late");
+        assert_eq!(h.delay(-40), "on time");
+        assert_eq!(h.every(170, 250), "every 3-4 min");
+        assert_eq!(h.every(20, 60), "every 1 min");
+        assert_eq!(h.no_trains("4", TripDir::North, Agency::Nyct, 1190), "no northbound 4 trains in the last 20 minutes");
+        let es = Humanize { locale: Locale::Es, ..h };
+        assert_eq!(es.no_trains("LIRR", TripDir::South, Agency::Lirr, 600), "no hay trenes LIRR de entrada en los últimos 10 minutos");
+    }
+}
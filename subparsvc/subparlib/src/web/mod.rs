@@ -52,7 +52,7 @@ async fn poll_elevators(client: ApiClient, state: States) {
     let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
     loop {
         // todo need a get() version that doesn't just read the file
-        match client.get_outages_nocache().await {
+        match client.get_outage().await {
             Ok(o) => {
                 println!("Fetched 'outages'");
                 state.elevators.update(o.as_ref())
@@ -0,0 +1,202 @@
+//! Conditional GETs from the state's snapshot versions, and gzipped responses.
+
+use axum::{body::{self, Body}, extract::{Request, State}, middleware::Next, response::{IntoResponse, Response}};
+use flate2::{Compression, write::GzEncoder};
+use http::{StatusCode, HeaderMap, header::{self, HeaderValue}};
+use std::io::{self, Write};
+use subpar::{Timestamp, state::{States, Version}};
+use tracing::error;
+
+// smaller responses aren't worth compressing
+const MIN_GZIP: usize = 1024;
+// bigger than anything we serve
+const MAX_BODY: usize = 64 << 20;
+
+/// For routes serving trains, which change with every feed update
+pub async fn live(State(state): State<States>, req: Request, next: Next) -> Response {
+    revalidate(state.version(), false, req, next).await
+}
+
+/// For routes serving only elevators and outages, which change with the hourly poll
+pub async fn elevators(State(state): State<States>, req: Request, next: Next) -> Response {
+    revalidate(state.elevators.version(), false, req, next).await
+}
+
+/// For elevator stats, which count ongoing outages up to now
+pub async fn elevator_stats(State(state): State<States>, req: Request, next: Next) -> Response {
+    revalidate(state.elevators.version(), true, req, next).await
+}
+
+/// Answers 304 when the client already has `version`. It's read before the handler runs,
+/// so a response is never tagged newer than what it shows. Responses that depend on the
+/// clock too, like `?text=true` countdowns, are tagged with the minute as well
+async fn revalidate(version: Version, clocked: bool, req: Request, next: Next) -> Response {
+    let minute = (clocked || countdowns(&req)).then(|| Timestamp::now().as_unix_utc() / 60);
+    let (etag, modified) = tag(version, minute);
+    let etag = HeaderValue::from_str(&etag).unwrap();
+    let fresh = req.headers().get(header::IF_NONE_MATCH).is_some_and(|h| matches(h, &etag));
+    let mut rsp = match fresh {
+        true => StatusCode::NOT_MODIFIED.into_response(),
+        false => next.run(req).await,
+    };
+    if rsp.status().is_success() || rsp.status() == StatusCode::NOT_MODIFIED {
+        let headers = rsp.headers_mut();
+        headers.insert(header::ETAG, etag);
+        headers.insert(header::LAST_MODIFIED, HeaderValue::from_str(&modified).unwrap());
+        // revalidating is cheap, and trains move
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        // `?text=true` falls back to it
+        headers.append(header::VARY, HeaderValue::from_static("accept-language"));
+    }
+    rsp
+}
+
+/// The ETag and Last-Modified for `version`, within `minute` if given (since the epoch)
+fn tag(version: Version, minute: Option<u64>) -> (String, String) {
+    let (etag, modified) = match minute {
+        None => (format!("W/\"{version}\""), version.published),
+        Some(m) => (format!("W/\"{version}-{m}\""), version.published.max(Timestamp::from_unix(m as i64 * 60))),
+    };
+    (etag, modified.as_utc().format("%a, %d %b %Y %H:%M:%S GMT").to_string())
+}
+
+fn countdowns(req: &Request) -> bool {
+    req.uri().query().is_some_and(|q| q.split('&').any(|p| p == "text=true"))
+}
+
+/// Weak comparison: gzip changes the bytes, not the content
+fn matches(if_none_match: &HeaderValue, etag: &HeaderValue) -> bool {
+    let bare = |t: &str| t.trim().trim_start_matches("W/").to_owned();
+    let ours = bare(etag.to_str().unwrap_or_default());
+    if_none_match.to_str().is_ok_and(|tags| tags.split(',').any(|t| t.trim() == "*" || bare(t) == ours))
+}
+
+pub async fn gzip(req: Request, next: Next) -> Response {
+    let accepted = accepts_gzip(req.headers());
+    let rsp = next.run(req).await;
+    if !rsp.status().is_success() || rsp.headers().contains_key(header::CONTENT_ENCODING) {
+        return rsp;
+    }
+    let (mut parts, body) = rsp.into_parts();
+    parts.headers.append(header::VARY, HeaderValue::from_static("accept-encoding"));
+    if !accepted {
+        return Response::from_parts(parts, body);
+    }
+    let bytes = match body::to_bytes(body, MAX_BODY).await {
+        Ok(b) => b,
+        Err(e) => {
+            error!("couldn't buffer response: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        },
+    };
+    if bytes.len() < MIN_GZIP {
+        return Response::from_parts(parts, Body::from(bytes));
+    }
+    match compress(&bytes) {
+        Ok(gz) => {
+            parts.headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+            parts.headers.remove(header::CONTENT_LENGTH);
+            Response::from_parts(parts, Body::from(gz))
+        },
+        Err(e) => {
+            error!("gzip failed: {e}");
+            Response::from_parts(parts, Body::from(bytes))
+        },
+    }
+}
+
+fn compress(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let mut gz = GzEncoder::new(Vec::with_capacity(bytes.len() / 4), Compression::fast());
+    gz.write_all(bytes)?;
+    gz.finish()
+}
+
+/// e.g. `gzip, deflate, br`, but not `gzip;q=0`
+fn accepts_gzip(headers: &HeaderMap) -> bool {
+    let Some(accept) = headers.get(header::ACCEPT_ENCODING).and_then(|h| h.to_str().ok()) else {
+        return false;
+    };
+    accept.split(',').any(|enc| {
+        let mut params = enc.split(';').map(str::trim);
+        let name = params.next().unwrap_or_default();
+        let refused = params.any(|p| p.strip_prefix("q=").and_then(|q| q.parse::<f32>().ok()) == Some(0.0));
+        (name.eq_ignore_ascii_case("gzip") || name == "*") && !refused
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{accepts_gzip, matches, revalidate, tag};
+    use axum::{Router, body::{self, Body}, extract::Request, middleware::{self, Next}, routing::get};
+    use http::{StatusCode, HeaderMap, header::{self, HeaderValue}};
+    use subpar::state::Published;
+    use tower::ServiceExt;
+
+    #[test]
+    fn if_none_match() {
+        let etag = HeaderValue::from_static("W/\"1a-7\"");
+        let check = |h: &'static str| matches(&HeaderValue::from_static(h), &etag);
+        assert!(check("W/\"1a-7\""));
+        assert!(check("\"1a-7\""), "weak comparison ignores W/");
+        assert!(check("\"1a-6\", W/\"1a-7\""));
+        assert!(check("*"));
+        assert!(!check("W/\"1a-6\", \"1a-8\""));
+        assert!(!check(""));
+    }
+
+    #[test]
+    fn gzip_accepted() {
+        let accepts = |h: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::ACCEPT_ENCODING, HeaderValue::from_static(h));
+            accepts_gzip(&headers)
+        };
+        assert!(accepts("gzip, deflate, br"));
+        assert!(accepts("br;q=1.0, GZIP;q=0.5"));
+        assert!(accepts("*"));
+        assert!(!accepts("gzip;q=0"));
+        assert!(!accepts("gzip; q=0.0, deflate"));
+        assert!(!accepts("deflate, br"));
+        assert!(!accepts_gzip(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn by_minute() {
+        let version = Published::new(()).version();
+        let now = version.published.as_unix_utc() / 60;
+        let (plain, published) = tag(version, None);
+        let (a, modified) = tag(version, Some(now));
+        assert_ne!(plain, a);
+        assert_eq!(modified, published, "published within the minute");
+        let (b, modified) = tag(version, Some(now + 1));
+        assert_ne!(a, b, "countdowns change every minute");
+        assert!(modified.ends_with(":00 GMT") && modified != published, "{modified}");
+    }
+
+    #[tokio::test]
+    async fn not_modified() {
+        let version = Published::new(()).version();
+        let app = Router::new()
+            .route("/", get(|| async { "trains" }))
+            .layer(middleware::from_fn(move |req: Request, next: Next| revalidate(version, false, req, next)));
+        let get = |inm: Option<&str>| {
+            let mut req = Request::get("/");
+            if let Some(t) = inm {
+                req = req.header(header::IF_NONE_MATCH, t);
+            }
+            app.clone().oneshot(req.body(Body::empty()).unwrap())
+        };
+        let rsp = get(None).await.unwrap();
+        assert_eq!(rsp.status(), StatusCode::OK);
+        let etag = rsp.headers()[header::ETAG].to_str().unwrap().to_owned();
+        assert_eq!(etag, format!("W/\"{version}\""));
+        let rsp = get(Some(&etag)).await.unwrap();
+        assert_eq!(rsp.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(rsp.headers()[header::ETAG], etag.as_str());
+        assert!(rsp.headers().contains_key(header::LAST_MODIFIED));
+        assert!(body::to_bytes(rsp.into_body(), 1024).await.unwrap().is_empty());
+        let rsp = get(Some("W/\"0-0\"")).await.unwrap();
+        assert_eq!(body::to_bytes(rsp.into_body(), 1024).await.unwrap(), "trains");
+    }
+}
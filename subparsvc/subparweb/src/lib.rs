@@ -1,40 +1,205 @@
-use axum::{ Router, routing::{get}, extract::{Json, Path, State}, body::Body, http::StatusCode, };
-use std::{time::Duration};
-use subpar::{api::ComplexId, ApiClient, Listener, Feed, state::{States, Elevator, Upcoming, ComplexFull, ElevatorSummary }};
+use axum::{ Router, routing::{get}, extract::{Json, Path, Query, State}, body::Body, http::{StatusCode, HeaderMap}, middleware, };
+use std::{time::Duration, sync::Arc};
+use subpar::{msg::Route, api::{ComplexId, EquipmentId, CacheMode, Unmatched, ComplexInfo, AccessEquipment, AccessOutage, SubwayEntrance}, ApiClient, Listener, RouteInfo, Timestamp, Humanize, Locale, NYC, routes::{self, Agency}, manifest::{GtfsSource, ManifestStops, Policy, StaticSchedule}, state::{States, Elevator, Upcoming, ComplexFull, ElevatorSummary, Nearby, NearbyQuery, JsonlSink, OutageEvent, ComplexReliability, ElevatorReliability, RankQuery, Alternatives, ComplexAccess, ArrivalGroup, ArrivalsQuery, LiveTrip, TripQuery, LineStatus, ServiceAlert, Saved }};
 use tokio_stream::StreamExt as _;
 use tokio::{fs, net::TcpListener};
 use tracing::{info, debug, warn, error};
 use tower_http::cors;
-use http::{Method, header::HeaderValue};
+use http::{Method, header::{HeaderValue, ACCEPT_LANGUAGE}};
+
+mod cache;
 
 const FEED_POLL_PERIOD: Duration = Duration::new(10, 0);
+// drops departed trains between feed updates, e.g. when a feed stalls
+const TRAIN_SWEEP_PERIOD: Duration = Duration::new(15, 0);
+// how often routes are checked for gaps in service
+const SERVICE_CHECK_PERIOD: Duration = Duration::new(60, 0);
+// a static GTFS zip, a directory of them, or an unzipped one
+const GTFS_ARCHIVE: &str = "archive";
+// static GTFS for each commuter railroad, e.g. gtfslirr.zip
+const COMMUTER_ARCHIVES: &[(Agency, &str)] = &[(Agency::Lirr, "archive/lirr"), (Agency::Mnr, "archive/mnr")];
+// appended to as outages start, end, or change
+const OUTAGE_LOG: &str = "outage_events.jsonl";
+// live state, restored on startup
+const STATE_FILE: &str = "state.json";
+const STATE_SAVE_PERIOD: Duration = Duration::new(60, 0);
+
+type Datasets = (Vec<ComplexInfo>, Vec<AccessEquipment>, Vec<AccessOutage>, Vec<SubwayEntrance>);
 
-pub async fn serve() -> anyhow::Result<()> {
-    let client = ApiClient::default();
+/// Startup only fails if a dataset is neither fetchable nor cached.
+/// With a saved state to restore, it starts from cached datasets however old, and refreshes them after
+pub async fn serve(client: ApiClient) -> anyhow::Result<()> {
+    let saved = Saved::load(STATE_FILE).unwrap_or_else(|e| {
+        warn!("Ignoring {STATE_FILE}: {e:#}");
+        None
+    });
+    let cached = match &saved {
+        Some(_) => get_datasets(&client.cached()).await
+            .map_err(|e| warn!("Can't start from cached datasets: {e:#}"))
+            .ok(),
+        None => None,
+    };
+    let warm = cached.is_some();
+    // what's served until a restart, to tell whether the refresh changed anything
+    let served = cached.as_ref().map(|(complexes, elevators, _, entrances)| {
+        [serde_json::to_value(complexes), serde_json::to_value(elevators), serde_json::to_value(entrances)]
+            .map(Result::unwrap_or_default)
+    });
     let state = {
-        let complexes = client.get_complexes().await?;
-        let elevators = client.get_equipment().await?;
-        let outages = client.get_outages_nocache().await?;
-        let entrances = client.get_entrances().await?;
-        States::new(&complexes, &elevators, &outages, &entrances)
+        let (complexes, elevators, outages, entrances) = match cached {
+            Some(datasets) => datasets,
+            None => get_datasets(&client).await?,
+        };
+        let mut state = States::new(&complexes, &elevators, &outages, &entrances)
+            .with_outage_log(Arc::new(JsonlSink::new(OUTAGE_LOG)));
+        for &(agency, path) in COMMUTER_ARCHIVES {
+            if let Some(stops) = load_stops(path).await {
+                info!("{} {agency:?} stations", stops.len());
+                state = state.with_commuter(agency, &stops);
+            }
+        }
+        match load_schedule().await {
+            Some(schedule) => state.with_schedule(schedule),
+            None => state,
+        }
     };
+    if let Some(saved) = saved {
+        info!("Restoring state saved at {}", saved.saved);
+        state.restore(saved);
+    }
+    if warm {
+        state.elevators.mark_stale();
+        state.elevators.mark_datasets_stale(true);
+    }
     let cors = cors::CorsLayer::new()
         .allow_methods([Method::GET])
         .allow_origin(HeaderValue::from_static("https://api.subpar.nyc"));
-    let app = Router::new()
+    let live = Router::new()
         .route("/upcoming/:id", get(get_trains))
+        .route("/arrivals/:id", get(get_arrivals))
+        .route("/trains/:trip_id", get(get_train))
+        .route("/route/:route", get(get_line))
+        .route("/service", get(get_service))
+        .route("/elevators/:id/alternatives", get(get_alternatives))
+        .route("/complex/:id", get(get_complex_api))
+        .route_layer(middleware::from_fn_with_state(state.clone(), cache::live));
+    let elevators = Router::new()
         .route("/elevators/:id", get(get_elevators))
+        .route("/elevators/:id/events", get(get_elevator_events))
+        .route("/elevators/:id/access", get(get_access))
+        .route("/crosswalk/unmatched", get(get_unmatched))
         .route("/elevators_overview", get(get_elevators_overview))
-        .route("/complex/:id", get(get_complex_api))
+        .route("/nearby", get(get_nearby))
+        .route_layer(middleware::from_fn_with_state(state.clone(), cache::elevators));
+    let stats = Router::new()
+        .route("/elevators/:id/stats", get(get_elevator_stats))
+        .route("/reliability/worst", get(get_worst_elevators))
+        .route_layer(middleware::from_fn_with_state(state.clone(), cache::elevator_stats));
+    let app = Router::new()
+        .merge(live)
+        .merge(elevators)
+        .merge(stats)
         .route("/c/:id", get(get_complex_page))
+        .route("/routes", get(get_routes))
+        .route("/metrics", get(get_metrics))
+        .layer(middleware::from_fn(cache::gzip))
         .layer(cors)
         .with_state(state.clone());
     tokio::spawn(populate_feeds(state.clone()));
-    tokio::spawn(poll_elevators(client, state.clone()));
+    tokio::spawn(sweep_trains(state.clone()));
+    tokio::spawn(check_service(state.clone()));
+    tokio::spawn(save_state(state.clone()));
+    if client.cache().mode() == CacheMode::Online {
+        let client = Arc::new(client);
+        if let Some(served) = served {
+            tokio::spawn(refresh_datasets(client.clone(), served, state.clone()));
+        }
+        tokio::spawn(poll_elevators(client, state.clone()));
+    }
     webserver("0.0.0.0:3000", app).await;
     Ok(())
 }
 
+async fn get_datasets(client: &ApiClient) -> anyhow::Result<Datasets> {
+    let complexes = client.get_complexes().await?;
+    let elevators = client.get_equipment().await?;
+    let outages = client.get_outage().await?;
+    let entrances = client.get_entrances().await?;
+    Ok((complexes, elevators, outages, entrances))
+}
+
+/// After a warm start, so the cache is fresh for the next one. Outages are polled anyway;
+/// the rest only apply on restart, so what's served stays marked stale unless they're unchanged
+async fn refresh_datasets(client: Arc<ApiClient>, served: [serde_json::Value; 3], state: States) {
+    let fresh = [
+        client.get_complexes().await.and_then(|d| Ok(serde_json::to_value(d)?)),
+        client.get_equipment().await.and_then(|d| Ok(serde_json::to_value(d)?)),
+        client.get_entrances().await.and_then(|d| Ok(serde_json::to_value(d)?)),
+    ];
+    let mut unchanged = true;
+    for (fresh, served) in fresh.into_iter().zip(served) {
+        match fresh {
+            Ok(fresh) => unchanged &= fresh == served,
+            Err(e) => {
+                warn!("Dataset refresh failed: {e:#}");
+                unchanged = false;
+            },
+        }
+    }
+    match unchanged {
+        true => state.elevators.mark_datasets_stale(false),
+        false => warn!("Serving cached datasets that may be out of date until restart"),
+    }
+}
+
+/// Without a static schedule we just can't tell how late trains are
+async fn load_schedule() -> Option<Arc<StaticSchedule>> {
+    let src = GtfsSource::detect(GTFS_ARCHIVE)
+        .map_err(|e| warn!("No static GTFS in {GTFS_ARCHIVE}: {e}"))
+        .ok()?;
+    let load = move || StaticSchedule::try_from_source(&src, Policy::SkipBad);
+    match tokio::task::spawn_blocking(load).await {
+        Ok(Ok((schedule, reports))) => {
+            for report in reports.iter().filter(|r| !r.is_clean()) {
+                warn!("{report}");
+            }
+            Some(Arc::new(schedule))
+        },
+        Ok(Err(report)) => {
+            error!("{report}");
+            None
+        },
+        Err(e) => {
+            error!("Static GTFS loader panicked: {e}");
+            None
+        },
+    }
+}
+
+/// Without them a railroad's realtime feed has no stations to attach to
+async fn load_stops(path: &'static str) -> Option<ManifestStops> {
+    let src = GtfsSource::detect(path)
+        .map_err(|e| warn!("No static GTFS in {path}: {e}"))
+        .ok()?;
+    let load = move || ManifestStops::try_from_source(&src, Policy::SkipBad);
+    match tokio::task::spawn_blocking(load).await {
+        Ok(Ok((stops, report))) => {
+            if !report.is_clean() {
+                warn!("{report}");
+            }
+            Some(stops)
+        },
+        Ok(Err(report)) => {
+            error!("{report}");
+            None
+        },
+        Err(e) => {
+            error!("Stop loader panicked: {e}");
+            None
+        },
+    }
+}
+
 async fn webserver(addr: &str, app: Router) {
     info!("listening at {addr}");
     let listener = TcpListener::bind(addr).await.unwrap();
@@ -44,23 +209,59 @@ async fn webserver(addr: &str, app: Router) {
 }
 
 async fn populate_feeds(state: States) {
-    let feeds = "l 1234567 ace bdfm g jz nqrw si".split(" ")
-        .map(Feed::from_static)
-        .collect();
+    let feeds = routes::catalog().feeds();
     let mut listener = Listener::new(Default::default(), feeds, FEED_POLL_PERIOD).spawn();
     while let Some(rsp) = listener.next().await {
         debug!(%rsp.feed, "feed update");
         state.trains.update(&rsp);
+        state.trips.update(&rsp);
+        state.service.update(&rsp);
+    }
+}
+
+async fn sweep_trains(state: States) {
+    let mut interval = tokio::time::interval(TRAIN_SWEEP_PERIOD);
+    loop {
+        interval.tick().await;
+        state.trains.sweep(Timestamp::now());
+        state.trips.sweep(Timestamp::now());
     }
 }
 
-async fn poll_elevators(client: ApiClient, state: States) {
+async fn check_service(state: States) {
+    let mut interval = tokio::time::interval(SERVICE_CHECK_PERIOD);
+    loop {
+        interval.tick().await;
+        state.service.check(Timestamp::now());
+    }
+}
+
+async fn save_state(state: States) {
+    let mut interval = tokio::time::interval(STATE_SAVE_PERIOD);
+    // the first tick is immediate, with nothing new to save
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let state = state.clone();
+        match tokio::task::spawn_blocking(move || state.save(STATE_FILE)).await {
+            Ok(Ok(())) => debug!("Saved state to {STATE_FILE}"),
+            Ok(Err(e)) => error!("Failed to save state: {e:#}"),
+            Err(e) => error!("State saver panicked: {e}"),
+        }
+    }
+}
+
+async fn poll_elevators(client: Arc<ApiClient>, state: States) {
     let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
     loop {
-        match client.get_outages_nocache().await {
+        match client.get_outage().await {
             Ok(o) => {
                 info!("Updated 'outages'");
-                state.elevators.update(o.as_ref())
+                // logging the changes writes a file
+                let elevators = state.elevators.clone();
+                if let Err(e) = tokio::task::spawn_blocking(move || elevators.update(o.as_ref())).await {
+                    error!("Outage update panicked: {e}");
+                }
             },
             Err(e) => error!("Outage poll error: {e}"),
         }
@@ -84,19 +285,121 @@ async fn get_elevators(
     }
 }
 
+#[derive(serde::Deserialize)]
+struct EquipmentQuery {
+    equipment: Option<EquipmentId>,
+}
+
+async fn get_elevator_events(
+    Path(id): Path<ComplexId>,
+    Query(q): Query<EquipmentQuery>,
+    State(state): State<States>,
+) -> Result<Json< Vec<OutageEvent> >, (StatusCode, String)> {
+    state.elevators.events(id, q.equipment.as_ref())
+        .map(Json)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("complex '{id}' not found")))
+}
+
+/// `?equipment=` for one elevator, else every one that's out
+async fn get_alternatives(
+    Path(id): Path<ComplexId>,
+    Query(q): Query<EquipmentQuery>,
+    Query(text): Query<TextQuery>,
+    headers: HeaderMap,
+    State(state): State<States>,
+) -> Result<Json< Vec<Alternatives> >, (StatusCode, String)> {
+    let human = text.humanize(&headers)?;
+    let mut alts = state.alternatives(id, q.equipment.as_ref())
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("complex '{id}' not found")))?;
+    if let Some(h) = human {
+        alts.iter_mut()
+            .flat_map(|a| a.north.iter_mut().chain(a.south.iter_mut()))
+            .flat_map(|alt| alt.upcoming.iter_mut())
+            .for_each(|u| u.humanize(&h));
+    }
+    Ok(Json(alts))
+}
+
+async fn get_access(
+    Path(id): Path<ComplexId>,
+    State(state): State<States>,
+) -> Result<Json< ComplexAccess >, (StatusCode, String)> {
+    state.elevators.access(id)
+        .map(Json)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("no elevators at complex '{id}'")))
+}
+
+async fn get_elevator_stats(
+    Path(id): Path<ComplexId>,
+    State(state): State<States>,
+) -> Result<Json< ComplexReliability >, (StatusCode, String)> {
+    state.reliability(id)
+        .map(Json)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("complex '{id}' not found")))
+}
+
+async fn get_worst_elevators(
+    Query(q): Query<RankQuery>,
+    State(state): State<States>,
+) -> Result<Json< Vec<ElevatorReliability> >, (StatusCode, String)> {
+    if !(1..=365).contains(&q.days) || q.limit > 500 {
+        return Err((StatusCode::BAD_REQUEST, "days must be 1-365 and limit at most 500".into()));
+    }
+    Ok(Json(state.worst_elevators(&q)))
+}
+
+async fn get_unmatched(
+    State(state): State<States>,
+) -> Json<Vec<Unmatched>> {
+    Json(state.elevators.unmatched())
+}
+
 async fn get_elevators_overview(
     State(state): State<States>,
 ) -> Json<ElevatorSummary> {
     Json(state.elevators.get_summary())
 }
 
+/// Opt-in human-readable times, e.g. `?text=true&tz=America/New_York&lang=es`
+#[derive(serde::Deserialize)]
+struct TextQuery {
+    #[serde(default)]
+    text: bool,
+    tz: Option<String>,
+    lang: Option<String>,
+}
+
+impl TextQuery {
+    // falls back to Accept-Language when there's no `lang`
+    fn humanize(&self, headers: &HeaderMap) -> Result<Option<Humanize>, (StatusCode, String)> {
+        if !self.text {
+            return Ok(None);
+        }
+        let tz = match &self.tz {
+            None => NYC,
+            Some(s) => s.parse().map_err(|e| (StatusCode::BAD_REQUEST, format!("bad tz '{s}': {e}")))?,
+        };
+        let locale = self.lang.as_deref()
+            .or_else(|| headers.get(ACCEPT_LANGUAGE)?.to_str().ok())
+            .and_then(Locale::from_lang)
+            .unwrap_or_default();
+        Ok(Some(Humanize::new(tz, locale)))
+    }
+}
+
 async fn get_trains(
     Path(id): Path<ComplexId>,
+    Query(q): Query<TextQuery>,
+    headers: HeaderMap,
     State(state): State<States>,
 ) -> Result<Json< Vec<Upcoming> >, (StatusCode, String)> {
+    let human = q.humanize(&headers)?;
     match state.trains.get(id) {
-        Some(u) => {
+        Some(mut u) => {
             info!(%id, "serving upcoming");
+            if let Some(h) = human {
+                u.iter_mut().for_each(|u| u.humanize(&h));
+            }
             Ok(Json(u))
         },
         None => {
@@ -106,6 +409,91 @@ async fn get_trains(
     }
 }
 
+async fn get_arrivals(
+    Path(id): Path<ComplexId>,
+    Query(q): Query<ArrivalsQuery>,
+    Query(text): Query<TextQuery>,
+    headers: HeaderMap,
+    State(state): State<States>,
+) -> Result<Json< Vec<ArrivalGroup> >, (StatusCode, String)> {
+    let human = text.humanize(&headers)?;
+    if !(1..=20).contains(&q.n) {
+        return Err((StatusCode::BAD_REQUEST, "n must be 1-20".into()));
+    }
+    let mut groups = state.arrivals(id, q.n)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("complex '{id}' not found")))?;
+    if let Some(h) = human {
+        groups.iter_mut().for_each(|g| g.humanize(&h));
+    }
+    Ok(Json(groups))
+}
+
+async fn get_train(
+    Path(trip_id): Path<String>,
+    Query(q): Query<TripQuery>,
+    Query(text): Query<TextQuery>,
+    headers: HeaderMap,
+    State(state): State<States>,
+) -> Result<Json< LiveTrip >, (StatusCode, String)> {
+    let human = text.humanize(&headers)?;
+    let mut live = match (state.trip(&trip_id, q.to.as_ref()), &q.to) {
+        (Some(live), _) => live,
+        (None, Some(to)) if state.trips.get(&trip_id).is_some() => {
+            return Err((StatusCode::NOT_FOUND, format!("trip '{trip_id}' doesn't stop at {to} any more")));
+        },
+        (None, _) => return Err((StatusCode::NOT_FOUND, format!("trip '{trip_id}' not found"))),
+    };
+    if let Some(h) = human {
+        live.humanize(&h);
+    }
+    Ok(Json(live))
+}
+
+async fn get_line(
+    Path(route): Path<Route>,
+    Query(text): Query<TextQuery>,
+    headers: HeaderMap,
+    State(state): State<States>,
+) -> Result<Json< LineStatus >, (StatusCode, String)> {
+    let human = text.humanize(&headers)?;
+    let mut line = state.line(&route)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, format!("route '{route}' not found")))?;
+    if let Some(h) = human {
+        line.humanize(&h);
+    }
+    Ok(Json(line))
+}
+
+/// Routes and directions with no trains when some were expected
+async fn get_service(
+    Query(text): Query<TextQuery>,
+    headers: HeaderMap,
+    State(state): State<States>,
+) -> Result<Json< Vec<ServiceAlert> >, (StatusCode, String)> {
+    let human = text.humanize(&headers)?;
+    let mut alerts = state.service.get();
+    if let Some(h) = human {
+        alerts.iter_mut().for_each(|a| a.humanize(&h));
+    }
+    Ok(Json(alerts))
+}
+
+async fn get_nearby(
+    Query(q): Query<NearbyQuery>,
+    State(state): State<States>,
+) -> Result<Json< Vec<Nearby> >, (StatusCode, String)> {
+    q.validate().map_err(|e| (StatusCode::BAD_REQUEST, e.into()))?;
+    Ok(Json(state.nearby(&q)))
+}
+
+async fn get_routes() -> Json<Vec<&'static RouteInfo>> {
+    Json(routes::catalog().iter().collect())
+}
+
+async fn get_metrics() -> String {
+    format!("subpar_sso_heap_fallbacks {}\n", subpar::sso_heap_fallbacks())
+}
+
 async fn get_complex_page() -> Result<Body, (StatusCode, String)> {
     fs::read_to_string("ui/index.html").await
         .map(Body::new)
@@ -114,10 +502,18 @@ async fn get_complex_page() -> Result<Body, (StatusCode, String)> {
 
 async fn get_complex_api(
     Path(id): Path<ComplexId>,
+    Query(q): Query<TextQuery>,
+    headers: HeaderMap,
     State(state): State<States>,
 ) -> Result<Json< ComplexFull >, (StatusCode, String)> {
+    let human = q.humanize(&headers)?;
     match state.get_full(id) {
-        Some(x) => Ok(Json(x)),
+        Some(mut x) => {
+            if let Some(h) = human {
+                x.humanize(&h);
+            }
+            Ok(Json(x))
+        },
         None => Err((StatusCode::NOT_FOUND, format!("complex '{id}' not found"))),
     }
 }
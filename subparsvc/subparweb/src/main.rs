@@ -2,7 +2,6 @@
 /*
  * todo
  * rotatiing/zip logs
- * gzipping responses
  * s/println/log
  * pass port in argv?
  * warnings
@@ -12,8 +11,12 @@
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
-    
-    subparweb::serve().await?;
+    // --offline: serve only what's in cache/, e.g. when upstream is down
+    let client = match std::env::args().any(|a| a == "--offline") {
+        true => subpar::ApiClient::offline(),
+        false => subpar::ApiClient::default(),
+    };
+    subparweb::serve(client).await?;
     Ok(())
 }
 